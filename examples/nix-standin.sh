@@ -0,0 +1,130 @@
+#!/usr/bin/env bash
+# Stand-in for the nix CLI for testing the nix backend without nix
+#
+#   COSMIC_STORE_NIX=examples/nix-standin.sh cargo run --features nix
+#
+# The profile and fake store paths are kept in NIX_STANDIN_DIR, which defaults to
+# /tmp/nix-standin. Remove it to start over. Commands sleep a little so progress and
+# cancelling can be seen.
+
+set -e
+
+DIR="${NIX_STANDIN_DIR:-/tmp/nix-standin}"
+
+# Latest version of each package in the stand-in nixpkgs
+declare -A LATEST=(
+    [hello]="2.12.2"
+    [xeyes]="1.3.0"
+    [gnome-calculator]="46.1"
+)
+
+# Create a fake store path with share/applications and share/metainfo
+store_path() {
+    local name="$1" version="$2"
+    local path="$DIR/store/$(echo -n "$name-$version" | md5sum | cut -c1-32)-$name-$version"
+    if [ ! -d "$path" ]; then
+        mkdir -p "$path/bin"
+        if [ "$name" != "hello" ]; then
+            mkdir -p "$path/share/applications"
+            cat > "$path/share/applications/$name.desktop" <<EOF
+[Desktop Entry]
+Type=Application
+Name=${name^}
+Comment=$name from the nix stand-in
+Exec=$name
+Icon=utilities-terminal
+Categories=Utility;
+EOF
+        fi
+        if [ "$name" = "gnome-calculator" ]; then
+            mkdir -p "$path/share/metainfo"
+            cat > "$path/share/metainfo/org.gnome.Calculator.metainfo.xml" <<EOF
+<?xml version="1.0" encoding="UTF-8"?>
+<component type="desktop-application">
+  <id>org.gnome.Calculator</id>
+  <name>Calculator</name>
+  <summary>Perform arithmetic, scientific or financial calculations</summary>
+  <description><p>Calculator is an application that solves mathematical equations.</p></description>
+  <launchable type="desktop-id">gnome-calculator.desktop</launchable>
+  <categories><category>Utility</category></categories>
+</component>
+EOF
+        fi
+    fi
+    echo "$path"
+}
+
+set_entry() {
+    echo "$2" > "$DIR/profile/$1"
+}
+
+if [ ! -d "$DIR/profile" ]; then
+    mkdir -p "$DIR/profile"
+    set_entry hello 2.12.1
+    set_entry xeyes 1.2.0
+fi
+
+# Drop global options
+while [ "${1:0:2}" = "--" ]; do
+    case "$1" in
+        --version)
+            echo "nix (Nix) 2.24.0 stand-in"
+            exit 0
+            ;;
+        --extra-experimental-features) shift 2 ;;
+        *) shift ;;
+    esac
+done
+
+# Package name from an installable such as nixpkgs#hello
+package_name() {
+    local name="${1##*#}"
+    echo "${name##*.}"
+}
+
+case "$1 $2" in
+    "profile list")
+        echo -n '{"version":3,"elements":{'
+        first=1
+        for entry in "$DIR"/profile/*; do
+            [ -e "$entry" ] || continue
+            name="$(basename "$entry")"
+            [ $first = 1 ] || echo -n ','
+            first=0
+            echo -n "\"$name\":{\"active\":true,\"attrPath\":\"legacyPackages.x86_64-linux.$name\","
+            echo -n "\"originalUrl\":\"flake:nixpkgs\",\"storePaths\":[\"$(store_path "$name" "$(cat "$entry")")\"]}"
+        done
+        echo '}}'
+        ;;
+    "profile install")
+        name="$(package_name "$3")"
+        if [ -z "${LATEST[$name]}" ]; then
+            echo "error: flake 'flake:nixpkgs' does not provide attribute '$name'" >&2
+            exit 1
+        fi
+        sleep 2
+        store_path "$name" "${LATEST[$name]}" > /dev/null
+        set_entry "$name" "${LATEST[$name]}"
+        ;;
+    "profile remove")
+        if [ ! -e "$DIR/profile/$3" ]; then
+            echo "error: '$3' does not match any packages" >&2
+            exit 1
+        fi
+        sleep 1
+        rm "$DIR/profile/$3"
+        ;;
+    "profile upgrade")
+        sleep 2
+        store_path "$3" "${LATEST[$3]}" > /dev/null
+        set_entry "$3" "${LATEST[$3]}"
+        ;;
+    "eval --raw")
+        name="$(package_name "${3%.version}")"
+        echo -n "${LATEST[$name]}"
+        ;;
+    *)
+        echo "error: nix stand-in does not support '$*'" >&2
+        exit 1
+        ;;
+esac
@@ -0,0 +1,272 @@
+//! Minimal snapd REST API server for testing the snap backend without snapd
+//!
+//! ```sh
+//! cargo run --example snapd-mock -- /tmp/snapd.socket
+//! COSMIC_STORE_SNAPD_SOCKET=/tmp/snapd.socket cargo run --features snap
+//! ```
+
+use serde_json::{Value, json};
+use std::{
+    collections::{BTreeMap, HashMap},
+    env,
+    error::Error,
+    fs,
+    io::{BufRead, BufReader, Read, Write},
+    os::unix::net::{UnixListener, UnixStream},
+};
+
+// Polls needed for a change to finish
+const CHANGE_STEPS: u64 = 4;
+
+struct MockChange {
+    action: String,
+    snaps: Vec<String>,
+    polls: u64,
+    aborted: bool,
+}
+
+#[derive(Default)]
+struct State {
+    // Installed snaps with their version and revision
+    installed: BTreeMap<String, (String, String)>,
+    changes: HashMap<String, MockChange>,
+    next_change: u64,
+}
+
+fn catalog() -> Vec<Value> {
+    vec![
+        json!({
+            "name": "hello",
+            "title": "Hello",
+            "summary": "GNU Hello, the friendly greeter",
+            "description": "Prints a friendly greeting.",
+            "version": "2.11",
+            "revision": "42",
+            "license": "GPL-3.0",
+            "confinement": "strict",
+            "publisher": { "display-name": "Canonical" },
+            "media": [{ "type": "screenshot", "url": "https://example.com/hello.png" }],
+            "type": "app",
+        }),
+        json!({
+            "name": "code",
+            "title": "Visual Studio Code",
+            "summary": "Code editing. Redefined.",
+            "description": "Visual Studio Code is a code editor.",
+            "version": "1.90",
+            "revision": "160",
+            "license": "Proprietary",
+            "confinement": "classic",
+            "publisher": { "display-name": "Microsoft" },
+            "type": "app",
+        }),
+    ]
+}
+
+fn snap_json(name: &str, version: &str, revision: &str) -> Value {
+    let mut snap = catalog()
+        .into_iter()
+        .find(|snap| snap["name"] == name)
+        .unwrap_or_else(|| json!({ "name": name, "type": "app" }));
+    snap["version"] = json!(version);
+    snap["revision"] = json!(revision);
+    snap
+}
+
+fn sync(result: Value) -> (u16, Value) {
+    (
+        200,
+        json!({ "type": "sync", "status-code": 200, "status": "OK", "result": result }),
+    )
+}
+
+fn error(status_code: u16, kind: &str, message: &str) -> (u16, Value) {
+    (
+        status_code,
+        json!({
+            "type": "error",
+            "status-code": status_code,
+            "result": { "message": message, "kind": kind },
+        }),
+    )
+}
+
+fn handle(state: &mut State, method: &str, path: &str, body: Value) -> (u16, Value) {
+    match (method, path) {
+        ("GET", "/v2/snaps") => sync(
+            state
+                .installed
+                .iter()
+                .map(|(name, (version, revision))| {
+                    let mut snap = snap_json(name, version, revision);
+                    snap["icon"] = json!(format!("/v2/icons/{name}/icon"));
+                    snap
+                })
+                .collect(),
+        ),
+        ("GET", "/v2/sections") => sync(json!(["featured", "development"])),
+        ("GET", "/v2/find?select=refresh") => sync(
+            state
+                .installed
+                .iter()
+                .filter(|(_, (version, _))| version.as_str() != "2.11")
+                .map(|(name, _)| snap_json(name, "2.11", "42"))
+                .collect(),
+        ),
+        ("GET", path) if path.starts_with("/v2/find") => sync(catalog().into()),
+        ("GET", path) if path.starts_with("/v2/icons/") => {
+            error(404, "app-not-found", "no icon in mock server")
+        }
+        ("GET", path) if path.starts_with("/v2/changes/") => {
+            let id = path.trim_start_matches("/v2/changes/");
+            let Some(change) = state.changes.get_mut(id) else {
+                return error(404, "", "change not found");
+            };
+            change.polls = (change.polls + 1).min(CHANGE_STEPS);
+            let ready = change.aborted || change.polls == CHANGE_STEPS;
+            let status = if change.aborted {
+                "Undone"
+            } else if ready {
+                "Done"
+            } else {
+                "Doing"
+            };
+            let tasks: Vec<Value> = change
+                .snaps
+                .iter()
+                .map(|name| {
+                    json!({
+                        "kind": "download-snap",
+                        "summary": format!("Download snap \"{name}\" from channel \"stable\""),
+                        "status": status,
+                        "progress": { "label": "", "done": change.polls, "total": CHANGE_STEPS },
+                    })
+                })
+                .collect();
+            if ready && !change.aborted {
+                for name in change.snaps.iter() {
+                    match change.action.as_str() {
+                        "remove" => {
+                            state.installed.remove(name);
+                        }
+                        "revert" => {
+                            state
+                                .installed
+                                .insert(name.clone(), ("2.10".to_string(), "41".to_string()));
+                        }
+                        _ => {
+                            state
+                                .installed
+                                .insert(name.clone(), ("2.11".to_string(), "42".to_string()));
+                        }
+                    }
+                }
+            }
+            sync(json!({ "id": id, "status": status, "ready": ready, "err": "", "tasks": tasks }))
+        }
+        ("POST", path) if path.starts_with("/v2/changes/") => {
+            let id = path.trim_start_matches("/v2/changes/");
+            match state.changes.get_mut(id) {
+                Some(change) => {
+                    change.aborted = true;
+                    sync(json!({ "id": id }))
+                }
+                None => error(404, "", "change not found"),
+            }
+        }
+        ("POST", path) if path.starts_with("/v2/snaps") => {
+            let action = body["action"].as_str().unwrap_or_default().to_string();
+            let snaps = match path.strip_prefix("/v2/snaps/") {
+                Some(name) => vec![name.to_string()],
+                None => body["snaps"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|x| x.as_str().map(str::to_string))
+                    .collect(),
+            };
+            if let Some(name) = snaps
+                .iter()
+                .find(|name| !catalog().iter().any(|snap| snap["name"] == name.as_str()))
+            {
+                return error(404, "snap-not-found", &format!("snap {name:?} not found"));
+            }
+            state.next_change += 1;
+            let id = state.next_change.to_string();
+            state.changes.insert(
+                id.clone(),
+                MockChange {
+                    action,
+                    snaps,
+                    polls: 0,
+                    aborted: false,
+                },
+            );
+            (
+                202,
+                json!({ "type": "async", "status-code": 202, "change": id, "result": null }),
+            )
+        }
+        _ => error(404, "", &format!("{method} {path} not found")),
+    }
+}
+
+fn serve(state: &mut State, stream: UnixStream) -> Result<(), Box<dyn Error>> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let path = parts.next().unwrap_or_default().to_string();
+
+    let mut content_length = 0;
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':')
+            && name.eq_ignore_ascii_case("content-length")
+        {
+            content_length = value.trim().parse()?;
+        }
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+    let body = serde_json::from_slice(&body).unwrap_or(Value::Null);
+
+    let (status, response) = handle(state, &method, &path, body);
+    println!("{method} {path} -> {status}");
+    let response = serde_json::to_vec(&response)?;
+    let mut stream = stream;
+    write!(
+        stream,
+        "HTTP/1.1 {status} Mock\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.len()
+    )?;
+    stream.write_all(&response)?;
+    Ok(())
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let path = env::args()
+        .nth(1)
+        .unwrap_or_else(|| "/tmp/snapd.socket".to_string());
+    let _ = fs::remove_file(&path);
+    let listener = UnixListener::bind(&path)?;
+    println!("listening on {path}");
+
+    let mut state = State::default();
+    for stream_res in listener.incoming() {
+        let res = match stream_res {
+            Ok(stream) => serve(&mut state, stream),
+            Err(err) => Err(err.into()),
+        };
+        if let Err(err) = res {
+            eprintln!("failed to handle request: {err}");
+        }
+    }
+    Ok(())
+}
@@ -17,6 +17,7 @@ see-all = See all
 uninstall = Uninstall
 update = Update
 update-all = Update all
+reboot-required = Restart to finish installing and removing system packages.
 place-on-desktop = Place on desktop
 place-applet = Place applet
 place-applet-desc = Choose where to add the applet before refining its position.
@@ -56,6 +57,15 @@ adding = Adding...
 remove = Remove
 removing = Removing...
 
+# Resume operations dialog
+resume-operations = Resume unfinished operations?
+resume-operations-body = { $count ->
+    [one] { $count } operation was
+   *[other] { $count } operations were
+} not finished when COSMIC Store was last closed.
+resume = Resume
+discard = Discard
+
 # Uninstall Dialog
 uninstall-app = Uninstall {$name}?
 uninstall-app-warning = Uninstalling {$name} will delete its data.
@@ -119,11 +129,139 @@ translate = Translate
 
 ## Operations
 cancelled = Cancelled
+cancelling = Cancelling...
+retry = Retry
 operations = Operations
 no-operations = No operations in history.
 pending = Pending
+queued = Queued
 failed = Failed
 complete = Complete
+package-from-source = {$name} from {$source}
+package-count = { $count ->
+    [one] { $count } app
+   *[other] { $count } apps
+}
+download-progress = ({$downloaded} of {$total})
+size-bytes = {$size} B
+size-kilobytes = {$size} KB
+size-megabytes = {$size} MB
+size-gigabytes = {$size} GB
+size-terabytes = {$size} TB
+
+### Progress phases
+phase-resolving = resolving
+phase-downloading = downloading
+phase-installing = installing
+phase-removing = removing
+phase-cleaning-up = cleaning up
+
+### Package manager status
+status-waiting-in-queue = waiting in queue
+status-cancelling = cancelling
+status-waiting-for-lock = waiting for package manager lock
+status-waiting-for-auth = waiting for authentication
+
+## History
+history = History
+history-filter = Filter by app
+no-history = No past operations found.
+undo = Undo
+history-install = Install {$names}
+history-uninstall = Uninstall {$names}
+history-update = Update {$names}
+history-downgrade = Downgrade {$names}
+history-repository-add = Add repositories {$names}
+history-repository-remove = Remove repositories {$names}
+history-completed = Completed
+history-failed = Failed: {$error}
+
+## Deployments
+deployments = Deployments
+remove-pending = Remove
+rollback = Roll back
+pin = Pin
+unpin = Unpin
+switch = Switch
+switch-image = Image or ref to switch to
+deployment-booted = Booted
+deployment-pending-restart = Pending restart
+deployment-rollback-state = Rollback
+deployment-rollback = Roll back to the previous deployment
+deployment-rollback-pending = Rolling back to the previous deployment
+deployment-rollback-completed = Rolled back to the previous deployment
+deployment-cleanup-pending = Remove the pending deployment
+deployment-cleanup-pending-pending = Removing the pending deployment
+deployment-cleanup-pending-completed = Removed the pending deployment
+deployment-pin = Pin deployment {$id}
+deployment-pin-pending = Pinning deployment {$id}
+deployment-pin-completed = Pinned deployment {$id}
+deployment-unpin = Unpin deployment {$id}
+deployment-unpin-pending = Unpinning deployment {$id}
+deployment-unpin-completed = Unpinned deployment {$id}
+deployment-switch = Switch to {$image}
+deployment-switch-pending = Switching to {$image}
+deployment-switch-completed = Switched to {$image}
+
+## Install scope
+scope-user = For me only
+scope-system = For all users
+move-to-system = Move to system
+move-to-user = Move to user
+
+## Branches
+flatpak-ref = {$branch} ({$arch})
+switch-branch = Switch branch
+
+## Runtimes
+runtimes = Runtimes and extensions
+unused-runtimes-size = Unused runtimes use {$size}
+remove-unused = Remove unused
+unused = Unused
+used-by = Used by {$names}
+extension-of = Extension of {$name}
+
+## Permissions
+permissions = Permissions
+no-permissions = No special permissions
+permission-risky = Gives broad access to your data or system
+permission-from-app = From the app
+permission-changed = Changed by you
+permission-added = Added by you
+reset-permissions = Reset
+permission-network = Network access
+permission-ipc = Share memory with other programs
+permission-shared = Shares {$name} with the system
+permission-wayland = Show windows using Wayland
+permission-x11 = Show windows using X11, which can see other windows and keyboard input
+permission-fallback-x11 = Show windows using X11 when Wayland is not available
+permission-pulseaudio = Play and record sound
+permission-session-bus-all = Talk to all programs on the session bus
+permission-system-bus-all = Talk to all system services
+permission-ssh-auth = Use your SSH keys
+permission-gpg-agent = Use your GPG keys
+permission-pcsc = Use smart cards
+permission-cups = Print documents
+permission-socket = Use the {$name} socket
+permission-device-all = Use all devices, such as cameras and USB devices
+permission-dri = Use graphics acceleration
+permission-input = Use input devices, such as game controllers
+permission-usb = Use USB devices
+permission-kvm = Run virtual machines
+permission-shm = Use shared memory
+permission-device = Use {$name} devices
+permission-home = your home folder
+permission-host = all files on the system
+permission-host-os = system programs and libraries
+permission-host-etc = system configuration
+permission-xdg = the {$folder} folder
+permission-path = {$path}
+permission-read-write = Read and write {$location}
+permission-read-only = Read {$location}
+permission-session-bus-talk = Talk to {$name}
+permission-session-bus-own = Use the name {$name} on the session bus
+permission-system-bus-talk = Talk to the system service {$name}
+permission-system-bus-own = Use the name {$name} on the system bus
 
 ## Settings
 settings = Settings
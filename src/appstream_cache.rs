@@ -24,8 +24,8 @@ use std::{
 
 use crate::{AppIcon, AppId, AppInfo, stats};
 
-const PREFIXES: &[&str] = &["/usr/share", "/var/lib", "/var/cache"];
-const CATALOGS: &[&str] = &["swcatalog", "app-info"];
+pub const PREFIXES: &[&str] = &["/usr/share", "/var/lib", "/var/cache"];
+pub const CATALOGS: &[&str] = &["swcatalog", "app-info"];
 
 #[derive(
     Clone,
@@ -128,11 +128,17 @@ impl AppstreamCache {
 
     /// Get cache for system appstream data sources
     pub fn system(source_id: String, source_name: String, locale: &str) -> Self {
+        Self::system_root(source_id, source_name, Path::new("/"), locale)
+    }
+
+    /// Get cache for system appstream data sources below a root directory, such as a copy of a
+    /// container's catalogs
+    pub fn system_root(source_id: String, source_name: String, root: &Path, locale: &str) -> Self {
         let mut paths = Vec::new();
         let mut icons_paths = Vec::new();
         //TODO: get using xdg dirs?
         for prefix in PREFIXES {
-            let prefix_path = Path::new(prefix);
+            let prefix_path = root.join(prefix.trim_start_matches('/'));
             if !prefix_path.is_dir() {
                 continue;
             }
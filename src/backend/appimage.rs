@@ -0,0 +1,1051 @@
+use appstream::{Component, xmltree};
+use cosmic::widget;
+use sha1::{Digest, Sha1};
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs,
+    io::{self, Read, Seek, SeekFrom, Write},
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::Arc,
+    time::SystemTime,
+};
+
+use super::{Backend, BackendError, CancelToken, Capabilities, Package};
+use crate::{
+    AppIcon, AppId, AppInfo, AppstreamCache, Operation, OperationKind, OperationPhase,
+    OperationProgress,
+};
+
+const SOURCE_ID: &str = "appimage";
+//TODO: translate?
+const SOURCE_NAME: &str = "AppImage";
+
+// Prefix of desktop entries and icons registered for installed AppImages
+const DESKTOP_PREFIX: &str = "cosmic-store-appimage-";
+
+/// Location of the squashfs payload and update information of an AppImage
+struct Elf {
+    payload_offset: u64,
+    update_info_opt: Option<String>,
+}
+
+fn read_at(file: &mut fs::File, offset: u64, len: usize) -> Result<Vec<u8>, Box<dyn Error>> {
+    // Offsets and sizes come from the file, check them before allocating
+    let file_len = file.metadata()?.len();
+    if offset
+        .checked_add(len as u64)
+        .is_none_or(|end| end > file_len)
+    {
+        return Err("truncated ELF file".into());
+    }
+    let mut data = vec![0; len];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut data)?;
+    Ok(data)
+}
+
+// Read a little endian integer of 2, 4 or 8 bytes
+fn read_uint(data: &[u8], offset: usize, size: usize) -> Result<u64, Box<dyn Error>> {
+    let bytes = data
+        .get(offset..offset + size)
+        .ok_or("truncated ELF header")?;
+    Ok(bytes
+        .iter()
+        .rev()
+        .fold(0, |value, byte| (value << 8) | u64::from(*byte)))
+}
+
+/// The squashfs payload starts where the ELF runtime ends, after its section headers
+fn read_elf(path: &Path) -> Result<Elf, Box<dyn Error>> {
+    let mut file = fs::File::open(path)?;
+    let header = read_at(&mut file, 0, 64)?;
+    if &header[..4] != b"\x7fELF" {
+        return Err(format!("{path:?} is not an ELF file").into());
+    }
+    // Type 1 AppImages use an ISO 9660 payload instead
+    if &header[8..11] != b"AI\x02" {
+        return Err(format!("{path:?} is not a type 2 AppImage").into());
+    }
+    if header[5] != 1 {
+        return Err(format!("{path:?} is not little endian").into());
+    }
+    let elf64 = match header[4] {
+        1 => false,
+        2 => true,
+        class => return Err(format!("{path:?} has unknown ELF class {class}").into()),
+    };
+    let (shoff, shentsize, shnum, shstrndx) = if elf64 {
+        (
+            read_uint(&header, 0x28, 8)?,
+            read_uint(&header, 0x3A, 2)?,
+            read_uint(&header, 0x3C, 2)?,
+            read_uint(&header, 0x3E, 2)?,
+        )
+    } else {
+        (
+            read_uint(&header, 0x20, 4)?,
+            read_uint(&header, 0x2E, 2)?,
+            read_uint(&header, 0x30, 2)?,
+            read_uint(&header, 0x32, 2)?,
+        )
+    };
+    let payload_offset = shoff
+        .checked_add(shentsize * shnum)
+        .ok_or("invalid ELF section header offset")?;
+
+    // Sections are listed as (name offset, file offset, size)
+    let section_headers = read_at(&mut file, shoff, (shentsize * shnum) as usize)?;
+    let mut sections = Vec::with_capacity(shnum as usize);
+    for i in 0..shnum as usize {
+        let base = i * shentsize as usize;
+        sections.push(if elf64 {
+            (
+                read_uint(&section_headers, base, 4)?,
+                read_uint(&section_headers, base + 0x18, 8)?,
+                read_uint(&section_headers, base + 0x20, 8)?,
+            )
+        } else {
+            (
+                read_uint(&section_headers, base, 4)?,
+                read_uint(&section_headers, base + 0x10, 4)?,
+                read_uint(&section_headers, base + 0x14, 4)?,
+            )
+        });
+    }
+    let mut update_info_opt = None;
+    if let Some((_, strtab_offset, strtab_size)) = sections.get(shstrndx as usize).copied() {
+        let strtab = read_at(&mut file, strtab_offset, strtab_size as usize)?;
+        for (name_offset, offset, size) in sections.iter().copied() {
+            let name = strtab
+                .get(name_offset as usize..)
+                .and_then(|x| x.split(|byte| *byte == 0).next())
+                .unwrap_or_default();
+            if name == b".upd_info" {
+                let data = read_at(&mut file, offset, size as usize)?;
+                let update_info = String::from_utf8_lossy(&data)
+                    .trim_end_matches('\0')
+                    .trim()
+                    .to_string();
+                if !update_info.is_empty() {
+                    update_info_opt = Some(update_info);
+                }
+            }
+        }
+    }
+
+    Ok(Elf {
+        payload_offset,
+        update_info_opt,
+    })
+}
+
+fn is_appimage(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("appimage"))
+}
+
+// Match a file name against a pattern where `*` matches any text
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = text.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+fn http_client() -> Result<reqwest::Client, Box<dyn Error>> {
+    // GitHub rejects requests without a user agent
+    Ok(reqwest::Client::builder()
+        .user_agent(concat!("cosmic-store/", env!("CARGO_PKG_VERSION")))
+        .build()?)
+}
+
+fn http_get(url: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    // Backend calls run on blocking threads of the tokio runtime
+    tokio::runtime::Handle::current().block_on(async {
+        let response = http_client()?.get(url).send().await?.error_for_status()?;
+        Ok(response.bytes().await?.to_vec())
+    })
+}
+
+/// Header fields of a zsync file, which describe the latest release
+struct Zsync {
+    filename: String,
+    length: u64,
+    sha1_opt: Option<String>,
+    url: reqwest::Url,
+}
+
+/// Find the zsync file described by update information, then read its header
+fn zsync(update_info: &str) -> Result<Zsync, Box<dyn Error>> {
+    let parts: Vec<&str> = update_info.split('|').collect();
+    let zsync_url = match parts.as_slice() {
+        ["zsync", url] => url.to_string(),
+        ["gh-releases-zsync", user, repo, tag, pattern] => {
+            let release_url = if *tag == "latest" {
+                format!("https://api.github.com/repos/{user}/{repo}/releases/latest")
+            } else {
+                format!("https://api.github.com/repos/{user}/{repo}/releases/tags/{tag}")
+            };
+            let release: serde_json::Value = serde_json::from_slice(&http_get(&release_url)?)?;
+            release["assets"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .find(|asset| {
+                    asset["name"]
+                        .as_str()
+                        .is_some_and(|name| glob_match(pattern, name))
+                })
+                .and_then(|asset| asset["browser_download_url"].as_str())
+                .ok_or_else(|| format!("no release asset matching {pattern:?} in {user}/{repo}"))?
+                .to_string()
+        }
+        _ => return Err(format!("unsupported update information {update_info:?}").into()),
+    };
+
+    zsync_header(&zsync_url, &http_get(&zsync_url)?)
+}
+
+/// Read the header of a zsync file downloaded from `zsync_url`
+fn zsync_header(zsync_url: &str, data: &[u8]) -> Result<Zsync, Box<dyn Error>> {
+    // The header ends at the first empty line, checksums follow
+    let mut fields = HashMap::new();
+    for line in data.split(|byte| *byte == b'\n') {
+        let line = String::from_utf8_lossy(line);
+        let line = line.trim_end_matches('\r');
+        if line.is_empty() {
+            break;
+        }
+        if let Some((key, value)) = line.split_once(':') {
+            fields.insert(key.trim().to_string(), value.trim().to_string());
+        }
+    }
+    let zsync_url = reqwest::Url::parse(zsync_url)?;
+    let filename = fields.remove("Filename").unwrap_or_default();
+    // The URL is relative to the zsync file, and defaults to the file name
+    let url = zsync_url.join(fields.get("URL").unwrap_or(&filename))?;
+    Ok(Zsync {
+        length: fields
+            .get("Length")
+            .and_then(|x| x.parse().ok())
+            .ok_or_else(|| format!("zsync file {zsync_url} missing Length"))?,
+        sha1_opt: fields.remove("SHA-1"),
+        filename,
+        url,
+    })
+}
+
+/// Remove extractions of earlier versions of a file, named after the file, its size and mtime
+fn prune_extractions(cache_dir: &Path, file_name: &str, keep: &Path) {
+    let read_dir = match fs::read_dir(cache_dir) {
+        Ok(ok) => ok,
+        Err(err) => {
+            log::warn!("failed to read AppImage cache {:?}: {}", cache_dir, err);
+            return;
+        }
+    };
+    let is_number = |x: &str| !x.is_empty() && x.bytes().all(|byte| byte.is_ascii_digit());
+    for entry in read_dir.filter_map(|entry_res| entry_res.ok()) {
+        let path = entry.path();
+        if path == keep || !path.is_dir() {
+            continue;
+        }
+        let name = entry.file_name();
+        let Some((size, modified)) = name
+            .to_str()
+            .and_then(|x| x.strip_prefix(file_name))
+            .and_then(|x| x.strip_prefix('-'))
+            .and_then(|x| x.split_once('-'))
+        else {
+            continue;
+        };
+        if is_number(size) && is_number(modified) {
+            log::info!("removing stale AppImage extraction {:?}", path);
+            if let Err(err) = fs::remove_dir_all(&path) {
+                log::warn!("failed to remove {:?}: {}", path, err);
+            }
+        }
+    }
+}
+
+/// SHA-1 of a file in hex, as listed in zsync headers
+fn sha1_file(path: &Path) -> Result<String, Box<dyn Error>> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = Sha1::new();
+    io::copy(&mut file, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Metadata read from the squashfs payload of an AppImage
+struct Metadata {
+    id: AppId,
+    info: AppInfo,
+    version: String,
+    /// Extracted desktop entry
+    desktop_path: PathBuf,
+    icon_path_opt: Option<PathBuf>,
+    update_info_opt: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct AppImage {
+    dir: PathBuf,
+    locale: String,
+    appstream_caches: Vec<AppstreamCache>,
+}
+
+impl AppImage {
+    pub fn new(locale: &str) -> Result<Self, Box<dyn Error>> {
+        let dir = dirs::home_dir()
+            .ok_or("failed to find home directory")?
+            .join("Applications");
+        Ok(Self {
+            dir,
+            locale: locale.to_string(),
+            appstream_caches: Vec::new(),
+        })
+    }
+
+    fn appimages(&self) -> Vec<PathBuf> {
+        let read_dir = match fs::read_dir(&self.dir) {
+            Ok(ok) => ok,
+            Err(err) => {
+                log::debug!("failed to read AppImage directory {:?}: {}", self.dir, err);
+                return Vec::new();
+            }
+        };
+        let mut paths: Vec<PathBuf> = read_dir
+            .filter_map(|entry_res| entry_res.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.is_file() && is_appimage(path))
+            .collect();
+        paths.sort();
+        paths
+    }
+
+    /// Extract the desktop entry, icon and metainfo of an AppImage, reusing earlier extractions
+    fn extract(&self, path: &Path, elf: &Elf) -> Result<PathBuf, Box<dyn Error>> {
+        let metadata = fs::metadata(path)?;
+        let modified = metadata
+            .modified()?
+            .duration_since(SystemTime::UNIX_EPOCH)?
+            .as_secs();
+        let file_name = path
+            .file_name()
+            .ok_or_else(|| format!("{path:?} has no file name"))?
+            .to_string_lossy();
+        let cache_dir = dirs::cache_dir()
+            .ok_or("failed to find cache directory")?
+            .join("cosmic-store")
+            .join("appimage");
+        let dest = cache_dir.join(format!("{}-{}-{}", file_name, metadata.len(), modified));
+        if dest.is_dir() {
+            return Ok(dest);
+        }
+
+        log::info!("extracting metadata from {:?}", path);
+        let output = Command::new("unsquashfs")
+            .arg("-no-progress")
+            .arg("-offset")
+            .arg(elf.payload_offset.to_string())
+            .arg("-dest")
+            .arg(&dest)
+            .arg(path)
+            .args([
+                "*.desktop",
+                "*.png",
+                "*.svg",
+                ".DirIcon",
+                "usr/share/metainfo",
+            ])
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .output()
+            .map_err(|err| {
+                format!("failed to run unsquashfs, is squashfs-tools installed? {err}")
+            })?;
+        if !output.status.success() {
+            let _ = fs::remove_dir_all(&dest);
+            return Err(format!(
+                "unsquashfs failed on {:?} with status {}: {}",
+                path,
+                output.status,
+                String::from_utf8_lossy(&output.stderr)
+            )
+            .into());
+        }
+        prune_extractions(&cache_dir, &file_name, &dest);
+        Ok(dest)
+    }
+
+    fn metadata(&self, path: &Path) -> Result<Metadata, Box<dyn Error>> {
+        let elf = read_elf(path)?;
+        let dest = self.extract(path, &elf)?;
+
+        let desktop_path = fs::read_dir(&dest)?
+            .filter_map(|entry_res| entry_res.ok())
+            .map(|entry| entry.path())
+            .find(|x| x.extension().is_some_and(|ext| ext == "desktop"))
+            .ok_or_else(|| format!("{path:?} has no desktop entry"))?;
+        let entry = freedesktop_entry_parser::parse_entry(&desktop_path)?;
+        let get_attr = |key| {
+            entry
+                .get("Desktop Entry", key)
+                .and_then(|attr| attr.first())
+                .cloned()
+        };
+
+        // Prefer the icon named by the desktop entry, .DirIcon may be a dangling link
+        let icon_path_opt = get_attr("Icon")
+            .into_iter()
+            .flat_map(|icon| ["png", "svg"].map(|ext| dest.join(format!("{icon}.{ext}"))))
+            .chain([dest.join(".DirIcon")])
+            .find(|x| x.is_file());
+
+        let mut metainfo_opt = None;
+        if let Ok(read_dir) = fs::read_dir(dest.join("usr/share/metainfo")) {
+            for entry in read_dir.filter_map(|entry_res| entry_res.ok()) {
+                let metainfo_path = entry.path();
+                if !metainfo_path.to_string_lossy().ends_with(".xml") {
+                    continue;
+                }
+                let component = fs::File::open(&metainfo_path)
+                    .map_err(Box::<dyn Error>::from)
+                    .and_then(|file| Ok(xmltree::Element::parse(file)?))
+                    .and_then(|e| Ok(Component::try_from(&e)?));
+                match component {
+                    Ok(component) => {
+                        metainfo_opt = Some(component);
+                        break;
+                    }
+                    Err(err) => {
+                        log::warn!("failed to parse metainfo {:?}: {}", metainfo_path, err);
+                    }
+                }
+            }
+        }
+
+        let file_name = path
+            .file_name()
+            .map(|x| x.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let (id, mut info) = match metainfo_opt {
+            Some(component) => (
+                AppId::new(&component.id.0),
+                AppInfo::new(SOURCE_ID, SOURCE_NAME, None, component, &self.locale, 0),
+            ),
+            None => (
+                AppId::new(
+                    &desktop_path
+                        .file_stem()
+                        .map(|x| x.to_string_lossy().to_string())
+                        .unwrap_or_else(|| file_name.clone()),
+                ),
+                AppInfo {
+                    source_id: SOURCE_ID.to_string(),
+                    source_name: SOURCE_NAME.to_string(),
+                    name: get_attr("Name").unwrap_or_else(|| file_name.clone()),
+                    summary: get_attr("Comment").unwrap_or_default(),
+                    categories: get_attr("Categories")
+                        .unwrap_or_default()
+                        .split(';')
+                        .filter(|x| !x.is_empty())
+                        .map(str::to_string)
+                        .collect(),
+                    ..Default::default()
+                },
+            ),
+        };
+        if let Some(icon_path) = &icon_path_opt {
+            info.icons.insert(
+                0,
+                AppIcon::Local(icon_path.to_string_lossy().to_string(), None, None, None),
+            );
+        }
+        info.pkgnames = vec![file_name.clone()];
+        info.package_paths = vec![path.to_string_lossy().to_string()];
+        info.desktop_ids = vec![format!("{DESKTOP_PREFIX}{}.desktop", id.normalized())];
+
+        let version = get_attr("X-AppImage-Version")
+            .or_else(|| info.releases.first().map(|release| release.version.clone()))
+            .unwrap_or_default();
+        Ok(Metadata {
+            id,
+            info,
+            version,
+            desktop_path,
+            icon_path_opt,
+            update_info_opt: elf.update_info_opt,
+        })
+    }
+
+    fn package(metadata: Metadata) -> Package {
+        let icon = match &metadata.icon_path_opt {
+            Some(icon_path) => widget::icon::from_path(icon_path.clone()),
+            None => widget::icon::from_name("package-x-generic")
+                .size(128)
+                .handle(),
+        };
+        let mut extra = HashMap::new();
+        if let Some(update_info) = metadata.update_info_opt {
+            extra.insert("update_information".to_string(), update_info);
+        }
+        Package {
+            id: metadata.id,
+            icon,
+            info: Arc::new(metadata.info),
+            version: metadata.version,
+            extra,
+        }
+    }
+
+    /// Paths of the desktop entry and icon registered for an installed AppImage
+    fn registration_paths(metadata: &Metadata) -> Result<(PathBuf, PathBuf), Box<dyn Error>> {
+        let data_dir = dirs::data_dir().ok_or("failed to find user data directory")?;
+        let name = format!("{DESKTOP_PREFIX}{}", metadata.id.normalized());
+        let icon_ext = metadata
+            .icon_path_opt
+            .as_ref()
+            .and_then(|x| fs::read(x).ok())
+            .map_or("png", |data| {
+                if data.starts_with(b"\x89PNG") {
+                    "png"
+                } else {
+                    "svg"
+                }
+            });
+        Ok((
+            data_dir
+                .join("applications")
+                .join(format!("{name}.desktop")),
+            data_dir
+                .join("cosmic-store")
+                .join("appimage-icons")
+                .join(format!("{name}.{icon_ext}")),
+        ))
+    }
+
+    /// Add a desktop entry that runs the AppImage, so it shows up in launchers
+    fn register(metadata: &Metadata, path: &Path) -> Result<(), Box<dyn Error>> {
+        let (desktop_path, icon_path) = Self::registration_paths(metadata)?;
+        let mut icon_opt = None;
+        if let Some(extracted_icon) = &metadata.icon_path_opt {
+            if let Some(parent) = icon_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::copy(extracted_icon, &icon_path)?;
+            icon_opt = Some(icon_path);
+        }
+
+        let mut desktop = String::new();
+        for line in fs::read_to_string(&metadata.desktop_path)?.lines() {
+            if let Some(exec) = line.strip_prefix("Exec=") {
+                // Keep field codes and arguments, replacing the command
+                let args = exec.split_once(' ').map_or("", |(_, args)| args);
+                desktop.push_str(&format!("Exec=\"{}\" {}", path.display(), args));
+            } else if line.starts_with("Icon=") && icon_opt.is_some() {
+                if let Some(icon_path) = &icon_opt {
+                    desktop.push_str(&format!("Icon={}", icon_path.display()));
+                }
+            } else if line.starts_with("TryExec=") {
+                continue;
+            } else {
+                desktop.push_str(line);
+            }
+            desktop.push('\n');
+        }
+        if let Some(parent) = desktop_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&desktop_path, desktop)?;
+        Ok(())
+    }
+
+    fn unregister(metadata: &Metadata) -> Result<(), Box<dyn Error>> {
+        let (desktop_path, icon_path) = Self::registration_paths(metadata)?;
+        for path in [desktop_path, icon_path] {
+            if let Err(err) = fs::remove_file(&path)
+                && err.kind() != std::io::ErrorKind::NotFound
+            {
+                return Err(err.into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Copy an AppImage into the applications directory and make it executable
+    fn install(&self, source: &Path) -> Result<PathBuf, Box<dyn Error>> {
+        let file_name = source
+            .file_name()
+            .ok_or_else(|| format!("{source:?} has no file name"))?;
+        let target = self.dir.join(file_name);
+        fs::create_dir_all(&self.dir)?;
+        if source != target {
+            log::info!("copying {:?} to {:?}", source, target);
+            fs::copy(source, &target)?;
+        }
+        let mut permissions = fs::metadata(&target)?.permissions();
+        permissions.set_mode(permissions.mode() | 0o755);
+        fs::set_permissions(&target, permissions)?;
+        Ok(target)
+    }
+
+    /// Replace an AppImage with the latest release, reporting download progress
+    fn update(
+        &self,
+        path: &Path,
+        cancel_token: &CancelToken,
+        f: &mut dyn FnMut(u64, u64),
+    ) -> Result<(), Box<dyn Error>> {
+        let update_info = read_elf(path)?
+            .update_info_opt
+            .ok_or_else(|| format!("{path:?} has no update information"))?;
+        let zsync = zsync(&update_info)?;
+        let file_name = path
+            .file_name()
+            .ok_or_else(|| format!("{path:?} has no file name"))?
+            .to_string_lossy();
+        // Download next to the original, so it can be replaced atomically
+        let partial = path.with_file_name(format!(".{file_name}.part"));
+        log::info!("downloading {} to {:?}", zsync.url, partial);
+        let res = tokio::runtime::Handle::current().block_on(async {
+            let mut response = http_client()?
+                .get(zsync.url.clone())
+                .send()
+                .await?
+                .error_for_status()?;
+            let total = response.content_length().unwrap_or(zsync.length);
+            let mut file = fs::File::create(&partial)?;
+            let mut downloaded = 0;
+            while let Some(chunk) = response.chunk().await? {
+                if cancel_token.is_cancelled() {
+                    return Err(Box::<dyn Error>::from(BackendError::Cancelled));
+                }
+                file.write_all(&chunk)?;
+                downloaded += chunk.len() as u64;
+                f(downloaded, total);
+            }
+            Ok(())
+        });
+        if let Err(err) = res {
+            let _ = fs::remove_file(&partial);
+            return Err(err);
+        }
+        let mut permissions = fs::metadata(&partial)?.permissions();
+        permissions.set_mode(permissions.mode() | 0o755);
+        fs::set_permissions(&partial, permissions)?;
+        fs::rename(&partial, path)?;
+        Ok(())
+    }
+}
+
+impl Backend for AppImage {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::INSTALL
+            | Capabilities::UNINSTALL
+            | Capabilities::UPDATE
+            | Capabilities::FILE_INSTALL
+    }
+
+    fn load_caches(&mut self, _refresh: bool) -> Result<(), Box<dyn Error>> {
+        // Installed AppImages are the only catalog
+        let mut cache = AppstreamCache {
+            source_id: SOURCE_ID.to_string(),
+            source_name: SOURCE_NAME.to_string(),
+            locale: self.locale.clone(),
+            ..Default::default()
+        };
+        for path in self.appimages() {
+            match self.metadata(&path) {
+                Ok(metadata) => {
+                    for pkgname in metadata.info.pkgnames.iter() {
+                        cache
+                            .pkgnames
+                            .entry(pkgname.clone())
+                            .or_default()
+                            .insert(metadata.id.clone());
+                    }
+                    cache.infos.insert(metadata.id, Arc::new(metadata.info));
+                }
+                Err(err) => {
+                    log::warn!("failed to read AppImage {:?}: {}", path, err);
+                }
+            }
+        }
+        self.appstream_caches = vec![cache];
+        Ok(())
+    }
+
+    fn info_caches(&self) -> &[AppstreamCache] {
+        &self.appstream_caches
+    }
+
+    fn installed(&self) -> Result<Vec<Package>, Box<dyn Error>> {
+        let mut packages = Vec::new();
+        for path in self.appimages() {
+            match self.metadata(&path) {
+                Ok(metadata) => packages.push(Self::package(metadata)),
+                Err(err) => {
+                    log::warn!("failed to read AppImage {:?}: {}", path, err);
+                }
+            }
+        }
+        Ok(packages)
+    }
+
+    fn updates(&self) -> Result<Vec<Package>, Box<dyn Error>> {
+        let mut packages = Vec::new();
+        for path in self.appimages() {
+            let metadata = match self.metadata(&path) {
+                Ok(ok) => ok,
+                Err(err) => {
+                    log::warn!("failed to read AppImage {:?}: {}", path, err);
+                    continue;
+                }
+            };
+            let Some(update_info) = &metadata.update_info_opt else {
+                continue;
+            };
+            let zsync = match zsync(update_info) {
+                Ok(ok) => ok,
+                Err(err) => {
+                    log::warn!("failed to check updates for {:?}: {}", path, err);
+                    continue;
+                }
+            };
+            // Releases are told apart by checksum, or by size if the zsync file has none
+            let up_to_date = match &zsync.sha1_opt {
+                Some(sha1) => sha1_file(&path).map(|x| x.eq_ignore_ascii_case(sha1)),
+                None => fs::metadata(&path)
+                    .map(|x| x.len() == zsync.length)
+                    .map_err(Into::into),
+            };
+            match up_to_date {
+                Ok(true) => continue,
+                Ok(false) => {}
+                Err(err) => {
+                    log::warn!("failed to compare {:?} with latest release: {}", path, err);
+                    continue;
+                }
+            }
+            let mut package = Self::package(metadata);
+            package.version = zsync
+                .filename
+                .trim_end_matches(".AppImage")
+                .trim_end_matches(".appimage")
+                .to_string();
+            packages.push(package);
+        }
+        Ok(packages)
+    }
+
+    fn file_packages(&self, path: &str) -> Result<Vec<Package>, Box<dyn Error>> {
+        let path = Path::new(path);
+        if !is_appimage(path) {
+            return Err(format!("appimage backend does not support file {path:?}").into());
+        }
+        Ok(vec![Self::package(self.metadata(path)?)])
+    }
+
+    fn operation(
+        &self,
+        op: &Operation,
+        cancel_token: &CancelToken,
+        mut f: Box<dyn FnMut(OperationProgress) + 'static>,
+    ) -> Result<(), BackendError> {
+        let total = op.infos.len().max(1) as f32;
+        for (i, info) in op.infos.iter().enumerate() {
+            if cancel_token.is_cancelled() {
+                return Err(BackendError::Cancelled);
+            }
+            let path = info
+                .package_paths
+                .first()
+                .map(PathBuf::from)
+                .ok_or_else(|| format!("{} has no AppImage path", info.name))?;
+            let package_opt = Some(info.name.clone());
+            match &op.kind {
+                OperationKind::Install => {
+                    f(OperationProgress {
+                        percent: (i as f32) * 100.0 / total,
+                        phase_opt: Some(OperationPhase::Installing),
+                        package_opt,
+                        ..Default::default()
+                    });
+                    let target = self.install(&path)?;
+                    Self::register(&self.metadata(&target)?, &target)?;
+                }
+                OperationKind::Uninstall { .. } => {
+                    f(OperationProgress {
+                        percent: (i as f32) * 100.0 / total,
+                        phase_opt: Some(OperationPhase::Removing),
+                        package_opt,
+                        ..Default::default()
+                    });
+                    Self::unregister(&self.metadata(&path)?)?;
+                    log::info!("removing {:?}", path);
+                    fs::remove_file(&path)?;
+                }
+                OperationKind::Update => {
+                    let mut on_download = |bytes_downloaded: u64, bytes_total: u64| {
+                        let fraction = if bytes_total > 0 {
+                            (bytes_downloaded as f32) / (bytes_total as f32)
+                        } else {
+                            0.0
+                        };
+                        f(OperationProgress {
+                            percent: ((i as f32) + fraction) * 100.0 / total,
+                            phase_opt: Some(OperationPhase::Downloading),
+                            package_opt: package_opt.clone(),
+                            bytes_downloaded,
+                            bytes_total,
+                            ..Default::default()
+                        });
+                    };
+                    self.update(&path, cancel_token, &mut on_download)?;
+                    // Refresh the desktop entry from the new release
+                    Self::register(&self.metadata(&path)?, &path)?;
+                }
+                OperationKind::Downgrade(_)
+                | OperationKind::RepositoryAdd(_)
+                | OperationKind::RepositoryRemove(_, _)
+                | OperationKind::Deployment(_) => {
+                    return Err(BackendError::NotSupported(
+                        "appimage backend only supports installing, uninstalling and updating"
+                            .to_string(),
+                    ));
+                }
+            }
+        }
+        f(OperationProgress::new(100.0));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const UPDATE_INFO: &str = "zsync|https://example.com/Example.AppImage.zsync";
+
+    // Minimal ELF64 type 2 AppImage runtime with `.upd_info` and `.shstrtab` sections
+    fn elf64(update_info: &str) -> Vec<u8> {
+        let mut upd_info = update_info.as_bytes().to_vec();
+        // Update information is padded with zeros in the runtime
+        upd_info.resize(upd_info.len() + 16, 0);
+        let strtab = b"\0.upd_info\0.shstrtab\0";
+
+        let upd_info_offset = 64;
+        let strtab_offset = upd_info_offset + upd_info.len();
+        let shoff = strtab_offset + strtab.len();
+
+        let mut data = vec![0; 64];
+        data[..4].copy_from_slice(b"\x7fELF");
+        data[4] = 2;
+        data[5] = 1;
+        data[6] = 1;
+        data[8..11].copy_from_slice(b"AI\x02");
+        data[0x28..0x30].copy_from_slice(&(shoff as u64).to_le_bytes());
+        data[0x3A..0x3C].copy_from_slice(&64u16.to_le_bytes());
+        data[0x3C..0x3E].copy_from_slice(&3u16.to_le_bytes());
+        data[0x3E..0x40].copy_from_slice(&2u16.to_le_bytes());
+        data.extend_from_slice(&upd_info);
+        data.extend_from_slice(strtab);
+        for (name, offset, size) in [
+            (0u32, 0, 0),
+            (1, upd_info_offset, upd_info.len()),
+            (11, strtab_offset, strtab.len()),
+        ] {
+            let mut section = [0; 64];
+            section[..4].copy_from_slice(&name.to_le_bytes());
+            section[0x18..0x20].copy_from_slice(&(offset as u64).to_le_bytes());
+            section[0x20..0x28].copy_from_slice(&(size as u64).to_le_bytes());
+            data.extend_from_slice(&section);
+        }
+        data
+    }
+
+    fn write(dir: &TempDir, data: &[u8]) -> PathBuf {
+        let path = dir.path().join("Example.AppImage");
+        fs::write(&path, data).unwrap();
+        path
+    }
+
+    #[test]
+    fn elf() {
+        let dir = tempfile::tempdir().unwrap();
+        let data = elf64(UPDATE_INFO);
+        let elf = read_elf(&write(&dir, &data)).unwrap();
+        assert_eq!(elf.update_info_opt.as_deref(), Some(UPDATE_INFO));
+        // The payload follows the section headers at the end of the runtime
+        assert_eq!(elf.payload_offset, data.len() as u64);
+    }
+
+    #[test]
+    fn elf_without_update_info() {
+        let dir = tempfile::tempdir().unwrap();
+        let elf = read_elf(&write(&dir, &elf64(""))).unwrap();
+        assert_eq!(elf.update_info_opt, None);
+    }
+
+    #[test]
+    fn elf_invalid() {
+        let dir = tempfile::tempdir().unwrap();
+        let data = elf64(UPDATE_INFO);
+
+        let mut not_elf = data.clone();
+        not_elf[0] = 0;
+        let mut type_1 = data.clone();
+        type_1[10] = 1;
+        let mut big_endian = data.clone();
+        big_endian[5] = 2;
+        let mut huge_offset = data.clone();
+        huge_offset[0x28..0x30].copy_from_slice(&u64::MAX.to_le_bytes());
+        let mut huge_section = data.clone();
+        let strtab_size = data.len() - 64 + 0x20;
+        huge_section[strtab_size..strtab_size + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+
+        for (name, data) in [
+            ("empty", &[][..]),
+            ("truncated header", &data[..40]),
+            ("truncated section headers", &data[..data.len() - 10]),
+            ("not ELF", &not_elf[..]),
+            ("type 1", &type_1[..]),
+            ("big endian", &big_endian[..]),
+            ("huge section header offset", &huge_offset[..]),
+            ("huge section size", &huge_section[..]),
+        ] {
+            assert!(read_elf(&write(&dir, data)).is_err(), "{name}");
+        }
+    }
+
+    #[test]
+    fn glob() {
+        for (pattern, text, expected) in [
+            ("Example.AppImage", "Example.AppImage", true),
+            ("Example.AppImage", "Example-x86_64.AppImage", false),
+            ("*.AppImage.zsync", "Example.AppImage.zsync", true),
+            ("*.AppImage.zsync", "Example.AppImage", false),
+            ("Example-*", "Example-1.0-x86_64.AppImage", true),
+            ("Example-*", "Other-1.0-x86_64.AppImage", false),
+            (
+                "Example-*x86_64.AppImage.zsync",
+                "Example-1.0-x86_64.AppImage.zsync",
+                true,
+            ),
+            (
+                "Example-*x86_64.AppImage.zsync",
+                "Example-1.0-aarch64.AppImage.zsync",
+                false,
+            ),
+            ("*-*-*.zsync", "Example-1.0-x86_64.AppImage.zsync", true),
+            ("*-*-*.zsync", "Example-x86_64.AppImage.zsync", false),
+            ("*", "", true),
+            // Middle parts do not overlap the last part
+            ("a*b*b", "ab", false),
+        ] {
+            assert_eq!(
+                glob_match(pattern, text),
+                expected,
+                "pattern {pattern:?} text {text:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn zsync_headers() {
+        let zsync_url = "https://example.com/releases/Example.AppImage.zsync";
+        for (header, url, sha1_opt) in [
+            (
+                "Filename: Example-2.0.AppImage\nLength: 1234\nURL: Example-2.0.AppImage\nSHA-1: 0123abcd\n",
+                "https://example.com/releases/Example-2.0.AppImage",
+                Some("0123abcd"),
+            ),
+            (
+                "Filename: Example-2.0.AppImage\r\nLength: 1234\r\nURL: ../download/Example.AppImage\r\n",
+                "https://example.com/download/Example.AppImage",
+                None,
+            ),
+            (
+                "Filename: Example-2.0.AppImage\nLength: 1234\nURL: https://cdn.example.com/Example.AppImage\n",
+                "https://cdn.example.com/Example.AppImage",
+                None,
+            ),
+            // The URL defaults to the file name
+            (
+                "Filename: Example-2.0.AppImage\nLength: 1234\n",
+                "https://example.com/releases/Example-2.0.AppImage",
+                None,
+            ),
+        ] {
+            // Checksums follow the header after an empty line
+            let data = [header.as_bytes(), b"\nSHA-1: ignored\n\xff\x00"].concat();
+            let zsync = zsync_header(zsync_url, &data).unwrap();
+            assert_eq!(zsync.filename, "Example-2.0.AppImage", "{header:?}");
+            assert_eq!(zsync.length, 1234, "{header:?}");
+            assert_eq!(zsync.url.as_str(), url, "{header:?}");
+            assert_eq!(zsync.sha1_opt.as_deref(), sha1_opt, "{header:?}");
+        }
+    }
+
+    #[test]
+    fn zsync_header_without_length() {
+        assert!(
+            zsync_header(
+                "https://example.com/Example.AppImage.zsync",
+                b"Filename: x\n"
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn prune() {
+        let dir = tempfile::tempdir().unwrap();
+        let keep = dir.path().join("Example.AppImage-200-20");
+        for name in [
+            "Example.AppImage-100-10",
+            "Example.AppImage-200-20",
+            "Example.AppImage-old",
+            "Example.AppImage.bak-100-10",
+            "Other.AppImage-100-10",
+        ] {
+            fs::create_dir(dir.path().join(name)).unwrap();
+        }
+        fs::write(dir.path().join("Example.AppImage-300-30"), "").unwrap();
+
+        prune_extractions(dir.path(), "Example.AppImage", &keep);
+
+        let mut names: Vec<String> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        names.sort();
+        assert_eq!(
+            names,
+            [
+                "Example.AppImage-200-20",
+                "Example.AppImage-300-30",
+                "Example.AppImage-old",
+                "Example.AppImage.bak-100-10",
+                "Other.AppImage-100-10",
+            ]
+        );
+    }
+}
@@ -0,0 +1,318 @@
+use cosmic::widget;
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    error::Error,
+    process::{Command, Stdio},
+    sync::Arc,
+};
+
+use super::{
+    Backend, BackendError, CancelToken, Capabilities, Deployment, DeploymentState, Package,
+};
+use crate::{
+    AppId, AppInfo, AppstreamCache, DeploymentAction, Operation, OperationKind, OperationPhase,
+    OperationProgress,
+};
+
+const SOURCE_ID: &str = "bootc";
+//TODO: translate?
+const SOURCE_NAME: &str = "System";
+
+/// Output of `bootc status --format=json`
+#[derive(Debug, Deserialize)]
+struct Host {
+    #[serde(default)]
+    status: HostStatus,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HostStatus {
+    #[serde(default)]
+    staged: Option<BootEntry>,
+    #[serde(default)]
+    booted: Option<BootEntry>,
+    #[serde(default)]
+    rollback: Option<BootEntry>,
+    /// The rollback entry boots next
+    #[serde(default)]
+    rollback_queued: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BootEntry {
+    #[serde(default)]
+    image: Option<ImageStatus>,
+    /// Update fetched by `bootc upgrade --check`
+    #[serde(default)]
+    cached_update: Option<ImageStatus>,
+    #[serde(default)]
+    pinned: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ImageStatus {
+    image: ImageReference,
+    #[serde(default)]
+    version: Option<String>,
+    /// RFC 3339 creation time of the image
+    #[serde(default)]
+    timestamp: Option<String>,
+    image_digest: String,
+}
+
+impl ImageStatus {
+    fn version(&self) -> String {
+        self.version.clone().unwrap_or_else(|| {
+            self.image_digest
+                .trim_start_matches("sha256:")
+                .chars()
+                .take(12)
+                .collect()
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ImageReference {
+    image: String,
+    #[serde(default)]
+    transport: String,
+}
+
+impl ImageReference {
+    fn text(&self) -> String {
+        if self.transport.is_empty() || self.transport == "registry" {
+            self.image.clone()
+        } else {
+            format!("{}:{}", self.transport, self.image)
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Bootc {
+    appstream_caches: Vec<AppstreamCache>,
+}
+
+impl Bootc {
+    pub fn new(locale: &str) -> Result<Self, Box<dyn Error>> {
+        Ok(Self {
+            appstream_caches: vec![AppstreamCache {
+                source_id: SOURCE_ID.to_string(),
+                source_name: SOURCE_NAME.to_string(),
+                locale: locale.to_string(),
+                ..Default::default()
+            }],
+        })
+    }
+
+    fn run_command(&self, args: &[&str], privileged: bool) -> Result<String, Box<dyn Error>> {
+        if !std::path::Path::new("/usr/bin/bootc").exists() {
+            return Err("bootc command not found. Please install bootc package.".into());
+        }
+
+        // Changing the system requires root, so ask with polkit unless already root
+        let mut command = if privileged && unsafe { libc::geteuid() } != 0 {
+            let mut command = Command::new("pkexec");
+            command.arg("bootc");
+            command
+        } else {
+            Command::new("bootc")
+        };
+        let output = command
+            .args(args)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .output()?;
+
+        if !output.status.success() {
+            // pkexec exits with 126 if authorization was dismissed
+            if privileged && output.status.code() == Some(126) {
+                return Err(Box::new(BackendError::AuthCancelled));
+            }
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(format!(
+                "bootc command failed with status {}: {}",
+                output.status, stderr
+            )
+            .into());
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    }
+
+    fn status(&self) -> Result<HostStatus, Box<dyn Error>> {
+        let output = self.run_command(&["status", "--format=json"], false)?;
+        let host: Host = serde_json::from_str(&output)?;
+        Ok(host.status)
+    }
+
+    fn parse_upgrade_check(&self, status: &HostStatus) -> Vec<Package> {
+        let Some(booted) = &status.booted else {
+            return Vec::new();
+        };
+        let Some(update) = &booted.cached_update else {
+            return Vec::new();
+        };
+        if booted
+            .image
+            .as_ref()
+            .is_some_and(|image| image.image_digest == update.image_digest)
+        {
+            return Vec::new();
+        }
+
+        // If the available update matches the staged image by digest, we have already
+        // queued the update.
+        if status
+            .staged
+            .as_ref()
+            .and_then(|staged| staged.image.as_ref())
+            .is_some_and(|image| image.image_digest == update.image_digest)
+        {
+            log::debug!(
+                "Update image {} is already staged, not showing in UI",
+                update.image_digest
+            );
+            return Vec::new();
+        }
+
+        let version = update.version();
+        let mut extra = HashMap::new();
+        extra.insert("version".to_string(), version.clone());
+        extra.insert("digest".to_string(), update.image_digest.clone());
+
+        vec![Package {
+            id: AppId::system(),
+            icon: widget::icon::from_name("package-x-generic")
+                .size(128)
+                .handle(),
+            info: Arc::new(AppInfo {
+                source_id: SOURCE_ID.to_string(),
+                source_name: SOURCE_NAME.to_string(),
+                name: "System Upgrade".to_string(),
+                summary: format!("Update to version {}", version),
+                description: format!(
+                    "An OS update is available via bootc from {}.",
+                    update.image.text()
+                ),
+                pkgnames: vec!["system".to_string()],
+                ..Default::default()
+            }),
+            version,
+            extra,
+        }]
+    }
+}
+
+impl Backend for Bootc {
+    fn capabilities(&self) -> Capabilities {
+        // Only whole images are managed, changes apply after a reboot
+        Capabilities::UPDATE | Capabilities::DEPLOYMENTS
+    }
+
+    fn load_caches(&mut self, _refresh: bool) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    fn info_caches(&self) -> &[AppstreamCache] {
+        &self.appstream_caches
+    }
+
+    fn installed(&self) -> Result<Vec<Package>, Box<dyn Error>> {
+        Ok(Vec::new())
+    }
+
+    fn reboot_required(&self) -> Result<bool, Box<dyn Error>> {
+        let status = self.status()?;
+        Ok(status.staged.is_some() || status.rollback_queued)
+    }
+
+    fn deployments(&self) -> Result<Vec<Deployment>, Box<dyn Error>> {
+        let status = self.status()?;
+        let mut deployments = Vec::with_capacity(3);
+        for (entry_opt, state) in [
+            (&status.staged, DeploymentState::Pending),
+            (&status.booted, DeploymentState::Booted),
+            (&status.rollback, DeploymentState::Rollback),
+        ] {
+            let Some(entry) = entry_opt else {
+                continue;
+            };
+            let Some(image) = &entry.image else {
+                continue;
+            };
+            let timestamp = image
+                .timestamp
+                .as_deref()
+                .and_then(|x| chrono::DateTime::parse_from_rfc3339(x).ok())
+                .map_or(0, |dt| dt.timestamp());
+            deployments.push(Deployment {
+                id: image.image_digest.clone(),
+                version: image.version(),
+                origin: image.image.text(),
+                state,
+                pinned: entry.pinned,
+                timestamp,
+                diff: Vec::new(),
+            });
+        }
+        Ok(deployments)
+    }
+
+    fn updates(&self) -> Result<Vec<Package>, Box<dyn Error>> {
+        // Checking fetches the manifest of the new image, which requires root
+        if unsafe { libc::geteuid() } == 0
+            && let Err(err) = self.run_command(&["upgrade", "--check"], false)
+        {
+            log::warn!("failed to check for bootc updates: {}", err);
+        }
+        // Otherwise, use the last check, such as by bootc-fetch-apply-updates.timer
+        Ok(self.parse_upgrade_check(&self.status()?))
+    }
+
+    fn file_packages(&self, _path: &str) -> Result<Vec<Package>, Box<dyn Error>> {
+        Err("bootc backend does not support file-based package parsing".into())
+    }
+
+    fn operation(
+        &self,
+        op: &Operation,
+        _cancel_token: &CancelToken,
+        mut f: Box<dyn FnMut(OperationProgress) + 'static>,
+    ) -> Result<(), BackendError> {
+        let args: Vec<&str> = match &op.kind {
+            OperationKind::Update => vec!["upgrade"],
+            OperationKind::Deployment(DeploymentAction::Rollback) => vec!["rollback"],
+            OperationKind::Deployment(DeploymentAction::Switch(image)) => vec!["switch", image],
+            OperationKind::Deployment(DeploymentAction::CleanupPending)
+            | OperationKind::Deployment(DeploymentAction::Pin { .. }) => {
+                return Err(BackendError::NotSupported(
+                    "bootc backend does not support removing or pinning deployments".to_string(),
+                ));
+            }
+            OperationKind::Install
+            | OperationKind::Uninstall { .. }
+            | OperationKind::Downgrade(_)
+            | OperationKind::RepositoryAdd(_)
+            | OperationKind::RepositoryRemove(_, _) => {
+                return Err(BackendError::NotSupported(
+                    "bootc backend only manages system images".to_string(),
+                ));
+            }
+        };
+        f(OperationProgress {
+            phase_opt: Some(OperationPhase::Downloading),
+            ..Default::default()
+        });
+        log::info!("running bootc {}", args.join(" "));
+        self.run_command(&args, true)?;
+        f(OperationProgress::new(100.0));
+        log::info!("bootc {} completed", args[0]);
+        Ok(())
+    }
+}
@@ -0,0 +1,533 @@
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::Arc,
+};
+
+use super::{Backend, BackendError, CancelToken, Capabilities, Package};
+use crate::{
+    AppId, AppInfo, AppstreamCache, Operation, OperationKind, OperationPhase, OperationProgress,
+    appstream_cache,
+};
+
+// Source IDs are the container name with this prefix, so they do not clash with other sources
+const SOURCE_PREFIX: &str = "container-";
+
+/// Entry of `podman ps --all --format json`
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct PodmanContainer {
+    names: Vec<String>,
+    #[serde(default)]
+    image: String,
+    #[serde(default)]
+    labels: Option<HashMap<String, String>>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum ContainerKind {
+    Distrobox,
+    Toolbox,
+}
+
+#[derive(Clone, Debug)]
+struct Container {
+    name: String,
+    kind: ContainerKind,
+}
+
+impl Container {
+    fn source_id(&self) -> String {
+        format!("{SOURCE_PREFIX}{}", self.name)
+    }
+
+    /// Run a command inside the container, starting it if needed
+    fn command(&self, args: &[&str]) -> Command {
+        let mut command = match self.kind {
+            ContainerKind::Distrobox => {
+                let mut command = Command::new("distrobox");
+                command.args(["enter", "--name", &self.name, "--"]);
+                command
+            }
+            ContainerKind::Toolbox => {
+                let mut command = Command::new("toolbox");
+                command.args(["run", "--container", &self.name]);
+                command
+            }
+        };
+        command.args(args);
+        command
+    }
+
+    fn run(&self, args: &[&str]) -> Result<String, BackendError> {
+        log::info!("running {:?} in container {}", args, self.name);
+        let output = self
+            .command(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .output()?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let stderr = stderr.trim();
+            if stderr.contains("Unable to locate package")
+                || stderr.contains("No match for argument")
+                || stderr.contains("target not found")
+            {
+                return Err(BackendError::PackageNotFound(stderr.to_string()));
+            }
+            return Err(BackendError::Other(format!(
+                "{} in container {} failed with status {}: {}",
+                args.join(" "),
+                self.name,
+                output.status,
+                stderr
+            )));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    }
+
+    /// Finds the package manager of the container's distribution
+    fn package_manager(&self) -> Result<PackageManager, BackendError> {
+        let output = self.run(&[
+            "sh",
+            "-c",
+            "for pm in apt-get dnf zypper pacman apk; do command -v $pm && break; done",
+        ])?;
+        let path = output.trim();
+        let name = path.rsplit('/').next().unwrap_or(path);
+        PackageManager::from_name(name).ok_or_else(|| {
+            BackendError::NotSupported(format!(
+                "no supported package manager found in container {}",
+                self.name
+            ))
+        })
+    }
+
+    /// Directory with the container's catalogs copied from its file system
+    fn catalog_dir(&self) -> Option<PathBuf> {
+        dirs::cache_dir().map(|x| x.join("cosmic-store").join("containers").join(&self.name))
+    }
+
+    /// Copy appstream catalogs out of the container, which works while it is stopped
+    fn copy_catalogs(&self, dest: &Path) -> Result<(), Box<dyn Error>> {
+        if dest.is_dir() {
+            fs::remove_dir_all(dest)?;
+        }
+        for prefix in appstream_cache::PREFIXES {
+            let prefix_dest = dest.join(prefix.trim_start_matches('/'));
+            fs::create_dir_all(&prefix_dest)?;
+            for catalog in appstream_cache::CATALOGS {
+                // Most containers do not have every catalog directory, so failures are expected
+                let status = Command::new("podman")
+                    .arg("cp")
+                    .arg(format!("{}:{}/{}", self.name, prefix, catalog))
+                    .arg(&prefix_dest)
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null())
+                    .status()?;
+                if status.success() {
+                    log::info!("copied {}/{} from container {}", prefix, catalog, self.name);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Exported desktop entries are named after the container, like `distrobox-export` does
+    fn exported_path(&self, desktop_id: &str) -> Option<PathBuf> {
+        dirs::data_dir().map(|x| {
+            x.join("applications")
+                .join(format!("{}-{}", self.name, desktop_id))
+        })
+    }
+
+    fn export(&self, desktop_id: &str) -> Result<(), BackendError> {
+        match self.kind {
+            ContainerKind::Distrobox => {
+                self.run(&["distrobox-export", "--app", desktop_id])?;
+            }
+            ContainerKind::Toolbox => {
+                // Toolbox has no export command, so the entry is rewritten to run inside it
+                let source =
+                    self.run(&["cat", &format!("/usr/share/applications/{desktop_id}")])?;
+                let mut desktop = String::new();
+                for line in source.lines() {
+                    if let Some(exec) = line.strip_prefix("Exec=") {
+                        desktop.push_str(&format!(
+                            "Exec=toolbox run --container {} {}",
+                            self.name, exec
+                        ));
+                    } else if let Some(name) = line.strip_prefix("Name=") {
+                        desktop.push_str(&format!("Name={} ({})", name, self.name));
+                    } else if line.starts_with("TryExec=") || line.starts_with("DBusActivatable=") {
+                        continue;
+                    } else {
+                        desktop.push_str(line);
+                    }
+                    desktop.push('\n');
+                }
+                let path = self
+                    .exported_path(desktop_id)
+                    .ok_or("failed to find user data directory")?;
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::write(&path, desktop)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn unexport(&self, desktop_id: &str) -> Result<(), BackendError> {
+        match self.kind {
+            ContainerKind::Distrobox => {
+                self.run(&["distrobox-export", "--app", desktop_id, "--delete"])?;
+            }
+            ContainerKind::Toolbox => {
+                if let Some(path) = self.exported_path(desktop_id)
+                    && path.is_file()
+                {
+                    fs::remove_file(path)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Desktop IDs of exported apps, as named inside the container
+    fn exported_desktop_ids(&self) -> Vec<String> {
+        let Some(dir) = dirs::data_dir().map(|x| x.join("applications")) else {
+            return Vec::new();
+        };
+        let prefix = format!("{}-", self.name);
+        let mut desktop_ids = Vec::new();
+        for entry in fs::read_dir(&dir)
+            .into_iter()
+            .flatten()
+            .filter_map(|entry_res| entry_res.ok())
+        {
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            let Some(desktop_id) = file_name.strip_prefix(&prefix) else {
+                continue;
+            };
+            if !desktop_id.ends_with(".desktop") {
+                continue;
+            }
+            // Other apps may start with the container name, so check that it runs in the container
+            let Ok(entry) = freedesktop_entry_parser::parse_entry(entry.path()) else {
+                continue;
+            };
+            let exec = entry
+                .get("Desktop Entry", "Exec")
+                .and_then(|attr| attr.first())
+                .cloned()
+                .unwrap_or_default();
+            if exec.contains(&self.name) && (exec.contains("distrobox") || exec.contains("toolbox"))
+            {
+                desktop_ids.push(desktop_id.to_string());
+            }
+        }
+        desktop_ids.sort();
+        desktop_ids
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum PackageManager {
+    Apk,
+    Apt,
+    Dnf,
+    Pacman,
+    Zypper,
+}
+
+impl PackageManager {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "apk" => Some(Self::Apk),
+            "apt-get" => Some(Self::Apt),
+            "dnf" => Some(Self::Dnf),
+            "pacman" => Some(Self::Pacman),
+            "zypper" => Some(Self::Zypper),
+            _ => None,
+        }
+    }
+
+    fn install_args(self) -> &'static [&'static str] {
+        match self {
+            Self::Apk => &["sudo", "apk", "add"],
+            Self::Apt => &["sudo", "apt-get", "install", "-y"],
+            Self::Dnf => &["sudo", "dnf", "install", "-y"],
+            Self::Pacman => &["sudo", "pacman", "-S", "--noconfirm", "--needed"],
+            Self::Zypper => &["sudo", "zypper", "--non-interactive", "install"],
+        }
+    }
+
+    fn remove_args(self) -> &'static [&'static str] {
+        match self {
+            Self::Apk => &["sudo", "apk", "del"],
+            Self::Apt => &["sudo", "apt-get", "remove", "-y"],
+            Self::Dnf => &["sudo", "dnf", "remove", "-y"],
+            Self::Pacman => &["sudo", "pacman", "-R", "--noconfirm"],
+            Self::Zypper => &["sudo", "zypper", "--non-interactive", "remove"],
+        }
+    }
+}
+
+fn containers() -> Result<Vec<Container>, Box<dyn Error>> {
+    let output = Command::new("podman")
+        .args(["ps", "--all", "--format", "json"])
+        .stderr(Stdio::piped())
+        .output()
+        .map_err(|err| format!("failed to run podman: {}", err))?;
+    if !output.status.success() {
+        return Err(format!(
+            "podman ps failed with status {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+        .into());
+    }
+    let podman_containers: Vec<PodmanContainer> = serde_json::from_slice(&output.stdout)?;
+    let mut containers = Vec::new();
+    for podman_container in podman_containers {
+        let Some(name) = podman_container.names.into_iter().next() else {
+            continue;
+        };
+        let labels = podman_container.labels.unwrap_or_default();
+        let kind = if labels.get("manager").is_some_and(|x| x == "distrobox") {
+            ContainerKind::Distrobox
+        } else if labels.contains_key("com.github.containers.toolbox") {
+            ContainerKind::Toolbox
+        } else {
+            continue;
+        };
+        log::info!(
+            "found {:?} container {} from {}",
+            kind,
+            name,
+            podman_container.image
+        );
+        containers.push(Container { name, kind });
+    }
+    containers.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(containers)
+}
+
+#[derive(Debug)]
+pub struct Distrobox {
+    containers: Vec<Container>,
+    locale: String,
+    appstream_caches: Vec<AppstreamCache>,
+}
+
+impl Distrobox {
+    pub fn new(locale: &str) -> Result<Self, Box<dyn Error>> {
+        let containers = containers()?;
+        if containers.is_empty() {
+            return Err("no distrobox or toolbox containers found".into());
+        }
+        Ok(Self {
+            containers,
+            locale: locale.to_string(),
+            appstream_caches: Vec::new(),
+        })
+    }
+
+    fn container(&self, source_id: &str) -> Result<&Container, BackendError> {
+        self.containers
+            .iter()
+            .find(|container| container.source_id() == source_id)
+            .ok_or_else(|| BackendError::Other(format!("container for {source_id} not found")))
+    }
+
+    /// Info from the container's catalog for an exported desktop entry
+    fn exported_info(
+        &self,
+        container: &Container,
+        appstream_cache: &AppstreamCache,
+        desktop_id: &str,
+    ) -> (AppId, AppInfo) {
+        let id = AppId::new(desktop_id.trim_end_matches(".desktop"));
+        if let Some((id, info)) = appstream_cache
+            .infos
+            .get(&id)
+            .map(|info| (id.clone(), info))
+            .or_else(|| {
+                appstream_cache
+                    .infos
+                    .iter()
+                    .find(|(_, info)| info.desktop_ids.iter().any(|x| x == desktop_id))
+                    .map(|(id, info)| (id.clone(), info))
+            })
+        {
+            return (id, (**info).clone());
+        }
+
+        // Apps not in the catalog are described by their exported desktop entries
+        let mut info = AppInfo {
+            source_id: container.source_id(),
+            source_name: container.name.clone(),
+            name: desktop_id.trim_end_matches(".desktop").to_string(),
+            desktop_ids: vec![desktop_id.to_string()],
+            ..Default::default()
+        };
+        if let Some(path) = container.exported_path(desktop_id)
+            && let Ok(entry) = freedesktop_entry_parser::parse_entry(path)
+        {
+            let get_attr = |key| {
+                entry
+                    .get("Desktop Entry", key)
+                    .and_then(|attr| attr.first())
+                    .cloned()
+            };
+            if let Some(name) = get_attr("Name") {
+                info.name = name;
+            }
+            info.summary = get_attr("Comment").unwrap_or_default();
+        }
+        (id, info)
+    }
+}
+
+impl Backend for Distrobox {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::INSTALL | Capabilities::UNINSTALL
+    }
+
+    fn load_caches(&mut self, refresh: bool) -> Result<(), Box<dyn Error>> {
+        let mut appstream_caches = Vec::with_capacity(self.containers.len());
+        for container in self.containers.iter() {
+            let Some(catalog_dir) = container.catalog_dir() else {
+                continue;
+            };
+            if (refresh || !catalog_dir.is_dir())
+                && let Err(err) = container.copy_catalogs(&catalog_dir)
+            {
+                log::warn!(
+                    "failed to copy catalogs from container {}: {}",
+                    container.name,
+                    err
+                );
+            }
+            let mut appstream_cache = AppstreamCache::system_root(
+                container.source_id(),
+                container.name.clone(),
+                &catalog_dir,
+                &self.locale,
+            );
+            appstream_cache.reload();
+            appstream_caches.push(appstream_cache);
+        }
+        self.appstream_caches = appstream_caches;
+        Ok(())
+    }
+
+    fn info_caches(&self) -> &[AppstreamCache] {
+        &self.appstream_caches
+    }
+
+    fn installed(&self) -> Result<Vec<Package>, Box<dyn Error>> {
+        let mut packages = Vec::new();
+        for container in self.containers.iter() {
+            let Some(appstream_cache) = self
+                .appstream_caches
+                .iter()
+                .find(|x| x.source_id == container.source_id())
+            else {
+                continue;
+            };
+            for desktop_id in container.exported_desktop_ids() {
+                let (id, info) = self.exported_info(container, appstream_cache, &desktop_id);
+                packages.push(Package {
+                    id,
+                    icon: appstream_cache.icon(&info),
+                    info: Arc::new(info),
+                    version: String::new(),
+                    extra: HashMap::new(),
+                });
+            }
+        }
+        Ok(packages)
+    }
+
+    fn updates(&self) -> Result<Vec<Package>, Box<dyn Error>> {
+        //TODO: check for updates with the container's package manager
+        Ok(Vec::new())
+    }
+
+    fn file_packages(&self, _path: &str) -> Result<Vec<Package>, Box<dyn Error>> {
+        Err("distrobox backend does not support file-based package parsing".into())
+    }
+
+    fn operation(
+        &self,
+        op: &Operation,
+        _cancel_token: &CancelToken,
+        mut f: Box<dyn FnMut(OperationProgress) + 'static>,
+    ) -> Result<(), BackendError> {
+        let install = match &op.kind {
+            OperationKind::Install => true,
+            OperationKind::Uninstall { .. } => false,
+            OperationKind::Update
+            | OperationKind::Downgrade(_)
+            | OperationKind::RepositoryAdd(_)
+            | OperationKind::RepositoryRemove(_, _)
+            | OperationKind::Deployment(_) => {
+                return Err(BackendError::NotSupported(
+                    "distrobox backend only supports installing and uninstalling".to_string(),
+                ));
+            }
+        };
+
+        let total = op.infos.len().max(1) as f32;
+        for (i, info) in op.infos.iter().enumerate() {
+            let container = self.container(&info.source_id)?;
+            let package_manager = container.package_manager()?;
+            let item_percent = |percent: f32| ((i as f32) * 100.0 + percent) / total;
+            if install {
+                f(OperationProgress {
+                    percent: item_percent(0.0),
+                    phase_opt: Some(OperationPhase::Installing),
+                    package_opt: Some(info.name.clone()),
+                    ..Default::default()
+                });
+                let mut args = package_manager.install_args().to_vec();
+                args.extend(info.pkgnames.iter().map(String::as_str));
+                container.run(&args)?;
+                f(OperationProgress {
+                    percent: item_percent(75.0),
+                    phase_opt: Some(OperationPhase::Installing),
+                    package_opt: Some(info.name.clone()),
+                    ..Default::default()
+                });
+                for desktop_id in info.desktop_ids.iter() {
+                    container.export(desktop_id)?;
+                }
+            } else {
+                f(OperationProgress {
+                    percent: item_percent(0.0),
+                    phase_opt: Some(OperationPhase::Removing),
+                    package_opt: Some(info.name.clone()),
+                    ..Default::default()
+                });
+                for desktop_id in info.desktop_ids.iter() {
+                    container.unexport(desktop_id)?;
+                }
+                // Apps only known by their desktop entry are just unexported
+                if !info.pkgnames.is_empty() {
+                    let mut args = package_manager.remove_args().to_vec();
+                    args.extend(info.pkgnames.iter().map(String::as_str));
+                    container.run(&args)?;
+                }
+            }
+        }
+        f(OperationProgress::new(100.0));
+        Ok(())
+    }
+}
@@ -0,0 +1,338 @@
+use std::{error::Error, fmt, io};
+
+use crate::{fl, operation::RepositoryRemoveError};
+
+/// Reason a backend operation failed, used to offer the user a way forward
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum BackendError {
+    NotSupported(String),
+    /// Authorization was denied or dismissed
+    AuthCancelled,
+    NetworkUnavailable(String),
+    PackageNotFound(String),
+    DependencyConflict(String),
+    DiskFull(String),
+    /// Repository still has installed items and removal was not forced
+    RepositoryInUse(RepositoryRemoveError),
+    Cancelled,
+    /// Backend specific error message
+    Other(String),
+}
+
+impl BackendError {
+    /// Short description shown in the list of failed operations
+    pub fn text(&self) -> String {
+        match self {
+            Self::Cancelled => fl!("cancelled"),
+            _ => self.to_string(),
+        }
+    }
+
+    /// Returns true if running the operation again may succeed
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::AuthCancelled
+            | Self::NetworkUnavailable(_)
+            | Self::DiskFull(_)
+            | Self::Cancelled
+            | Self::Other(_) => true,
+            Self::NotSupported(_)
+            | Self::PackageNotFound(_)
+            | Self::DependencyConflict(_)
+            | Self::RepositoryInUse(_) => false,
+        }
+    }
+}
+
+impl fmt::Display for BackendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotSupported(err) => write!(f, "not supported: {err}"),
+            Self::AuthCancelled => write!(f, "authorization cancelled"),
+            Self::NetworkUnavailable(err) => write!(f, "network unavailable: {err}"),
+            Self::PackageNotFound(err) => write!(f, "package not found: {err}"),
+            Self::DependencyConflict(err) => write!(f, "dependency conflict: {err}"),
+            Self::DiskFull(err) => write!(f, "disk full: {err}"),
+            Self::RepositoryInUse(err) => write!(f, "{err}"),
+            Self::Cancelled => write!(f, "cancelled"),
+            Self::Other(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl Error for BackendError {}
+
+impl From<String> for BackendError {
+    fn from(err: String) -> Self {
+        Self::Other(err)
+    }
+}
+
+impl From<&str> for BackendError {
+    fn from(err: &str) -> Self {
+        Self::Other(err.to_string())
+    }
+}
+
+impl From<RepositoryRemoveError> for BackendError {
+    fn from(err: RepositoryRemoveError) -> Self {
+        Self::RepositoryInUse(err)
+    }
+}
+
+impl From<io::Error> for BackendError {
+    fn from(err: io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::StorageFull => Self::DiskFull(err.to_string()),
+            _ => Self::Other(err.to_string()),
+        }
+    }
+}
+
+// Helpers still return boxed errors, keep the type of any error they wrap
+impl From<Box<dyn Error>> for BackendError {
+    fn from(err: Box<dyn Error>) -> Self {
+        let err = match err.downcast::<Self>() {
+            Ok(backend_err) => return *backend_err,
+            Err(err) => err,
+        };
+        let err = match err.downcast::<RepositoryRemoveError>() {
+            Ok(repo_rm) => return Self::RepositoryInUse(*repo_rm),
+            Err(err) => err,
+        };
+        let err = match err.downcast::<io::Error>() {
+            Ok(io_err) => return (*io_err).into(),
+            Err(err) => err,
+        };
+        #[cfg(feature = "flatpak")]
+        let err = match err.downcast::<libflatpak::glib::Error>() {
+            Ok(glib_err) => return (*glib_err).into(),
+            Err(err) => err,
+        };
+        #[cfg(feature = "packagekit")]
+        let err = match err.downcast::<packagekit_zbus::zbus::Error>() {
+            Ok(zbus_err) => return (*zbus_err).into(),
+            Err(err) => err,
+        };
+        Self::Other(err.to_string())
+    }
+}
+
+#[cfg(feature = "flatpak")]
+impl From<libflatpak::glib::Error> for BackendError {
+    fn from(err: libflatpak::glib::Error) -> Self {
+        use libflatpak::gio::{DBusError, IOErrorEnum};
+
+        let message = err.message().to_string();
+        if let Some(kind) = err.kind::<libflatpak::Error>() {
+            match kind {
+                libflatpak::Error::Aborted => return Self::Cancelled,
+                libflatpak::Error::NeedRuntime
+                | libflatpak::Error::RuntimeNotFound
+                | libflatpak::Error::RuntimeUsed => return Self::DependencyConflict(message),
+                _ => {}
+            }
+        } else if let Some(kind) = err.kind::<IOErrorEnum>() {
+            match kind {
+                IOErrorEnum::Cancelled => return Self::Cancelled,
+                IOErrorEnum::NoSpace => return Self::DiskFull(message),
+                IOErrorEnum::NotSupported => return Self::NotSupported(message),
+                IOErrorEnum::NetworkUnreachable
+                | IOErrorEnum::HostUnreachable
+                | IOErrorEnum::ConnectionRefused
+                | IOErrorEnum::TimedOut => return Self::NetworkUnavailable(message),
+                _ => {}
+            }
+        } else if err.matches(DBusError::AccessDenied) {
+            // Polkit denials from the system helper
+            return Self::AuthCancelled;
+        }
+        Self::Other(message)
+    }
+}
+
+#[cfg(feature = "packagekit")]
+impl From<packagekit_zbus::zbus::Error> for BackendError {
+    fn from(err: packagekit_zbus::zbus::Error) -> Self {
+        match &err {
+            // Polkit denials are reported by PackageKit as a method error
+            packagekit_zbus::zbus::Error::MethodError(name, _, _)
+                if name.as_str() == "org.freedesktop.PackageKit.Transaction.RefusedByPolicy" =>
+            {
+                Self::AuthCancelled
+            }
+            _ => Self::Other(err.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn repo_in_use() -> RepositoryRemoveError {
+        RepositoryRemoveError {
+            rms: Vec::new(),
+            installed: vec![("org.example.App".to_string(), "App".to_string())],
+        }
+    }
+
+    #[test]
+    fn retryable() {
+        for (err, retryable) in [
+            (BackendError::NotSupported("test".into()), false),
+            (BackendError::AuthCancelled, true),
+            (BackendError::NetworkUnavailable("test".into()), true),
+            (BackendError::PackageNotFound("test".into()), false),
+            (BackendError::DependencyConflict("test".into()), false),
+            (BackendError::DiskFull("test".into()), true),
+            (BackendError::RepositoryInUse(repo_in_use()), false),
+            (BackendError::Cancelled, true),
+            (BackendError::Other("test".into()), true),
+        ] {
+            assert_eq!(err.is_retryable(), retryable, "error {err:?}");
+        }
+    }
+
+    #[test]
+    fn from_io() {
+        for (kind, expected) in [
+            (
+                io::ErrorKind::StorageFull,
+                BackendError::DiskFull("test".into()),
+            ),
+            (io::ErrorKind::NotFound, BackendError::Other("test".into())),
+        ] {
+            let err = BackendError::from(io::Error::new(kind, "test"));
+            assert_eq!(err, expected, "kind {kind:?}");
+        }
+    }
+
+    #[test]
+    fn from_boxed() {
+        for (err, expected) in [
+            (
+                Box::new(BackendError::Cancelled) as Box<dyn Error>,
+                BackendError::Cancelled,
+            ),
+            (
+                Box::new(repo_in_use()),
+                BackendError::RepositoryInUse(repo_in_use()),
+            ),
+            (
+                Box::new(io::Error::new(io::ErrorKind::StorageFull, "test")),
+                BackendError::DiskFull("test".into()),
+            ),
+            ("test".into(), BackendError::Other("test".into())),
+        ] {
+            assert_eq!(BackendError::from(err), expected);
+        }
+    }
+
+    #[cfg(feature = "flatpak")]
+    #[test]
+    fn from_glib() {
+        use libflatpak::{
+            gio::{DBusError, IOErrorEnum},
+            glib,
+        };
+
+        for (err, expected) in [
+            (
+                glib::Error::new(libflatpak::Error::Aborted, "test"),
+                BackendError::Cancelled,
+            ),
+            (
+                glib::Error::new(libflatpak::Error::NeedRuntime, "test"),
+                BackendError::DependencyConflict("test".into()),
+            ),
+            (
+                glib::Error::new(libflatpak::Error::RuntimeNotFound, "test"),
+                BackendError::DependencyConflict("test".into()),
+            ),
+            (
+                glib::Error::new(libflatpak::Error::RuntimeUsed, "test"),
+                BackendError::DependencyConflict("test".into()),
+            ),
+            (
+                glib::Error::new(libflatpak::Error::AlreadyInstalled, "test"),
+                BackendError::Other("test".into()),
+            ),
+            (
+                glib::Error::new(IOErrorEnum::Cancelled, "test"),
+                BackendError::Cancelled,
+            ),
+            (
+                glib::Error::new(IOErrorEnum::NoSpace, "test"),
+                BackendError::DiskFull("test".into()),
+            ),
+            (
+                glib::Error::new(IOErrorEnum::NotSupported, "test"),
+                BackendError::NotSupported("test".into()),
+            ),
+            (
+                glib::Error::new(IOErrorEnum::NetworkUnreachable, "test"),
+                BackendError::NetworkUnavailable("test".into()),
+            ),
+            (
+                glib::Error::new(IOErrorEnum::HostUnreachable, "test"),
+                BackendError::NetworkUnavailable("test".into()),
+            ),
+            (
+                glib::Error::new(IOErrorEnum::ConnectionRefused, "test"),
+                BackendError::NetworkUnavailable("test".into()),
+            ),
+            (
+                glib::Error::new(IOErrorEnum::TimedOut, "test"),
+                BackendError::NetworkUnavailable("test".into()),
+            ),
+            (
+                glib::Error::new(IOErrorEnum::NotFound, "test"),
+                BackendError::Other("test".into()),
+            ),
+            (
+                glib::Error::new(DBusError::AccessDenied, "test"),
+                BackendError::AuthCancelled,
+            ),
+        ] {
+            let message = format!("{err:?}");
+            assert_eq!(BackendError::from(err), expected, "error {message}");
+        }
+    }
+
+    #[cfg(feature = "packagekit")]
+    #[test]
+    fn from_zbus() {
+        use packagekit_zbus::zbus::{self, message::Message, names::OwnedErrorName};
+
+        let method_error = |name: &str| {
+            let msg = Message::method_call("/", "Test")
+                .unwrap()
+                .build(&())
+                .unwrap();
+            zbus::Error::MethodError(
+                OwnedErrorName::try_from(name).unwrap(),
+                Some("test".to_string()),
+                msg,
+            )
+        };
+
+        for (err, expected) in [
+            (
+                method_error("org.freedesktop.PackageKit.Transaction.RefusedByPolicy"),
+                BackendError::AuthCancelled,
+            ),
+            (
+                method_error("org.freedesktop.DBus.Error.Failed"),
+                BackendError::Other(method_error("org.freedesktop.DBus.Error.Failed").to_string()),
+            ),
+            (
+                zbus::Error::Failure("test".to_string()),
+                BackendError::Other(zbus::Error::Failure("test".to_string()).to_string()),
+            ),
+        ] {
+            let message = err.to_string();
+            assert_eq!(BackendError::from(err), expected, "error {message}");
+        }
+    }
+}
@@ -0,0 +1,739 @@
+use cosmic::widget;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs,
+    io::{BufRead, BufReader, Write},
+    os::unix::fs::PermissionsExt,
+    path::PathBuf,
+    process::{Command, Stdio},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use super::{Backend, BackendError, CancelToken, Capabilities, Package};
+use crate::{
+    AppId, AppInfo, AppstreamCache, Operation, OperationKind, OperationPhase, OperationProgress,
+};
+
+// Plugin directories searched after the user data directory, in order of priority
+const PLUGIN_DIRS: &[&str] = &[
+    "/usr/local/lib/cosmic-store/backends",
+    "/usr/lib/cosmic-store/backends",
+];
+
+// Every process only handles one request, so the ID is constant
+const REQUEST_ID: u64 = 1;
+
+// Time a plugin has to exit after responding before it is killed
+const EXIT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Find external backend executables, returns the plugin name and path of each
+pub fn plugins() -> Vec<(String, PathBuf)> {
+    let mut plugin_dirs = Vec::new();
+    if let Some(data_dir) = dirs::data_dir() {
+        plugin_dirs.push(data_dir.join("cosmic-store").join("backends"));
+    }
+    plugin_dirs.extend(PLUGIN_DIRS.iter().map(PathBuf::from));
+
+    let mut plugins: Vec<(String, PathBuf)> = Vec::new();
+    for dir in plugin_dirs {
+        let read_dir = match fs::read_dir(&dir) {
+            Ok(ok) => ok,
+            Err(err) => {
+                log::debug!("failed to read plugin directory {:?}: {}", dir, err);
+                continue;
+            }
+        };
+
+        for entry_res in read_dir {
+            let entry = match entry_res {
+                Ok(ok) => ok,
+                Err(err) => {
+                    log::warn!(
+                        "failed to read entry in plugin directory {:?}: {}",
+                        dir,
+                        err
+                    );
+                    continue;
+                }
+            };
+
+            let path = entry.path();
+            let executable = fs::metadata(&path).is_ok_and(|metadata| {
+                metadata.is_file() && metadata.permissions().mode() & 0o111 != 0
+            });
+            if !executable {
+                log::debug!("ignoring non-executable plugin {:?}", path);
+                continue;
+            }
+
+            let plugin_name = entry.file_name().to_string_lossy().to_string();
+            // Earlier directories take priority
+            if plugins.iter().any(|(name, _)| name == &plugin_name) {
+                log::info!("ignoring {:?}, plugin {} already found", path, plugin_name);
+                continue;
+            }
+            plugins.push((plugin_name, path));
+        }
+    }
+    plugins
+}
+
+#[derive(Serialize)]
+struct Request<'a, P> {
+    jsonrpc: &'static str,
+    id: u64,
+    method: &'a str,
+    params: P,
+}
+
+#[derive(Deserialize)]
+struct ResponseError {
+    code: i64,
+    message: String,
+    #[serde(default)]
+    data: Option<ResponseErrorData>,
+}
+
+#[derive(Deserialize)]
+struct ResponseErrorData {
+    kind: String,
+}
+
+impl From<ResponseError> for BackendError {
+    fn from(err: ResponseError) -> Self {
+        let message = format!("{} (code {})", err.message, err.code);
+        match err.data.as_ref().map(|data| data.kind.as_str()) {
+            Some("not_supported") => Self::NotSupported(message),
+            Some("auth_cancelled") => Self::AuthCancelled,
+            Some("network_unavailable") => Self::NetworkUnavailable(message),
+            Some("package_not_found") => Self::PackageNotFound(message),
+            Some("dependency_conflict") => Self::DependencyConflict(message),
+            Some("disk_full") => Self::DiskFull(message),
+            Some("cancelled") => Self::Cancelled,
+            _ => Self::Other(message),
+        }
+    }
+}
+
+// Either a response to the request or a notification
+#[derive(Deserialize)]
+struct Response {
+    id: Option<u64>,
+    method: Option<String>,
+    #[serde(default)]
+    params: serde_json::Value,
+    #[serde(default)]
+    result: serde_json::Value,
+    error: Option<ResponseError>,
+}
+
+#[derive(Deserialize)]
+struct ProgressParams {
+    progress: f32,
+    phase: Option<String>,
+    package: Option<String>,
+    #[serde(default)]
+    bytes_downloaded: u64,
+    #[serde(default)]
+    bytes_total: u64,
+    #[serde(default)]
+    status: String,
+}
+
+impl From<ProgressParams> for OperationProgress {
+    fn from(params: ProgressParams) -> Self {
+        let phase_opt = match params.phase.as_deref() {
+            Some("resolving") => Some(OperationPhase::Resolving),
+            Some("downloading") => Some(OperationPhase::Downloading),
+            Some("installing") => Some(OperationPhase::Installing),
+            Some("removing") => Some(OperationPhase::Removing),
+            Some("cleaning_up") => Some(OperationPhase::CleaningUp),
+            Some(other) => {
+                log::warn!("unknown progress phase {:?}", other);
+                None
+            }
+            None => None,
+        };
+        Self {
+            percent: params.progress,
+            phase_opt,
+            package_opt: params.package,
+            bytes_downloaded: params.bytes_downloaded,
+            bytes_total: params.bytes_total,
+            status: params.status,
+            item_percents: Vec::new(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Catalog {
+    source_id: String,
+    source_name: String,
+    #[serde(default)]
+    paths: Vec<PathBuf>,
+    #[serde(default)]
+    icons_paths: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct LoadCachesResult {
+    catalogs: Vec<Catalog>,
+    capabilities: Option<Vec<String>>,
+}
+
+#[derive(Deserialize)]
+struct ExternalPackage {
+    id: String,
+    source_id: String,
+    #[serde(default)]
+    version: String,
+    name: Option<String>,
+    #[serde(default)]
+    summary: String,
+    #[serde(default)]
+    description: String,
+    icon: Option<String>,
+    #[serde(default)]
+    pkgnames: Vec<String>,
+    #[serde(default)]
+    package_paths: Vec<String>,
+    #[serde(default)]
+    extra: HashMap<String, String>,
+}
+
+#[derive(Serialize)]
+struct OperationPackage<'a> {
+    id: &'a str,
+    source_id: &'a str,
+    pkgnames: &'a [String],
+    package_paths: &'a [String],
+    /// Revision to downgrade to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    revision: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct OperationRepository<'a> {
+    id: &'a str,
+    data: Option<String>,
+}
+
+#[derive(Serialize)]
+struct OperationParams<'a> {
+    kind: &'static str,
+    purge_data: bool,
+    force: bool,
+    packages: Vec<OperationPackage<'a>>,
+    repositories: Vec<OperationRepository<'a>>,
+}
+
+#[derive(Debug)]
+pub struct External {
+    path: PathBuf,
+    locale: String,
+    capabilities: Capabilities,
+    appstream_caches: Vec<AppstreamCache>,
+}
+
+impl External {
+    pub fn new(path: PathBuf, locale: &str) -> Result<Self, Box<dyn Error>> {
+        if !path.is_file() {
+            return Err(format!("external backend {path:?} not found").into());
+        }
+        // Caches are only known after calling load_caches
+        Ok(Self {
+            path,
+            locale: locale.to_string(),
+            capabilities: Capabilities::empty(),
+            appstream_caches: Vec::new(),
+        })
+    }
+
+    /// Run the plugin to handle one request, passing progress notifications to the callback.
+    /// The plugin is killed if the cancel token is cancelled.
+    fn call<P: Serialize, R: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: P,
+        cancel_token_opt: Option<&CancelToken>,
+        mut on_progress: impl FnMut(OperationProgress),
+    ) -> Result<R, Box<dyn Error>> {
+        let child = Arc::new(Mutex::new(
+            Command::new(&self.path)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::inherit())
+                .spawn()?,
+        ));
+
+        if let Some(cancel_token) = cancel_token_opt {
+            let child = child.clone();
+            let path = self.path.clone();
+            let method = method.to_string();
+            cancel_token.on_cancel(move || {
+                log::info!("killing {:?} to cancel {}", path, method);
+                if let Err(err) = child.lock().unwrap().kill() {
+                    log::warn!("failed to kill {:?}: {}", path, err);
+                }
+            });
+        }
+
+        {
+            let mut stdin = child
+                .lock()
+                .unwrap()
+                .stdin
+                .take()
+                .ok_or("failed to open plugin stdin")?;
+            let mut line = serde_json::to_string(&Request {
+                jsonrpc: "2.0",
+                id: REQUEST_ID,
+                method,
+                params,
+            })?;
+            line.push('\n');
+            stdin.write_all(line.as_bytes())?;
+            // Dropping stdin closes it, there are no further requests
+        }
+
+        let stdout = child
+            .lock()
+            .unwrap()
+            .stdout
+            .take()
+            .ok_or("failed to open plugin stdout")?;
+        let mut response_opt = None;
+        for line_res in BufReader::new(stdout).lines() {
+            let line = line_res?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let response: Response = serde_json::from_str(&line)?;
+            match response.method.as_deref() {
+                Some("progress") => match serde_json::from_value::<ProgressParams>(response.params)
+                {
+                    Ok(params) => on_progress(params.into()),
+                    Err(err) => {
+                        log::warn!("invalid progress from {:?}: {}", self.path, err);
+                    }
+                },
+                Some(other) => {
+                    log::warn!("unknown notification {:?} from {:?}", other, self.path);
+                }
+                None => {
+                    if response.id == Some(REQUEST_ID) {
+                        response_opt = Some(response);
+                        break;
+                    }
+                    log::warn!(
+                        "unexpected response id {:?} from {:?}",
+                        response.id,
+                        self.path
+                    );
+                }
+            }
+        }
+
+        // Poll without holding the lock for long, so cancelling can still kill the plugin
+        let responded = Instant::now();
+        let status = loop {
+            {
+                let mut child = child.lock().unwrap();
+                if let Some(status) = child.try_wait()? {
+                    break status;
+                }
+                if response_opt.is_some() && responded.elapsed() > EXIT_TIMEOUT {
+                    log::warn!(
+                        "killing {:?} as it did not exit after responding to {}",
+                        self.path,
+                        method
+                    );
+                    child.kill()?;
+                    break child.wait()?;
+                }
+            }
+            thread::sleep(Duration::from_millis(50));
+        };
+        let response = response_opt.ok_or_else(|| {
+            format!(
+                "{:?} exited with {} without responding to {}",
+                self.path, status, method
+            )
+        })?;
+        if let Some(err) = response.error {
+            return Err(BackendError::from(err).into());
+        }
+        Ok(serde_json::from_value(response.result)?)
+    }
+
+    fn packages(&self, external_packages: Vec<ExternalPackage>) -> Vec<Package> {
+        let mut packages = Vec::with_capacity(external_packages.len());
+        for external_package in external_packages {
+            let id = AppId::new(&external_package.id);
+            let Some(appstream_cache) = self
+                .appstream_caches
+                .iter()
+                .find(|cache| cache.source_id == external_package.source_id)
+            else {
+                log::warn!(
+                    "{:?} returned {:?} from unknown source {:?}",
+                    self.path,
+                    id,
+                    external_package.source_id
+                );
+                continue;
+            };
+
+            // Prefer appstream info if the catalog has it
+            let (icon, info) = match appstream_cache.infos.get(&id) {
+                Some(info) => (appstream_cache.icon(info), info.clone()),
+                None => {
+                    let icon = match &external_package.icon {
+                        Some(icon) if icon.starts_with('/') => {
+                            widget::icon::from_path(PathBuf::from(icon))
+                        }
+                        Some(icon) => widget::icon::from_name(icon.as_str()).size(128).handle(),
+                        None => widget::icon::from_name("package-x-generic")
+                            .size(128)
+                            .handle(),
+                    };
+                    //TODO: fill in more AppInfo fields
+                    let info = Arc::new(AppInfo {
+                        source_id: appstream_cache.source_id.clone(),
+                        source_name: appstream_cache.source_name.clone(),
+                        name: external_package
+                            .name
+                            .unwrap_or_else(|| external_package.id.clone()),
+                        summary: external_package.summary,
+                        description: external_package.description,
+                        pkgnames: external_package.pkgnames,
+                        package_paths: external_package.package_paths,
+                        ..Default::default()
+                    });
+                    (icon, info)
+                }
+            };
+
+            packages.push(Package {
+                id,
+                icon,
+                info,
+                version: external_package.version,
+                extra: external_package.extra,
+            });
+        }
+        packages
+    }
+}
+
+impl Backend for External {
+    fn capabilities(&self) -> Capabilities {
+        self.capabilities
+    }
+
+    fn load_caches(&mut self, refresh: bool) -> Result<(), Box<dyn Error>> {
+        let result: LoadCachesResult = self.call(
+            "load_caches",
+            serde_json::json!({
+                "refresh": refresh,
+                "locale": self.locale,
+            }),
+            None,
+            |_| {},
+        )?;
+
+        self.capabilities = match result.capabilities {
+            Some(names) => {
+                let mut capabilities = Capabilities::empty();
+                for name in names {
+                    match Capabilities::from_name(&name.to_uppercase()) {
+                        Some(capability) => capabilities |= capability,
+                        None => {
+                            log::warn!("unknown capability {:?} from {:?}", name, self.path);
+                        }
+                    }
+                }
+                capabilities
+            }
+            None => Capabilities::INSTALL | Capabilities::UNINSTALL | Capabilities::UPDATE,
+        };
+
+        self.appstream_caches = result
+            .catalogs
+            .into_iter()
+            .map(|catalog| {
+                let mut cache = AppstreamCache::new(
+                    catalog.source_id,
+                    catalog.source_name,
+                    catalog.paths,
+                    catalog.icons_paths,
+                    &self.locale,
+                );
+                cache.reload();
+                cache
+            })
+            .collect();
+        Ok(())
+    }
+
+    fn info_caches(&self) -> &[AppstreamCache] {
+        &self.appstream_caches
+    }
+
+    fn installed(&self) -> Result<Vec<Package>, Box<dyn Error>> {
+        let packages = self.call("installed", serde_json::json!({}), None, |_| {})?;
+        Ok(self.packages(packages))
+    }
+
+    fn updates(&self) -> Result<Vec<Package>, Box<dyn Error>> {
+        let packages = self.call("updates", serde_json::json!({}), None, |_| {})?;
+        Ok(self.packages(packages))
+    }
+
+    fn file_packages(&self, path: &str) -> Result<Vec<Package>, Box<dyn Error>> {
+        let packages = self.call(
+            "file_packages",
+            serde_json::json!({ "path": path }),
+            None,
+            |_| {},
+        )?;
+        Ok(self.packages(packages))
+    }
+
+    fn operation(
+        &self,
+        op: &Operation,
+        cancel_token: &CancelToken,
+        mut f: Box<dyn FnMut(OperationProgress) + 'static>,
+    ) -> Result<(), BackendError> {
+        let mut params = OperationParams {
+            kind: "",
+            purge_data: false,
+            force: false,
+            packages: op
+                .package_ids
+                .iter()
+                .zip(op.infos.iter())
+                .map(|(id, info)| OperationPackage {
+                    id: id.raw(),
+                    source_id: &info.source_id,
+                    pkgnames: &info.pkgnames,
+                    package_paths: &info.package_paths,
+                    revision: None,
+                })
+                .collect(),
+            repositories: Vec::new(),
+        };
+        match &op.kind {
+            OperationKind::Install => {
+                params.kind = "install";
+            }
+            OperationKind::Uninstall { purge_data } => {
+                params.kind = "uninstall";
+                params.purge_data = *purge_data;
+            }
+            OperationKind::Update => {
+                params.kind = "update";
+            }
+            OperationKind::Downgrade(revisions) => {
+                params.kind = "downgrade";
+                for (package, revision) in params.packages.iter_mut().zip(revisions.iter()) {
+                    package.revision = Some(revision);
+                }
+            }
+            OperationKind::RepositoryAdd(adds) => {
+                params.kind = "repository_add";
+                params.repositories = adds
+                    .iter()
+                    .map(|add| OperationRepository {
+                        id: &add.id,
+                        data: Some(String::from_utf8_lossy(&add.data).to_string()),
+                    })
+                    .collect();
+            }
+            OperationKind::Deployment(_) => {
+                return Err(BackendError::NotSupported(
+                    "external backends do not support deployments".to_string(),
+                ));
+            }
+            OperationKind::RepositoryRemove(rms, force) => {
+                params.kind = "repository_remove";
+                params.force = *force;
+                params.repositories = rms
+                    .iter()
+                    .map(|rm| OperationRepository {
+                        id: &rm.id,
+                        data: None,
+                    })
+                    .collect();
+            }
+        }
+        // The result of an operation is ignored, only errors matter
+        let result: Result<serde_json::Value, _> =
+            self.call("operation", params, Some(cancel_token), |progress| {
+                f(progress)
+            });
+        match result {
+            Ok(_) => Ok(()),
+            // The plugin process is killed when cancelled
+            Err(_) if cancel_token.is_cancelled() => Err(BackendError::Cancelled),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::BackendName;
+    use tempfile::TempDir;
+
+    // Write a plugin that reads the request and then runs the script
+    fn plugin(dir: &TempDir, script: &str) -> External {
+        let path = dir.path().join("plugin");
+        fs::write(&path, format!("#!/bin/sh\nread -r request\n{script}\n")).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        External::new(path, "en").unwrap()
+    }
+
+    fn install() -> Operation {
+        Operation {
+            kind: OperationKind::Install,
+            backend_name: BackendName::external("test"),
+            package_ids: vec![AppId::new("org.example.App")],
+            infos: vec![Arc::new(AppInfo::default())],
+        }
+    }
+
+    #[test]
+    fn capabilities_handshake() {
+        let dir = TempDir::new().unwrap();
+        let mut external = plugin(
+            &dir,
+            r#"echo '{"jsonrpc":"2.0","id":1,"result":{"catalogs":[],"capabilities":["install","cancel","bogus"]}}'"#,
+        );
+        external.load_caches(false).unwrap();
+        assert_eq!(
+            external.capabilities(),
+            Capabilities::INSTALL | Capabilities::CANCEL
+        );
+
+        let mut external = plugin(
+            &dir,
+            r#"echo '{"jsonrpc":"2.0","id":1,"result":{"catalogs":[]}}'"#,
+        );
+        external.load_caches(false).unwrap();
+        assert_eq!(
+            external.capabilities(),
+            Capabilities::INSTALL | Capabilities::UNINSTALL | Capabilities::UPDATE
+        );
+    }
+
+    #[test]
+    fn progress_notifications() {
+        let dir = TempDir::new().unwrap();
+        let external = plugin(
+            &dir,
+            r#"echo '{"jsonrpc":"2.0","method":"progress","params":{"progress":25.0,"phase":"downloading","package":"example","bytes_downloaded":1,"bytes_total":4}}'
+echo '{"jsonrpc":"2.0","method":"unknown","params":{}}'
+echo '{"jsonrpc":"2.0","method":"progress","params":{"progress":75.0,"phase":"installing"}}'
+echo '{"jsonrpc":"2.0","id":1,"result":null}'"#,
+        );
+        let progress = Arc::new(Mutex::new(Vec::new()));
+        let result = {
+            let progress = progress.clone();
+            external.operation(
+                &install(),
+                &CancelToken::new(),
+                Box::new(move |p| progress.lock().unwrap().push(p)),
+            )
+        };
+        assert_eq!(result, Ok(()));
+
+        let progress = progress.lock().unwrap();
+        assert_eq!(progress.len(), 2);
+        assert_eq!(progress[0].percent, 25.0);
+        assert_eq!(progress[0].phase_opt, Some(OperationPhase::Downloading));
+        assert_eq!(progress[0].package_opt.as_deref(), Some("example"));
+        assert_eq!(progress[0].bytes_total, 4);
+        assert_eq!(progress[1].percent, 75.0);
+        assert_eq!(progress[1].phase_opt, Some(OperationPhase::Installing));
+    }
+
+    #[test]
+    fn error_kinds() {
+        let error = |kind: &str| -> BackendError {
+            serde_json::from_value::<ResponseError>(serde_json::json!({
+                "code": 1,
+                "message": "failed",
+                "data": { "kind": kind },
+            }))
+            .unwrap()
+            .into()
+        };
+        let message = "failed (code 1)".to_string();
+        for (kind, expected) in [
+            ("not_supported", BackendError::NotSupported(message.clone())),
+            ("auth_cancelled", BackendError::AuthCancelled),
+            (
+                "network_unavailable",
+                BackendError::NetworkUnavailable(message.clone()),
+            ),
+            (
+                "package_not_found",
+                BackendError::PackageNotFound(message.clone()),
+            ),
+            (
+                "dependency_conflict",
+                BackendError::DependencyConflict(message.clone()),
+            ),
+            ("disk_full", BackendError::DiskFull(message.clone())),
+            ("cancelled", BackendError::Cancelled),
+            ("unknown", BackendError::Other(message.clone())),
+        ] {
+            assert_eq!(error(kind), expected, "kind {kind}");
+        }
+
+        // Errors without data are shown as is
+        let no_data: ResponseError =
+            serde_json::from_value(serde_json::json!({ "code": 2, "message": "failed" })).unwrap();
+        assert_eq!(
+            BackendError::from(no_data),
+            BackendError::Other("failed (code 2)".to_string())
+        );
+
+        // The kind survives the plugin process and boxed errors
+        let dir = TempDir::new().unwrap();
+        let external = plugin(
+            &dir,
+            r#"echo '{"jsonrpc":"2.0","id":1,"error":{"code":1,"message":"failed","data":{"kind":"network_unavailable"}}}'"#,
+        );
+        assert_eq!(
+            external.operation(&install(), &CancelToken::new(), Box::new(|_| {})),
+            Err(BackendError::NetworkUnavailable(message))
+        );
+    }
+
+    #[test]
+    fn exit_timeout() {
+        let dir = TempDir::new().unwrap();
+        let external = plugin(
+            &dir,
+            r#"echo '{"jsonrpc":"2.0","id":1,"result":null}'
+exec sleep 60"#,
+        );
+        let start = Instant::now();
+        assert_eq!(
+            external.operation(&install(), &CancelToken::new(), Box::new(|_| {})),
+            Ok(())
+        );
+        let elapsed = start.elapsed();
+        assert!(elapsed >= EXIT_TIMEOUT, "killed early after {elapsed:?}");
+        assert!(elapsed < EXIT_TIMEOUT * 4, "not killed after {elapsed:?}");
+    }
+}
@@ -0,0 +1,503 @@
+use cosmic::widget;
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+    error::Error,
+    ffi::OsString,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use super::{Backend, BackendError, CancelToken, Capabilities, Package};
+use crate::{
+    AppId, AppInfo, AppstreamCache, Operation, OperationKind, OperationPhase, OperationProgress,
+};
+
+/// Directory containing appstream fixtures, `installed` and `updates` lists, and an `icons` directory
+pub const DIR_ENV: &str = "COSMIC_STORE_FAKE_DIR";
+/// Comma separated list of app IDs whose operations will fail, or `*` to fail all operations
+pub const FAIL_ENV: &str = "COSMIC_STORE_FAKE_FAIL";
+/// Delay in milliseconds between progress steps
+pub const DELAY_ENV: &str = "COSMIC_STORE_FAKE_DELAY_MS";
+
+const DEFAULT_DELAY_MS: u64 = 50;
+const PROGRESS_STEPS: u32 = 10;
+// Simulated download size of install and update operations
+const DOWNLOAD_BYTES: u64 = 10_000_000;
+
+#[derive(Debug, Default)]
+struct FakeState {
+    // App ID and version
+    installed: HashMap<AppId, String>,
+    updates: HashMap<AppId, String>,
+    failures: HashSet<String>,
+}
+
+/// In-memory backend for testing without flatpak or PackageKit
+#[derive(Debug)]
+pub struct Fake {
+    dir: PathBuf,
+    delay: Duration,
+    appstream_caches: Vec<AppstreamCache>,
+    state: Mutex<FakeState>,
+}
+
+impl Fake {
+    /// Create a fake backend from the directory in `COSMIC_STORE_FAKE_DIR`
+    pub fn from_env(locale: &str) -> Result<Self, Box<dyn Error>> {
+        Self::from_vars(locale, |name| env::var_os(name))
+    }
+
+    /// Create a fake backend from variables looked up by name, as `from_env` does
+    pub fn from_vars(
+        locale: &str,
+        var: impl Fn(&str) -> Option<OsString>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let dir = var(DIR_ENV).ok_or_else(|| format!("{DIR_ENV} not set"))?;
+        let mut fake = Self::new(Path::new(&dir), locale)?;
+        if let Some(delay_ms) = var(DELAY_ENV).and_then(|x| x.to_str()?.parse().ok()) {
+            fake.delay = Duration::from_millis(delay_ms);
+        }
+        if let Some(failures) = var(FAIL_ENV) {
+            for id in failures
+                .to_string_lossy()
+                .split(',')
+                .map(str::trim)
+                .filter(|x| !x.is_empty())
+            {
+                fake.fail(id);
+            }
+        }
+        Ok(fake)
+    }
+
+    pub fn new(dir: &Path, locale: &str) -> Result<Self, Box<dyn Error>> {
+        let mut paths = Vec::new();
+        for entry_res in fs::read_dir(dir)? {
+            let path = entry_res?.path();
+            let file_name = path
+                .file_name()
+                .map(|x| x.to_string_lossy())
+                .unwrap_or_default();
+            if [".xml", ".xml.gz", ".yml", ".yml.gz"]
+                .iter()
+                .any(|ext| file_name.ends_with(ext))
+            {
+                paths.push(path);
+            }
+        }
+        // Sort for stable order of duplicate infos
+        paths.sort();
+
+        let mut icons_paths = Vec::new();
+        let icons_path = dir.join("icons");
+        if icons_path.is_dir() {
+            icons_paths.push(icons_path.to_string_lossy().to_string());
+        }
+
+        let mut state = FakeState::default();
+        read_list(&dir.join("installed"), &mut state.installed)?;
+        read_list(&dir.join("updates"), &mut state.updates)?;
+
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            delay: Duration::from_millis(DEFAULT_DELAY_MS),
+            appstream_caches: vec![AppstreamCache::new(
+                "fake".to_string(),
+                "Fake".to_string(),
+                paths,
+                icons_paths,
+                locale,
+            )],
+            state: Mutex::new(state),
+        })
+    }
+
+    /// Make operations on the specified app ID fail, `*` makes all operations fail
+    pub fn fail(&self, id: &str) {
+        self.state.lock().unwrap().failures.insert(id.to_string());
+    }
+
+    fn packages(&self, versions: &HashMap<AppId, String>) -> Vec<Package> {
+        let appstream_cache = &self.appstream_caches[0];
+        let mut packages = Vec::with_capacity(versions.len());
+        for (id, version) in versions.iter() {
+            match appstream_cache.infos.get(id) {
+                Some(info) => packages.push(Package {
+                    id: id.clone(),
+                    icon: appstream_cache.icon(info),
+                    info: info.clone(),
+                    version: version.clone(),
+                    // Versions are used as revisions to downgrade to
+                    extra: HashMap::from([("revision".to_string(), version.clone())]),
+                }),
+                None => {
+                    log::warn!("fake: no fixture for {:?} in {:?}", id, self.dir);
+                    packages.push(Package {
+                        id: id.clone(),
+                        icon: widget::icon::from_name("package-x-generic")
+                            .size(128)
+                            .handle(),
+                        info: Arc::new(AppInfo {
+                            source_id: appstream_cache.source_id.clone(),
+                            source_name: appstream_cache.source_name.clone(),
+                            name: id.raw().to_string(),
+                            ..Default::default()
+                        }),
+                        version: version.clone(),
+                        extra: HashMap::from([("revision".to_string(), version.clone())]),
+                    });
+                }
+            }
+        }
+        packages
+    }
+}
+
+// Each line is an app ID, optionally followed by whitespace and a version
+fn read_list(path: &Path, list: &mut HashMap<AppId, String>) -> Result<(), Box<dyn Error>> {
+    if !path.is_file() {
+        return Ok(());
+    }
+    for line in fs::read_to_string(path)?.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (id, version) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        list.insert(AppId::new(id), version.trim().to_string());
+    }
+    Ok(())
+}
+
+impl Backend for Fake {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::INSTALL
+            | Capabilities::UNINSTALL
+            | Capabilities::UPDATE
+            | Capabilities::PURGE_DATA
+            | Capabilities::CANCEL
+            | Capabilities::DOWNGRADE
+    }
+
+    fn load_caches(&mut self, _refresh: bool) -> Result<(), Box<dyn Error>> {
+        // Fixtures are always loaded from the originals so the on-disk cache is not touched
+        for appstream_cache in self.appstream_caches.iter_mut() {
+            appstream_cache.load_original();
+        }
+        Ok(())
+    }
+
+    fn info_caches(&self) -> &[AppstreamCache] {
+        &self.appstream_caches
+    }
+
+    fn installed(&self) -> Result<Vec<Package>, Box<dyn Error>> {
+        let state = self.state.lock().unwrap();
+        Ok(self.packages(&state.installed))
+    }
+
+    fn updates(&self) -> Result<Vec<Package>, Box<dyn Error>> {
+        let state = self.state.lock().unwrap();
+        Ok(self.packages(&state.updates))
+    }
+
+    fn file_packages(&self, _path: &str) -> Result<Vec<Package>, Box<dyn Error>> {
+        Ok(Vec::new())
+    }
+
+    fn operation(
+        &self,
+        op: &Operation,
+        cancel_token: &CancelToken,
+        mut f: Box<dyn FnMut(OperationProgress) + 'static>,
+    ) -> Result<(), BackendError> {
+        let failed_id = {
+            let state = self.state.lock().unwrap();
+            if state.failures.contains("*") {
+                Some("*")
+            } else {
+                op.package_ids
+                    .iter()
+                    .find(|id| state.failures.contains(id.raw()))
+                    .map(|id| id.raw())
+            }
+        };
+
+        let downloads = matches!(
+            op.kind,
+            OperationKind::Install | OperationKind::Update | OperationKind::Downgrade(_)
+        );
+        let items = op.package_ids.len() as u32;
+
+        // Injected failures happen halfway through to exercise partial progress
+        for step in 0..=PROGRESS_STEPS {
+            if failed_id.is_some() && step * 2 > PROGRESS_STEPS {
+                break;
+            }
+            if cancel_token.is_cancelled() {
+                return Err(BackendError::Cancelled);
+            }
+            // Downloads happen in the first half, then changes are applied
+            let download_step = (step * 2).min(PROGRESS_STEPS) as u64;
+            let phase = if step == 0 {
+                OperationPhase::Resolving
+            } else if !downloads {
+                OperationPhase::Removing
+            } else if step * 2 <= PROGRESS_STEPS {
+                OperationPhase::Downloading
+            } else {
+                OperationPhase::Installing
+            };
+            // Packages are processed one after another
+            let item_percents: Vec<f32> = (0..items)
+                .map(|i| ((step * items) as f32 / PROGRESS_STEPS as f32 - i as f32) * 100.0)
+                .map(|percent| percent.clamp(0.0, 100.0))
+                .collect();
+            let package_opt = op
+                .package_ids
+                .get((step * items / PROGRESS_STEPS).min(items.saturating_sub(1)) as usize)
+                .map(|id| id.raw().to_string());
+            f(OperationProgress {
+                percent: (step * 100 / PROGRESS_STEPS) as f32,
+                phase_opt: Some(phase),
+                package_opt,
+                bytes_downloaded: if downloads {
+                    DOWNLOAD_BYTES * download_step / PROGRESS_STEPS as u64
+                } else {
+                    0
+                },
+                bytes_total: if downloads { DOWNLOAD_BYTES } else { 0 },
+                status: String::new(),
+                item_percents,
+            });
+            thread::sleep(self.delay);
+        }
+        if let Some(id) = failed_id {
+            return Err(format!("fake: injected failure for {}", id).into());
+        }
+
+        let mut state = self.state.lock().unwrap();
+        for (i, (id, info)) in op.package_ids.iter().zip(op.infos.iter()).enumerate() {
+            match &op.kind {
+                OperationKind::Install => {
+                    let version = info
+                        .releases
+                        .first()
+                        .map_or_else(String::new, |release| release.version.clone());
+                    state.installed.insert(id.clone(), version);
+                }
+                OperationKind::Uninstall { .. } => {
+                    state.installed.remove(id);
+                    state.updates.remove(id);
+                }
+                OperationKind::Update => {
+                    if let Some(version) = state.updates.remove(id) {
+                        state.installed.insert(id.clone(), version);
+                    }
+                }
+                OperationKind::Downgrade(versions) => {
+                    if let Some(version) = versions.get(i) {
+                        state.installed.insert(id.clone(), version.clone());
+                    }
+                }
+                OperationKind::RepositoryAdd(_)
+                | OperationKind::RepositoryRemove(_, _)
+                | OperationKind::Deployment(_) => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::backend::BackendName;
+    use tempfile::TempDir;
+
+    const FIXTURE: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<components version="0.14" origin="fake">
+  <component type="desktop-application">
+    <id>com.example.Hello</id>
+    <name>Hello</name>
+    <summary>Says hello</summary>
+    <categories>
+      <category>Utility</category>
+    </categories>
+    <releases>
+      <release version="2.0" timestamp="1700000000"/>
+    </releases>
+  </component>
+  <component type="desktop-application">
+    <id>com.example.Installed</id>
+    <name>Installed</name>
+    <summary>Already installed</summary>
+  </component>
+</components>
+"#;
+
+    /// Fixture directory with `com.example.Hello` available and `com.example.Installed`
+    /// installed at 1.0 with an update to 1.1, removed when dropped
+    pub fn fixture_dir() -> TempDir {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("fixture.xml"), FIXTURE).unwrap();
+        fs::write(dir.path().join("installed"), "com.example.Installed 1.0\n").unwrap();
+        fs::write(dir.path().join("updates"), "com.example.Installed 1.1\n").unwrap();
+        dir
+    }
+
+    /// Fake backend for the fixture directory, configured like `from_env` with extra variables
+    pub fn backend(dir: &TempDir, vars: &[(&str, &str)]) -> Fake {
+        let mut vars: HashMap<&str, &str> = vars.iter().copied().collect();
+        let dir_str = dir.path().to_str().unwrap();
+        vars.insert(DIR_ENV, dir_str);
+        vars.entry(DELAY_ENV).or_insert("0");
+        let mut fake = Fake::from_vars("en", |name| vars.get(name).map(OsString::from)).unwrap();
+        fake.load_caches(false).unwrap();
+        fake
+    }
+
+    fn operation(kind: OperationKind, id: &str) -> Operation {
+        Operation {
+            kind,
+            backend_name: BackendName::Fake,
+            package_ids: vec![AppId::new(id)],
+            infos: vec![Arc::new(AppInfo::default())],
+        }
+    }
+
+    fn versions(packages: Vec<Package>) -> HashMap<String, String> {
+        packages
+            .into_iter()
+            .map(|package| (package.id.raw().to_string(), package.version))
+            .collect()
+    }
+
+    fn run(fake: &Fake, op: &Operation, cancel_token: &CancelToken) -> Result<(), BackendError> {
+        fake.operation(op, cancel_token, Box::new(|_| {}))
+    }
+
+    #[test]
+    fn loads_fixtures() {
+        let dir = fixture_dir();
+        let fake = backend(&dir, &[]);
+        assert!(
+            fake.info_caches()[0]
+                .infos
+                .contains_key(&AppId::new("com.example.Hello"))
+        );
+        assert_eq!(
+            versions(fake.installed().unwrap()),
+            HashMap::from([("com.example.Installed".to_string(), "1.0".to_string())])
+        );
+        assert_eq!(fake.updates().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn reads_variables() {
+        let dir = fixture_dir();
+        assert!(Fake::from_vars("en", |_| None).is_err());
+
+        let fake = backend(
+            &dir,
+            &[
+                (DELAY_ENV, "7"),
+                (FAIL_ENV, "com.example.Hello, ,com.example.Other"),
+            ],
+        );
+        assert_eq!(fake.delay, Duration::from_millis(7));
+        assert_eq!(
+            fake.state.lock().unwrap().failures,
+            HashSet::from([
+                "com.example.Hello".to_string(),
+                "com.example.Other".to_string()
+            ])
+        );
+
+        // Invalid delays keep the default
+        let fake = backend(&dir, &[(DELAY_ENV, "soon")]);
+        assert_eq!(fake.delay, Duration::from_millis(DEFAULT_DELAY_MS));
+    }
+
+    #[test]
+    fn operations_change_state() {
+        let dir = fixture_dir();
+        let fake = backend(&dir, &[]);
+        let cancel_token = CancelToken::new();
+
+        run(
+            &fake,
+            &operation(OperationKind::Install, "com.example.Hello"),
+            &cancel_token,
+        )
+        .unwrap();
+        assert!(versions(fake.installed().unwrap()).contains_key("com.example.Hello"));
+
+        run(
+            &fake,
+            &operation(OperationKind::Update, "com.example.Installed"),
+            &cancel_token,
+        )
+        .unwrap();
+        assert_eq!(
+            versions(fake.installed().unwrap()).get("com.example.Installed"),
+            Some(&"1.1".to_string())
+        );
+        assert!(fake.updates().unwrap().is_empty());
+
+        run(
+            &fake,
+            &operation(
+                OperationKind::Uninstall { purge_data: false },
+                "com.example.Installed",
+            ),
+            &cancel_token,
+        )
+        .unwrap();
+        assert_eq!(
+            versions(fake.installed().unwrap())
+                .into_keys()
+                .collect::<Vec<_>>(),
+            vec!["com.example.Hello".to_string()]
+        );
+    }
+
+    #[test]
+    fn failure_stops_at_half_progress() {
+        let dir = fixture_dir();
+        let fake = backend(&dir, &[(FAIL_ENV, "*")]);
+        let percents = Arc::new(Mutex::new(Vec::new()));
+        let result = {
+            let percents = percents.clone();
+            fake.operation(
+                &operation(OperationKind::Install, "com.example.Hello"),
+                &CancelToken::new(),
+                Box::new(move |progress| percents.lock().unwrap().push(progress.percent)),
+            )
+        };
+        assert!(result.is_err());
+        assert_eq!(percents.lock().unwrap().last(), Some(&50.0));
+        assert!(!versions(fake.installed().unwrap()).contains_key("com.example.Hello"));
+    }
+
+    #[test]
+    fn cancelled() {
+        let dir = fixture_dir();
+        let fake = backend(&dir, &[]);
+        let cancel_token = CancelToken::new();
+        cancel_token.cancel();
+        assert_eq!(
+            run(
+                &fake,
+                &operation(OperationKind::Install, "com.example.Hello"),
+                &cancel_token,
+            ),
+            Err(BackendError::Cancelled)
+        );
+        assert!(!versions(fake.installed().unwrap()).contains_key("com.example.Hello"));
+    }
+}
@@ -1,9 +1,16 @@
+use appstream::{Component, xmltree};
 use cosmic::widget;
+use flate2::read::GzDecoder;
 use futures::StreamExt;
-use libflatpak::{Installation, Ref, Remote, Transaction, gio::Cancellable, glib, prelude::*};
+use libflatpak::{
+    BundleRef, Installation, Ref, RefKind, Remote, Transaction, TransactionOperationType,
+    gio::{self, Cancellable},
+    glib,
+    prelude::*,
+};
 use std::{
     cell::{Cell, RefCell},
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     error::Error,
     fmt::Write,
     fs, ptr,
@@ -11,14 +18,16 @@ use std::{
     sync::Arc,
 };
 
-use super::{Backend, Package};
+use super::{Backend, BackendError, BackendName, CancelToken, Capabilities, Package, Runtime};
 use crate::{
-    AppId, AppInfo, AppUrl, AppstreamCache, Operation, OperationKind, RepositoryRemoveError,
+    AppId, AppInfo, AppUrl, AppstreamCache, Operation, OperationKind, OperationPhase,
+    OperationProgress, Permissions, RepositoryRemoveError,
 };
 
 #[derive(Debug)]
 pub struct Flatpak {
     user: bool,
+    locale: String,
     appstream_caches: Vec<AppstreamCache>,
 }
 
@@ -33,15 +42,16 @@ impl Flatpak {
 
     fn source_id(&self, remote_name: &str) -> String {
         if self.user {
-            remote_name.to_string()
+            BackendName::FlatpakUser.flatpak_source_id(remote_name)
         } else {
-            format!("{remote_name} (system)")
+            BackendName::FlatpakSystem.flatpak_source_id(remote_name)
         }
     }
 
     pub fn new(user: bool, locale: &str) -> Result<Self, Box<dyn Error>> {
         let mut this = Self {
             user,
+            locale: locale.to_string(),
             appstream_caches: Vec::new(),
         };
 
@@ -154,11 +164,22 @@ impl Flatpak {
             if let Some(branch) = r.branch() {
                 extra.insert("branch".to_string(), branch.to_string());
             }
+            if let Some(commit) = r.commit() {
+                extra.insert("revision".to_string(), commit.to_string());
+            }
+
+            // Appstream only lists the default branch, while another may be installed
+            let mut info = info.clone();
+            if let Some(ref_str) = r.format_ref().map(|x| x.to_string())
+                && !info.flatpak_refs.contains(&ref_str)
+            {
+                Arc::make_mut(&mut info).flatpak_refs = vec![ref_str];
+            }
 
             return Some(Package {
                 id: id.clone(),
-                icon: appstream_cache.icon(info),
-                info: info.clone(),
+                icon: appstream_cache.icon(&info),
+                info,
                 version: r.appdata_version().unwrap_or_default().to_string(),
                 extra,
             });
@@ -225,9 +246,92 @@ impl Flatpak {
 
         packages
     }
+
+    fn bundle_packages(&self, path: &str) -> Result<Vec<Package>, Box<dyn Error>> {
+        let bundle = BundleRef::new(&gio::File::for_path(path))?;
+        let name = bundle
+            .name()
+            .ok_or_else(|| format!("flatpak bundle {path:?} missing name"))?;
+
+        // Check if already installed
+        let inst = self.installation()?;
+        if let Ok(r) = inst.installed_ref(
+            bundle.kind(),
+            &name,
+            bundle.arch().as_deref(),
+            bundle.branch().as_deref(),
+            Cancellable::NONE,
+        ) {
+            return Ok(self.refs_to_packages(vec![r]));
+        }
+
+        // Bundles with an origin are updated from it, otherwise they cannot be updated
+        let url = bundle
+            .origin()
+            .map_or_else(|| path.to_string(), |x| x.to_string());
+        let source_id = self.source_id(&url);
+
+        // Bundles may embed compressed appstream and icons for previews
+        let component_opt = bundle.appstream().and_then(|bytes| {
+            let e = xmltree::Element::parse(GzDecoder::new(&*bytes))
+                .map_err(|err| log::warn!("failed to parse appstream of {:?}: {}", path, err))
+                .ok()?;
+            e.children.iter().find_map(|node| match node {
+                xmltree::XMLNode::Element(e) if &*e.name == "component" => {
+                    Component::try_from(e).ok()
+                }
+                _ => None,
+            })
+        });
+        let mut info = match component_opt {
+            Some(component) => {
+                AppInfo::new(&source_id, &source_id, None, component, &self.locale, 0)
+            }
+            None => AppInfo {
+                source_id: source_id.clone(),
+                source_name: source_id.clone(),
+                name: name.to_string(),
+                ..Default::default()
+            },
+        };
+        info.flatpak_refs = vec![bundle.format_ref().unwrap_or_default().to_string()];
+        info.package_paths = vec![path.to_string()];
+
+        let icon = match bundle.icon(128) {
+            Some(bytes) => widget::icon::from_raster_bytes(bytes.to_vec()),
+            None => widget::icon::from_name("package-x-generic")
+                .size(128)
+                .handle(),
+        };
+
+        let mut extra = HashMap::new();
+        if let Some(branch) = bundle.branch() {
+            extra.insert("branch".to_string(), branch.to_string());
+        }
+
+        Ok(vec![Package {
+            id: AppId::new(&name),
+            icon,
+            info: Arc::new(info),
+            version: String::new(),
+            extra,
+        }])
+    }
 }
 
 impl Backend for Flatpak {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::INSTALL
+            | Capabilities::UNINSTALL
+            | Capabilities::UPDATE
+            | Capabilities::REPOSITORIES
+            | Capabilities::PURGE_DATA
+            | Capabilities::CANCEL
+            | Capabilities::DOWNGRADE
+            | Capabilities::RUNTIMES
+            | Capabilities::FILE_INSTALL
+    }
+
     fn load_caches(&mut self, refresh: bool) -> Result<(), Box<dyn Error>> {
         let inst = self.installation()?;
         let appstream_caches = std::mem::take(&mut self.appstream_caches);
@@ -299,13 +403,161 @@ impl Backend for Flatpak {
         Ok(packages)
     }
 
+    fn runtimes(&self) -> Result<Vec<Runtime>, Box<dyn Error>> {
+        let inst = self.installation()?;
+        let refs = inst.list_installed_refs(Cancellable::NONE)?;
+
+        // Unused refs are found the same way as `flatpak uninstall --unused`, which keeps pinned runtimes
+        let unused: HashSet<String> = inst
+            .list_unused_refs(None, Cancellable::NONE)?
+            .iter()
+            .filter_map(|r| r.format_ref().map(|x| x.to_string()))
+            .collect();
+
+        // Find what each ref uses and extends from its metadata
+        let mut names = HashMap::new();
+        let mut runtime_users: HashMap<String, Vec<String>> = HashMap::new();
+        let mut extension_of = HashMap::new();
+        for r in refs.iter() {
+            let Some(ref_str) = r.format_ref().map(|x| x.to_string()) else {
+                continue;
+            };
+            let name = r
+                .appdata_name()
+                .or_else(|| r.name())
+                .unwrap_or_default()
+                .to_string();
+            let metadata = glib::KeyFile::new();
+            if let Err(err) = r
+                .load_metadata(Cancellable::NONE)
+                .and_then(|bytes| metadata.load_from_bytes(&bytes, glib::KeyFileFlags::NONE))
+            {
+                log::warn!("failed to load metadata of {}: {}", ref_str, err);
+            }
+            if r.kind() == RefKind::App
+                && let Ok(runtime) = metadata.string("Application", "runtime")
+            {
+                runtime_users
+                    .entry(format!("runtime/{runtime}"))
+                    .or_default()
+                    .push(name.clone());
+            }
+            if let Ok(parent) = metadata.string("ExtensionOf", "ref") {
+                extension_of.insert(ref_str.clone(), parent.to_string());
+            }
+            names.insert(ref_str, name);
+        }
+
+        let mut runtimes = Vec::new();
+        for r in refs.iter() {
+            if r.kind() != RefKind::Runtime {
+                continue;
+            }
+            let Some(ref_str) = r.format_ref().map(|x| x.to_string()) else {
+                continue;
+            };
+            let mut used_by = runtime_users.remove(&ref_str).unwrap_or_default();
+            used_by.sort_by(|a, b| crate::LANGUAGE_SORTER.compare(a, b));
+            runtimes.push(Runtime {
+                name: r.name().unwrap_or_default().to_string(),
+                branch: r.branch().unwrap_or_default().to_string(),
+                origin: r.origin().unwrap_or_default().to_string(),
+                size: r.installed_size(),
+                extension_of: extension_of
+                    .get(&ref_str)
+                    .map(|parent| names.get(parent).unwrap_or(parent).clone()),
+                used_by,
+                unused: unused.contains(&ref_str),
+                id: ref_str,
+            });
+        }
+        runtimes.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(runtimes)
+    }
+
+    fn permissions(&self, info: &AppInfo) -> Result<Option<Permissions>, Box<dyn Error>> {
+        // Only apps have a sandbox
+        let Some(r) = info
+            .flatpak_refs
+            .iter()
+            .filter_map(|r_str| Ref::parse(r_str).ok())
+            .find(|r| r.kind() == RefKind::App)
+        else {
+            return Ok(None);
+        };
+        let inst = self.installation()?;
+        let installed_opt = inst
+            .installed_ref(
+                r.kind(),
+                &r.name().unwrap_or_default(),
+                r.arch().as_deref(),
+                r.branch().as_deref(),
+                Cancellable::NONE,
+            )
+            .ok()
+            .filter(|installed| {
+                installed
+                    .origin()
+                    .is_some_and(|origin| self.source_id(&origin) == info.source_id)
+            });
+        let metadata = match installed_opt {
+            Some(installed) => installed.load_metadata(Cancellable::NONE)?,
+            None => {
+                // Apps that are not installed from this remote use the metadata of the remote ref
+                let remote_name = inst
+                    .list_remotes(Cancellable::NONE)?
+                    .into_iter()
+                    .filter_map(|remote| remote.name())
+                    .find(|name| self.source_id(name) == info.source_id)
+                    .ok_or_else(|| format!("failed to find remote {}", info.source_id))?;
+                inst.fetch_remote_metadata_sync(&remote_name, &r, Cancellable::NONE)?
+            }
+        };
+        Ok(Some(Permissions::new(
+            r.name().unwrap_or_default().to_string(),
+            &String::from_utf8_lossy(&metadata),
+        )))
+    }
+
+    fn app_refs(&self, info: &AppInfo) -> Result<Vec<String>, Box<dyn Error>> {
+        let Some(name) = info
+            .flatpak_refs
+            .iter()
+            .filter_map(|r_str| Ref::parse(r_str).ok())
+            .find(|r| r.kind() == RefKind::App)
+            .and_then(|r| r.name())
+        else {
+            return Ok(Vec::new());
+        };
+        let inst = self.installation()?;
+        let Some(remote_name) = inst
+            .list_remotes(Cancellable::NONE)?
+            .into_iter()
+            .filter_map(|remote| remote.name())
+            .find(|name| self.source_id(name) == info.source_id)
+        else {
+            return Ok(Vec::new());
+        };
+        // Other arches are only offered if they can run here, such as i386 on x86_64
+        let arches = libflatpak::supported_arches();
+        let mut refs: Vec<String> = inst
+            .list_remote_refs_sync(&remote_name, Cancellable::NONE)?
+            .into_iter()
+            .filter(|r| {
+                r.kind() == RefKind::App
+                    && r.name() == Some(name.clone())
+                    && r.arch().is_some_and(|arch| arches.contains(&arch))
+            })
+            .filter_map(|r| r.format_ref().map(|x| x.to_string()))
+            .collect();
+        refs.sort();
+        refs.dedup();
+        Ok(refs)
+    }
+
     fn file_packages(&self, path: &str) -> Result<Vec<Package>, Box<dyn Error>> {
-        if !self.user {
-            return Err(
-                "flatpak backend only supports installing files with user installation"
-                    .to_string()
-                    .into(),
-            );
+        if path.ends_with(".flatpak") {
+            return self.bundle_packages(path);
         }
 
         if !path.ends_with(".flatpakref") {
@@ -324,8 +576,8 @@ impl Backend for Flatpak {
         let url =
             get_attr("Url").ok_or_else(|| format!("flatpak ref {path:?} missing Url attribute"))?;
 
-        let mut source_id = url.to_string();
-        let mut source_name = url.to_string();
+        let mut source_id = self.source_id(url);
+        let mut source_name = source_id.clone();
         let inst = self.installation()?;
         for remote in inst.list_remotes(Cancellable::NONE)? {
             if remote.url().is_some_and(|u| u == *url) {
@@ -379,12 +631,18 @@ impl Backend for Flatpak {
     fn operation(
         &self,
         op: &Operation,
-        callback: Box<dyn FnMut(f32) + 'static>,
-    ) -> Result<(), Box<dyn Error>> {
+        cancel_token: &CancelToken,
+        callback: Box<dyn FnMut(OperationProgress) + 'static>,
+    ) -> Result<(), BackendError> {
         let callback = Rc::new(RefCell::new(callback));
+        let cancellable = Cancellable::new();
+        {
+            let cancellable = cancellable.clone();
+            cancel_token.on_cancel(move || cancellable.cancel());
+        }
         let inst = self.installation()?;
         let total_ops = Rc::new(Cell::new(0));
-        let tx = Transaction::for_installation(&inst, Cancellable::NONE)?;
+        let tx = Transaction::for_installation(&inst, Some(&cancellable))?;
         {
             let total_ops = total_ops.clone();
             tx.connect_ready(move |tx| {
@@ -393,6 +651,13 @@ impl Backend for Flatpak {
             });
         }
         let started_ops = Rc::new(Cell::new(0));
+        // Refs of each package, to report progress per package
+        let item_refs: Vec<Vec<String>> = op
+            .infos
+            .iter()
+            .map(|info| info.flatpak_refs.clone())
+            .collect();
+        let item_percents = Rc::new(RefCell::new(vec![0.0; op.infos.len()]));
         tx.connect_new_operation(move |_, op, progress| {
             let current_op = started_ops.get();
             started_ops.set(current_op + 1);
@@ -404,6 +669,14 @@ impl Backend for Flatpak {
                 op.get_ref()
             );
             let callback = callback.clone();
+            let uninstall = op.operation_type() == TransactionOperationType::Uninstall;
+            let package_opt = op.get_ref().map(|r| r.to_string());
+            // Runtimes and other dependencies do not belong to a package
+            let item_opt = package_opt
+                .as_ref()
+                .and_then(|r| item_refs.iter().position(|refs| refs.contains(r)));
+            let item_percents = item_percents.clone();
+            let bytes_total = op.download_size();
             progress.connect_changed(move |progress| {
                 log::info!(
                     "{}: {}%",
@@ -412,8 +685,29 @@ impl Backend for Flatpak {
                 );
                 let op_progress = (progress.progress() as f32) / 100.0;
                 let total_progress = ((current_op as f32) + op_progress) * progress_per_op;
+                let bytes_downloaded = progress.bytes_transferred();
+                let phase = if progress.is_estimating() {
+                    OperationPhase::Resolving
+                } else if uninstall {
+                    OperationPhase::Removing
+                } else if bytes_downloaded < bytes_total {
+                    OperationPhase::Downloading
+                } else {
+                    OperationPhase::Installing
+                };
+                if let Some(item) = item_opt {
+                    item_percents.borrow_mut()[item] = progress.progress() as f32;
+                }
                 let mut callback = callback.borrow_mut();
-                callback(total_progress)
+                callback(OperationProgress {
+                    percent: total_progress,
+                    phase_opt: Some(phase),
+                    package_opt: package_opt.clone(),
+                    bytes_downloaded,
+                    bytes_total,
+                    status: progress.status().unwrap_or_default().to_string(),
+                    item_percents: item_percents.borrow().clone(),
+                })
             });
         });
         match &op.kind {
@@ -421,11 +715,16 @@ impl Backend for Flatpak {
                 for info in op.infos.iter() {
                     if !info.package_paths.is_empty() {
                         for package_path in info.package_paths.iter() {
-                            log::info!("installing flatpak ref {:?}", package_path);
-                            //TODO: keep package data in memory?
-                            let data = fs::read(package_path)?;
-                            let bytes = glib::Bytes::from_owned(data);
-                            tx.add_install_flatpakref(&bytes)?;
+                            if package_path.ends_with(".flatpak") {
+                                log::info!("installing flatpak bundle {:?}", package_path);
+                                tx.add_install_bundle(&gio::File::for_path(package_path), None)?;
+                            } else {
+                                log::info!("installing flatpak ref {:?}", package_path);
+                                //TODO: keep package data in memory?
+                                let data = fs::read(package_path)?;
+                                let bytes = glib::Bytes::from_owned(data);
+                                tx.add_install_flatpakref(&bytes)?;
+                            }
                         }
                     } else {
                         for r_str in info.flatpak_refs.iter() {
@@ -436,7 +735,7 @@ impl Backend for Flatpak {
                                     continue;
                                 }
                             };
-                            for remote in inst.list_remotes(Cancellable::NONE)? {
+                            for remote in inst.list_remotes(Some(&cancellable))? {
                                 let Some(remote_name) = remote.name() else {
                                     continue;
                                 };
@@ -449,7 +748,7 @@ impl Backend for Flatpak {
                                     &r.name().unwrap_or_default(),
                                     r.arch().as_deref(),
                                     r.branch().as_deref(),
-                                    Cancellable::NONE,
+                                    Some(&cancellable),
                                 ) {
                                     Ok(_) => {}
                                     Err(err) => {
@@ -493,7 +792,7 @@ impl Backend for Flatpak {
                             &r.name().unwrap_or_default(),
                             r.arch().as_deref(),
                             r.branch().as_deref(),
-                            Cancellable::NONE,
+                            Some(&cancellable),
                         ) {
                             Ok(_) => {}
                             Err(err) => {
@@ -516,7 +815,7 @@ impl Backend for Flatpak {
                     }
                 }
 
-                tx.run(Cancellable::NONE)?;
+                tx.run(Some(&cancellable))?;
 
                 // After successful uninstall, delete user data if requested
                 if *purge_data {
@@ -565,7 +864,7 @@ impl Backend for Flatpak {
                             &id,
                             r.arch().as_deref(),
                             r.branch().as_deref(),
-                            Cancellable::NONE,
+                            Some(&cancellable),
                         ) {
                             Ok(inst_r) => {
                                 if let Some(eol_rebase) = inst_r.eol_rebase() {
@@ -619,6 +918,33 @@ impl Backend for Flatpak {
                     }
                 }
             }
+            OperationKind::Downgrade(commits) => {
+                for (info, commit) in op.infos.iter().zip(commits.iter()) {
+                    for r_str in info.flatpak_refs.iter() {
+                        let r = match Ref::parse(r_str) {
+                            Ok(ok) => ok,
+                            Err(err) => {
+                                log::warn!("failed to parse flatpak ref {}: {}", r_str, err);
+                                continue;
+                            }
+                        };
+                        let id = r.name().unwrap_or_default();
+                        // Only the installed refs can be returned to a previous commit
+                        if let Err(err) = inst.installed_ref(
+                            r.kind(),
+                            &id,
+                            r.arch().as_deref(),
+                            r.branch().as_deref(),
+                            Some(&cancellable),
+                        ) {
+                            log::info!("failed to find {} installed locally: {}", r_str, err);
+                            continue;
+                        }
+                        log::info!("downgrading flatpak {} to commit {}", r_str, commit);
+                        tx.add_update(r_str, &[], Some(commit))?;
+                    }
+                }
+            }
             OperationKind::RepositoryAdd(adds) => {
                 drop(tx);
                 let mut remotes = Vec::with_capacity(adds.len());
@@ -626,13 +952,13 @@ impl Backend for Flatpak {
                     remotes.push(Remote::from_file(&add.id, &glib::Bytes::from(&add.data))?);
                 }
                 for remote in remotes {
-                    inst.add_remote(&remote, true, Cancellable::NONE)?;
+                    inst.add_remote(&remote, true, Some(&cancellable))?;
                 }
                 return Ok(());
             }
             OperationKind::RepositoryRemove(rms, force) => {
                 let mut installed = Vec::new();
-                for r in inst.list_installed_refs(Cancellable::NONE)? {
+                for r in inst.list_installed_refs(Some(&cancellable))? {
                     let Some(origin) = r.origin() else {
                         continue;
                     };
@@ -659,16 +985,21 @@ impl Backend for Flatpak {
                     .into());
                 }
                 if *force {
-                    tx.run(Cancellable::NONE)?;
+                    tx.run(Some(&cancellable))?;
                 }
                 drop(tx);
                 for rm in rms.iter() {
-                    inst.remove_remote(&rm.id, Cancellable::NONE)?;
+                    inst.remove_remote(&rm.id, Some(&cancellable))?;
                 }
                 return Ok(());
             }
+            OperationKind::Deployment(_) => {
+                return Err(BackendError::NotSupported(
+                    "flatpak backend does not support deployments".to_string(),
+                ));
+            }
         }
-        tx.run(Cancellable::NONE)?;
+        tx.run(Some(&cancellable))?;
         Ok(())
     }
 }
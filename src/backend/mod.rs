@@ -1,14 +1,19 @@
 use cosmic::widget;
 use futures::StreamExt;
 use std::{
-    collections::{BTreeMap, HashMap},
+    collections::{BTreeMap, BTreeSet, HashMap},
     error::Error,
-    fmt,
-    sync::Arc,
+    fmt, mem,
+    sync::{
+        Arc, LazyLock, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
     time::Instant,
 };
 
-use crate::{AppId, AppInfo, AppstreamCache, GStreamerCodec, Operation};
+use crate::{
+    AppId, AppInfo, AppstreamCache, GStreamerCodec, Operation, OperationProgress, Permissions,
+};
 
 /// Enum representing the available backend types
 #[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
@@ -18,17 +23,48 @@ pub enum BackendName {
     Packagekit,
     Pkgar,
     RpmOstree,
+    Bootc,
+    Distrobox,
+    Snap,
+    AppImage,
+    Nix,
+    Fake,
+    /// Out-of-process backend, holds the full name such as `external-foo`
+    External(&'static str),
 }
 
 impl BackendName {
+    /// Returns the name of the external backend with the specified plugin name
+    pub fn external(plugin_name: &str) -> Self {
+        // Backend names must be Copy, so external names are interned for the life of the process
+        static NAMES: LazyLock<Mutex<BTreeSet<&'static str>>> =
+            LazyLock::new(|| Mutex::new(BTreeSet::new()));
+
+        let name = format!("external-{plugin_name}");
+        let mut names = NAMES.lock().unwrap();
+        if let Some(interned) = names.get(name.as_str()).copied() {
+            return BackendName::External(interned);
+        }
+        let interned: &'static str = Box::leak(name.into_boxed_str());
+        names.insert(interned);
+        BackendName::External(interned)
+    }
+
     /// Returns the string representation of the backend name
     pub fn as_str(&self) -> &'static str {
         match self {
+            BackendName::Fake => "fake",
             BackendName::FlatpakUser => "flatpak-user",
             BackendName::FlatpakSystem => "flatpak-system",
             BackendName::Packagekit => "packagekit",
             BackendName::Pkgar => "pkgar",
             BackendName::RpmOstree => "rpm-ostree",
+            BackendName::Bootc => "bootc",
+            BackendName::Distrobox => "distrobox",
+            BackendName::Snap => "snap",
+            BackendName::AppImage => "appimage",
+            BackendName::Nix => "nix",
+            BackendName::External(name) => name,
         }
     }
 
@@ -36,6 +72,28 @@ impl BackendName {
     pub fn is_flatpak(&self) -> bool {
         matches!(self, BackendName::FlatpakUser | BackendName::FlatpakSystem)
     }
+
+    /// Source ID of a flatpak remote, system remotes are suffixed to tell them apart
+    pub fn flatpak_source_id(&self, remote_name: &str) -> String {
+        match self {
+            BackendName::FlatpakSystem => format!("{remote_name} (system)"),
+            _ => remote_name.to_string(),
+        }
+    }
+
+    /// The other flatpak installation and the source ID of the same remote in it
+    pub fn flatpak_other_scope(&self, source_id: &str) -> Option<(Self, String)> {
+        match self {
+            BackendName::FlatpakUser => Some((
+                BackendName::FlatpakSystem,
+                BackendName::FlatpakSystem.flatpak_source_id(source_id),
+            )),
+            BackendName::FlatpakSystem => source_id
+                .strip_suffix(" (system)")
+                .map(|remote_name| (BackendName::FlatpakUser, remote_name.to_string())),
+            _ => None,
+        }
+    }
 }
 
 impl fmt::Display for BackendName {
@@ -51,17 +109,47 @@ impl std::str::FromStr for BackendName {
         match s {
             "flatpak-user" => Ok(BackendName::FlatpakUser),
             "flatpak-system" => Ok(BackendName::FlatpakSystem),
+            "fake" => Ok(BackendName::Fake),
             "packagekit" => Ok(BackendName::Packagekit),
             "pkgar" => Ok(BackendName::Pkgar),
             "rpm-ostree" => Ok(BackendName::RpmOstree),
-            _ => Err(format!("unknown backend name: {}", s)),
+            "bootc" => Ok(BackendName::Bootc),
+            "distrobox" => Ok(BackendName::Distrobox),
+            "snap" => Ok(BackendName::Snap),
+            "appimage" => Ok(BackendName::AppImage),
+            "nix" => Ok(BackendName::Nix),
+            _ => match s.strip_prefix("external-") {
+                Some(plugin_name) => Ok(BackendName::external(plugin_name)),
+                None => Err(format!("unknown backend name: {}", s)),
+            },
         }
     }
 }
 
+mod error;
+pub use error::BackendError;
+
+#[cfg(feature = "appimage")]
+mod appimage;
+
+#[cfg(feature = "bootc")]
+mod bootc;
+
+#[cfg(feature = "distrobox")]
+mod distrobox;
+
+#[cfg(feature = "external")]
+mod external;
+
+#[cfg(feature = "fake")]
+pub mod fake;
+
 #[cfg(feature = "flatpak")]
 mod flatpak;
 
+#[cfg(feature = "nix")]
+mod nix;
+
 #[cfg(feature = "packagekit")]
 mod packagekit;
 
@@ -71,6 +159,89 @@ mod pkgar;
 #[cfg(feature = "rpm-ostree")]
 mod rpm_ostree;
 
+#[cfg(feature = "snap")]
+mod snap;
+
+bitflags::bitflags! {
+    /// Actions supported by a backend, used to hide actions it cannot perform
+    #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+    pub struct Capabilities: u32 {
+        const INSTALL = 1 << 0;
+        const UNINSTALL = 1 << 1;
+        const UPDATE = 1 << 2;
+        /// Adding and removing repositories
+        const REPOSITORIES = 1 << 3;
+        /// Installing from local files using `file_packages`
+        const FILE_INSTALL = 1 << 4;
+        /// Looking up codecs using `gstreamer_packages`
+        const GSTREAMER = 1 << 5;
+        /// Deleting app data when uninstalling
+        const PURGE_DATA = 1 << 6;
+        /// Cancelling running operations
+        const CANCEL = 1 << 7;
+        /// Returning packages to the `revision` recorded in `Package::extra`
+        const DOWNGRADE = 1 << 8;
+        /// Listing deployments using `deployments` and changing them
+        const DEPLOYMENTS = 1 << 9;
+        /// Listing runtimes and extensions using `runtimes`
+        const RUNTIMES = 1 << 10;
+        /// Removing the pending deployment using `DeploymentAction::CleanupPending`
+        const DEPLOYMENT_CLEANUP = 1 << 11;
+        /// Pinning deployments using `DeploymentAction::Pin`
+        const DEPLOYMENT_PIN = 1 << 12;
+    }
+}
+
+/// Token used to cancel a running operation
+#[derive(Clone, Default)]
+pub struct CancelToken(Arc<CancelTokenInner>);
+
+#[derive(Default)]
+struct CancelTokenInner {
+    cancelled: AtomicBool,
+    handlers: Mutex<Vec<Box<dyn FnOnce() + Send>>>,
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Request cancellation, running all registered handlers
+    pub fn cancel(&self) {
+        if self.0.cancelled.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let handlers = mem::take(&mut *self.0.handlers.lock().unwrap());
+        for handler in handlers {
+            handler();
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Register a handler to run on cancellation, it runs immediately if already cancelled
+    pub fn on_cancel(&self, handler: impl FnOnce() + Send + 'static) {
+        let mut handlers = self.0.handlers.lock().unwrap();
+        if self.is_cancelled() {
+            drop(handlers);
+            handler();
+        } else {
+            handlers.push(Box::new(handler));
+        }
+    }
+}
+
+impl fmt::Debug for CancelToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CancelToken")
+            .field("cancelled", &self.is_cancelled())
+            .finish_non_exhaustive()
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Package {
     pub id: AppId,
@@ -80,7 +251,53 @@ pub struct Package {
     pub extra: HashMap<String, String>,
 }
 
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DeploymentState {
+    Booted,
+    /// Boots next, once the system restarts
+    Pending,
+    /// Boots next after a rollback
+    Rollback,
+    /// Kept because it is pinned
+    Other,
+}
+
+/// Bootable system image of an image based backend, such as an rpm-ostree deployment
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Deployment {
+    /// Backend specific ID used by `DeploymentAction::Pin`
+    pub id: String,
+    pub version: String,
+    /// Remote or image the deployment came from
+    pub origin: String,
+    pub state: DeploymentState,
+    pub pinned: bool,
+    /// Seconds since the Unix epoch
+    pub timestamp: i64,
+    /// Package changes compared to the booted deployment
+    pub diff: Vec<String>,
+}
+
+/// Runtime or extension installed for apps, such as a flatpak runtime
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Runtime {
+    /// Backend specific ID, such as `runtime/org.gnome.Platform/x86_64/46`
+    pub id: String,
+    pub name: String,
+    pub branch: String,
+    pub origin: String,
+    /// Installed size in bytes
+    pub size: u64,
+    /// Name of the app or runtime this extends
+    pub extension_of: Option<String>,
+    /// Names of the apps using it
+    pub used_by: Vec<String>,
+    /// Nothing installed needs it, so it can be removed
+    pub unused: bool,
+}
+
 pub trait Backend: fmt::Debug + Send + Sync {
+    fn capabilities(&self) -> Capabilities;
     fn load_caches(&mut self, refresh: bool) -> Result<(), Box<dyn Error>>;
     fn info_caches(&self) -> &[AppstreamCache];
     fn installed(&self) -> Result<Vec<Package>, Box<dyn Error>>;
@@ -92,16 +309,62 @@ pub trait Backend: fmt::Debug + Send + Sync {
     ) -> Result<Vec<Package>, Box<dyn Error>> {
         Ok(Vec::new())
     }
+    /// Returns true if finished operations only take effect after a reboot
+    fn reboot_required(&self) -> Result<bool, Box<dyn Error>> {
+        Ok(false)
+    }
+    /// Bootable system images, newest first
+    fn deployments(&self) -> Result<Vec<Deployment>, Box<dyn Error>> {
+        Ok(Vec::new())
+    }
+    /// Installed runtimes and extensions
+    fn runtimes(&self) -> Result<Vec<Runtime>, Box<dyn Error>> {
+        Ok(Vec::new())
+    }
+    /// Sandbox permissions of an app, or `None` if the backend does not sandbox apps
+    fn permissions(&self, _info: &AppInfo) -> Result<Option<Permissions>, Box<dyn Error>> {
+        Ok(None)
+    }
+    /// Refs of an app available from its source, such as other flatpak branches and arches
+    fn app_refs(&self, _info: &AppInfo) -> Result<Vec<String>, Box<dyn Error>> {
+        Ok(Vec::new())
+    }
     fn operation(
         &self,
         op: &Operation,
-        f: Box<dyn FnMut(f32) + 'static>,
-    ) -> Result<(), Box<dyn Error>>;
+        cancel_token: &CancelToken,
+        f: Box<dyn FnMut(OperationProgress) + 'static>,
+    ) -> Result<(), BackendError>;
 }
 
 // BTreeMap for stable sort order
 pub type Backends = BTreeMap<BackendName, Arc<dyn Backend>>;
 
+/// Construct a backend on a blocking thread, the receiver gets `None` if it failed to load
+fn spawn_backend<B: Backend + 'static>(
+    backend_name: BackendName,
+    new: impl FnOnce() -> Result<B, Box<dyn Error>> + Send + 'static,
+) -> tokio::sync::oneshot::Receiver<Option<(BackendName, Arc<dyn Backend>)>> {
+    let (tx, rx) = tokio::sync::oneshot::channel();
+    tokio::task::spawn_blocking(move || {
+        let start = Instant::now();
+        log::info!("adding {} backend", backend_name);
+        _ = tx.send(match new() {
+            Ok(backend) => {
+                let duration = start.elapsed();
+                log::info!("initialized {} backend in {:?}", backend_name, duration);
+                let backend: Arc<dyn Backend> = Arc::new(backend);
+                Some((backend_name, backend))
+            }
+            Err(err) => {
+                log::warn!("failed to load {} backend: {}", backend_name, err);
+                None
+            }
+        });
+    });
+    rx
+}
+
 /// Load store backends using rayon parallelism and concurrency.
 pub fn backends<'a>(
     locale: &'a str,
@@ -109,129 +372,106 @@ pub fn backends<'a>(
 ) -> impl futures::Stream<Item = (BackendName, Arc<dyn Backend>)> + Send + Unpin + 'static {
     let backends = futures::stream::FuturesUnordered::new();
 
+    // Only the fake backend is loaded when fixtures are provided, so tests are hermetic
+    #[cfg(feature = "fake")]
+    let fake_only = std::env::var_os(fake::DIR_ENV).is_some();
+    #[cfg(not(feature = "fake"))]
+    let fake_only = false;
+
     #[cfg(feature = "flatpak")]
-    {
+    if !fake_only {
         for (backend_name, user) in [
             (BackendName::FlatpakUser, true),
             (BackendName::FlatpakSystem, false),
         ] {
-            let (tx, rx) = tokio::sync::oneshot::channel();
             let locale = locale.to_owned();
-
-            tokio::task::spawn_blocking(move || {
-                let start = Instant::now();
-                log::info!("adding flatpak repository");
-                _ = tx.send(match flatpak::Flatpak::new(user, &locale) {
-                    Ok(backend) => {
-                        let duration = start.elapsed();
-                        log::warn!("initialized {} backend in {:?}", backend_name, duration);
-                        let backend: Arc<dyn Backend> = Arc::new(backend);
-                        Some((backend_name, backend))
-                    }
-                    Err(err) => {
-                        log::warn!("failed to load {} backend: {}", backend_name, err);
-                        None
-                    }
-                });
-            });
-
-            backends.push(rx)
+            backends.push(spawn_backend(backend_name, move || {
+                flatpak::Flatpak::new(user, &locale)
+            }));
         }
     }
 
     #[cfg(feature = "packagekit")]
-    {
-        let (tx, rx) = tokio::sync::oneshot::channel();
+    if !fake_only {
         let locale = locale.to_owned();
+        backends.push(spawn_backend(BackendName::Packagekit, move || {
+            packagekit::Packagekit::new(&locale)
+        }));
+    }
 
-        tokio::task::spawn_blocking(move || {
-            let start = Instant::now();
-            log::info!("adding packagekit backend");
-            _ = tx.send(match packagekit::Packagekit::new(&locale) {
-                Ok(backend) => {
-                    let backend: Arc<dyn Backend> = Arc::new(backend);
-                    let duration = start.elapsed();
-                    log::warn!(
-                        "initialized {} backend in {:?}",
-                        BackendName::Packagekit,
-                        duration
-                    );
-                    Some((BackendName::Packagekit, backend))
-                }
-                Err(err) => {
-                    log::error!(
-                        "failed to load {} backend: {}",
-                        BackendName::Packagekit,
-                        err
-                    );
-                    None
-                }
-            });
-        });
+    #[cfg(feature = "pkgar")]
+    if !fake_only {
+        let locale = locale.to_owned();
+        backends.push(spawn_backend(BackendName::Pkgar, move || {
+            pkgar::Pkgar::new(&locale)
+        }));
+    }
 
-        backends.push(rx)
+    #[cfg(feature = "rpm-ostree")]
+    if !fake_only {
+        let locale = locale.to_owned();
+        backends.push(spawn_backend(BackendName::RpmOstree, move || {
+            rpm_ostree::RpmOstree::new(&locale)
+        }));
     }
 
-    #[cfg(feature = "pkgar")]
-    {
-        let (tx, rx) = tokio::sync::oneshot::channel();
+    #[cfg(feature = "bootc")]
+    if !fake_only {
         let locale = locale.to_owned();
+        backends.push(spawn_backend(BackendName::Bootc, move || {
+            bootc::Bootc::new(&locale)
+        }));
+    }
 
-        tokio::task::spawn_blocking(move || {
-            let start = Instant::now();
-            log::info!("adding pkgar backend");
-            _ = tx.send(match pkgar::Pkgar::new(&locale) {
-                Ok(backend) => {
-                    let backend: Arc<dyn Backend> = Arc::new(backend);
-                    let duration = start.elapsed();
-                    log::info!(
-                        "initialized {} backend in {:?}",
-                        BackendName::Pkgar,
-                        duration
-                    );
-                    Some((BackendName::Pkgar, backend))
-                }
-                Err(err) => {
-                    log::error!("failed to load {} backend: {}", BackendName::Pkgar, err);
-                    None
-                }
-            })
-        });
+    #[cfg(feature = "snap")]
+    if !fake_only {
+        let locale = locale.to_owned();
+        backends.push(spawn_backend(BackendName::Snap, move || {
+            snap::Snap::new(&locale)
+        }));
+    }
 
-        backends.push(rx)
+    #[cfg(feature = "appimage")]
+    if !fake_only {
+        let locale = locale.to_owned();
+        backends.push(spawn_backend(BackendName::AppImage, move || {
+            appimage::AppImage::new(&locale)
+        }));
     }
 
-    #[cfg(feature = "rpm-ostree")]
-    {
-        let (tx, rx) = tokio::sync::oneshot::channel();
+    #[cfg(feature = "nix")]
+    if !fake_only {
         let locale = locale.to_owned();
+        backends.push(spawn_backend(BackendName::Nix, move || {
+            nix::Nix::new(&locale)
+        }));
+    }
 
-        tokio::task::spawn_blocking(move || {
-            let start = Instant::now();
-            log::info!("adding rpm-ostree backend");
-            _ = tx.send(match rpm_ostree::RpmOstree::new(&locale) {
-                Ok(backend) => {
-                    let backend: Arc<dyn Backend> = Arc::new(backend);
-                    let duration = start.elapsed();
-                    log::warn!(
-                        "initialized {} backend in {:?}",
-                        BackendName::RpmOstree,
-                        duration
-                    );
-                    Some((BackendName::RpmOstree, backend))
-                }
-                Err(err) => {
-                    log::warn!(
-                        "failed to load {} backend: {}",
-                        BackendName::RpmOstree,
-                        err
-                    );
-                    None
-                }
-            });
-        });
+    #[cfg(feature = "distrobox")]
+    if !fake_only {
+        let locale = locale.to_owned();
+        backends.push(spawn_backend(BackendName::Distrobox, move || {
+            distrobox::Distrobox::new(&locale)
+        }));
+    }
+
+    #[cfg(feature = "external")]
+    if !fake_only {
+        for (plugin_name, path) in external::plugins() {
+            let locale = locale.to_owned();
+            backends.push(spawn_backend(
+                BackendName::external(&plugin_name),
+                move || external::External::new(path, &locale),
+            ));
+        }
+    }
 
-        backends.push(rx)
+    #[cfg(feature = "fake")]
+    if fake_only {
+        let locale = locale.to_owned();
+        backends.push(spawn_backend(BackendName::Fake, move || {
+            fake::Fake::from_env(&locale)
+        }));
     }
 
     backends
@@ -0,0 +1,546 @@
+use appstream::{Component, xmltree};
+use cosmic::widget;
+use std::{
+    collections::{HashMap, VecDeque},
+    env,
+    error::Error,
+    fmt::Write as _,
+    fs,
+    io::{BufRead, BufReader},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+
+use super::{Backend, BackendError, CancelToken, Capabilities, Package};
+use crate::{
+    AppIcon, AppId, AppInfo, AppstreamCache, Operation, OperationKind, OperationPhase,
+    OperationProgress,
+};
+
+/// Command used instead of `nix`, such as a stand-in script for testing
+pub const COMMAND_ENV: &str = "COSMIC_STORE_NIX";
+
+// Lines of stderr kept for error messages
+const STDERR_TAIL_LINES: usize = 20;
+
+const SOURCE_ID: &str = "nix";
+//TODO: translate?
+const SOURCE_NAME: &str = "Nix";
+
+// Icon sizes to look for, largest first
+const ICON_SIZES: &[&str] = &["256x256", "128x128", "scalable", "96x96", "64x64", "48x48"];
+
+/// Entry of `nix profile list --json`
+#[derive(Clone, Debug)]
+struct Element {
+    /// Name used to refer to the entry in `nix profile` commands
+    name: String,
+    /// Flake reference and attribute, such as `flake:nixpkgs#legacyPackages.x86_64-linux.hello`
+    installable_opt: Option<String>,
+    store_paths: Vec<PathBuf>,
+}
+
+impl Element {
+    fn from_json(name: String, value: &serde_json::Value) -> Option<Self> {
+        if value["active"].as_bool() == Some(false) {
+            return None;
+        }
+        let installable_opt = match (value["originalUrl"].as_str(), value["attrPath"].as_str()) {
+            (Some(url), Some(attr_path)) => Some(format!("{url}#{attr_path}")),
+            _ => None,
+        };
+        let store_paths = value["storePaths"]
+            .as_array()?
+            .iter()
+            .filter_map(|x| x.as_str().map(PathBuf::from))
+            .collect();
+        Some(Self {
+            name,
+            installable_opt,
+            store_paths,
+        })
+    }
+
+    /// Version from the store path name, such as `2.12.1` from `/nix/store/<hash>-hello-2.12.1`
+    fn version(&self) -> String {
+        let Some(file_name) = self
+            .store_paths
+            .first()
+            .and_then(|x| x.file_name())
+            .map(|x| x.to_string_lossy().to_string())
+        else {
+            return String::new();
+        };
+        // Skip the hash, then find the first component starting with a digit
+        let mut parts = file_name.split('-').skip(1);
+        while let Some(part) = parts.next() {
+            if part.starts_with(|c: char| c.is_ascii_digit()) {
+                let mut version = part.to_string();
+                for part in parts.by_ref() {
+                    version.push('-');
+                    version.push_str(part);
+                }
+                return version;
+            }
+        }
+        String::new()
+    }
+}
+
+#[derive(Debug)]
+pub struct Nix {
+    command: String,
+    locale: String,
+    appstream_caches: Vec<AppstreamCache>,
+}
+
+impl Nix {
+    pub fn new(locale: &str) -> Result<Self, Box<dyn Error>> {
+        let command = env::var(COMMAND_ENV).unwrap_or_else(|_| "nix".to_string());
+        let nix = Self {
+            command,
+            locale: locale.to_string(),
+            appstream_caches: Vec::new(),
+        };
+        // Fail early when nix is not installed
+        nix.run(&["--version"])?;
+        Ok(nix)
+    }
+
+    fn command(&self, args: &[&str]) -> Command {
+        let mut command = Command::new(&self.command);
+        command
+            .arg("--extra-experimental-features")
+            .arg("nix-command flakes")
+            .args(args);
+        command
+    }
+
+    fn run(&self, args: &[&str]) -> Result<String, Box<dyn Error>> {
+        let output = self
+            .command(args)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .output()
+            .map_err(|err| format!("failed to run {}: {}", self.command, err))?;
+        if !output.status.success() {
+            return Err(format!(
+                "nix {} failed with status {}: {}",
+                args.join(" "),
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )
+            .into());
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    }
+
+    /// Run a profile command, killing it if the operation is cancelled
+    fn run_cancellable(
+        &self,
+        args: &[&str],
+        cancel_token: &CancelToken,
+    ) -> Result<(), BackendError> {
+        log::info!("running nix {}", args.join(" "));
+        let mut child = self
+            .command(args)
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|err| format!("failed to run {}: {}", self.command, err))?;
+        // Drain stderr while waiting, so nix does not block on a full pipe
+        let stderr_thread = child.stderr.take().map(|pipe| {
+            thread::spawn(move || {
+                let mut tail = VecDeque::with_capacity(STDERR_TAIL_LINES);
+                for line in BufReader::new(pipe).lines().map_while(Result::ok) {
+                    if tail.len() == STDERR_TAIL_LINES {
+                        tail.pop_front();
+                    }
+                    tail.push_back(line);
+                }
+                tail
+            })
+        });
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if cancel_token.is_cancelled() {
+                log::info!("cancelling nix {}", args.join(" "));
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(BackendError::Cancelled);
+            }
+            thread::sleep(Duration::from_millis(100));
+        };
+        let stderr = stderr_thread
+            .and_then(|handle| handle.join().ok())
+            .map(|tail| Vec::from(tail).join("\n"))
+            .unwrap_or_default();
+        if !status.success() {
+            let stderr = stderr.trim();
+            if stderr.contains("unable to download") || stderr.contains("Could not resolve host") {
+                return Err(BackendError::NetworkUnavailable(stderr.to_string()));
+            }
+            return Err(BackendError::Other(format!(
+                "nix {} failed with status {}: {}",
+                args.join(" "),
+                status,
+                stderr
+            )));
+        }
+        Ok(())
+    }
+
+    fn elements(&self) -> Result<Vec<Element>, Box<dyn Error>> {
+        let output = self.run(&["profile", "list", "--json"])?;
+        let list: serde_json::Value = serde_json::from_str(&output)?;
+        let mut elements = Vec::new();
+        match &list["elements"] {
+            // Newer profiles refer to entries by name
+            serde_json::Value::Object(map) => {
+                for (name, value) in map.iter() {
+                    elements.extend(Element::from_json(name.clone(), value));
+                }
+            }
+            // Older profiles refer to entries by index
+            serde_json::Value::Array(array) => {
+                for (i, value) in array.iter().enumerate() {
+                    elements.extend(Element::from_json(i.to_string(), value));
+                }
+            }
+            _ => return Err("nix profile list returned no elements".into()),
+        }
+        elements.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(elements)
+    }
+
+    fn icon_path(store_path: &Path, icon: &str) -> Option<PathBuf> {
+        if icon.starts_with('/') {
+            return Some(PathBuf::from(icon)).filter(|x| x.is_file());
+        }
+        let share = store_path.join("share");
+        ICON_SIZES
+            .iter()
+            .flat_map(|size| {
+                ["png", "svg"].map(|ext| {
+                    share
+                        .join("icons/hicolor")
+                        .join(size)
+                        .join("apps")
+                        .join(format!("{icon}.{ext}"))
+                })
+            })
+            .chain(
+                ["png", "svg", "xpm"]
+                    .map(|ext| share.join("pixmaps").join(format!("{icon}.{ext}"))),
+            )
+            .find(|x| x.is_file())
+    }
+
+    /// Apps provided by a profile entry, from its metainfo or desktop entries
+    fn element_infos(&self, element: &Element) -> Vec<(AppId, AppInfo)> {
+        let mut element_infos = Vec::new();
+        for store_path in element.store_paths.iter() {
+            let mut infos = Vec::new();
+            let mut desktop_paths: Vec<PathBuf> =
+                fs::read_dir(store_path.join("share/applications"))
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|entry_res| entry_res.ok())
+                    .map(|entry| entry.path())
+                    .filter(|x| x.extension().is_some_and(|ext| ext == "desktop"))
+                    .collect();
+            desktop_paths.sort();
+
+            for dir in ["share/metainfo", "share/appdata"] {
+                for entry in fs::read_dir(store_path.join(dir))
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|entry_res| entry_res.ok())
+                {
+                    let path = entry.path();
+                    if !path.to_string_lossy().ends_with(".xml") {
+                        continue;
+                    }
+                    let component = fs::File::open(&path)
+                        .map_err(Box::<dyn Error>::from)
+                        .and_then(|file| Ok(xmltree::Element::parse(file)?))
+                        .and_then(|e| Ok(Component::try_from(&e)?));
+                    match component {
+                        Ok(component) => {
+                            let id = AppId::new(&component.id.0);
+                            let info = AppInfo::new(
+                                SOURCE_ID,
+                                SOURCE_NAME,
+                                None,
+                                component,
+                                &self.locale,
+                                0,
+                            );
+                            infos.push((id, info));
+                        }
+                        Err(err) => {
+                            log::warn!("failed to parse metainfo {:?}: {}", path, err);
+                        }
+                    }
+                }
+            }
+
+            // Apps without metainfo are described by their desktop entries
+            if infos.is_empty() {
+                for desktop_path in desktop_paths.iter() {
+                    let entry = match freedesktop_entry_parser::parse_entry(desktop_path) {
+                        Ok(ok) => ok,
+                        Err(err) => {
+                            log::warn!("failed to parse desktop entry {:?}: {}", desktop_path, err);
+                            continue;
+                        }
+                    };
+                    let get_attr = |key| {
+                        entry
+                            .get("Desktop Entry", key)
+                            .and_then(|attr| attr.first())
+                            .cloned()
+                    };
+                    if get_attr("NoDisplay").as_deref() == Some("true")
+                        || get_attr("Type").as_deref() != Some("Application")
+                    {
+                        continue;
+                    }
+                    let Some(file_stem) = desktop_path.file_stem() else {
+                        continue;
+                    };
+                    let id = AppId::new(&file_stem.to_string_lossy());
+                    let mut info = AppInfo {
+                        source_id: SOURCE_ID.to_string(),
+                        source_name: SOURCE_NAME.to_string(),
+                        name: get_attr("Name").unwrap_or_else(|| element.name.clone()),
+                        summary: get_attr("Comment").unwrap_or_default(),
+                        categories: get_attr("Categories")
+                            .unwrap_or_default()
+                            .split(';')
+                            .filter(|x| !x.is_empty())
+                            .map(str::to_string)
+                            .collect(),
+                        ..Default::default()
+                    };
+                    if let Some(icon) = get_attr("Icon") {
+                        info.icons.push(AppIcon::Stock(icon));
+                    }
+                    infos.push((id, info));
+                }
+            }
+
+            for (_, info) in infos.iter_mut() {
+                // Stock icons are looked up in the package, as the profile is not in the theme
+                let icon_path_opt = info.icons.iter().find_map(|icon| match icon {
+                    AppIcon::Stock(name) => Self::icon_path(store_path, name),
+                    _ => None,
+                });
+                if let Some(icon_path) = icon_path_opt {
+                    info.icons.insert(
+                        0,
+                        AppIcon::Local(icon_path.to_string_lossy().to_string(), None, None, None),
+                    );
+                }
+                if info.desktop_ids.is_empty() {
+                    info.desktop_ids = desktop_paths
+                        .iter()
+                        .filter_map(|x| x.file_name())
+                        .map(|x| x.to_string_lossy().to_string())
+                        .collect();
+                }
+                info.pkgnames = vec![element.name.clone()];
+            }
+            element_infos.extend(infos);
+        }
+        element_infos
+    }
+
+    fn packages(&self, elements: Vec<(Element, String)>) -> Vec<Package> {
+        let mut system_packages = Vec::new();
+        let mut packages = Vec::new();
+        for (element, version) in elements {
+            let infos = self.element_infos(&element);
+            if infos.is_empty() {
+                system_packages.push((element.name, version));
+                continue;
+            }
+            for (id, info) in infos {
+                let icon = match info.icons.first() {
+                    Some(AppIcon::Local(path, _, _, _)) => widget::icon::from_path(path.into()),
+                    _ => widget::icon::from_name("package-x-generic")
+                        .size(128)
+                        .handle(),
+                };
+                let mut extra = HashMap::new();
+                if let Some(installable) = &element.installable_opt {
+                    extra.insert("installable".to_string(), installable.clone());
+                }
+                let installed_version = element.version();
+                if installed_version != version {
+                    extra.insert(format!("{}_installed", element.name), installed_version);
+                }
+                packages.push(Package {
+                    id,
+                    icon,
+                    info: Arc::new(info),
+                    version: version.clone(),
+                    extra,
+                });
+            }
+        }
+
+        if !system_packages.is_empty() {
+            let name = crate::fl!("system-packages");
+            let summary = crate::fl!("system-packages-summary", count = system_packages.len());
+            let mut description = String::new();
+            let mut pkgnames = Vec::with_capacity(system_packages.len());
+            for (package_name, version) in system_packages {
+                let _ = writeln!(description, " * {}: {}", package_name, version);
+                pkgnames.push(package_name);
+            }
+            packages.push(Package {
+                id: AppId::system(),
+                icon: widget::icon::from_name("package-x-generic")
+                    .size(128)
+                    .handle(),
+                info: Arc::new(AppInfo {
+                    source_id: SOURCE_ID.to_string(),
+                    source_name: SOURCE_NAME.to_string(),
+                    name,
+                    summary,
+                    description,
+                    pkgnames,
+                    ..Default::default()
+                }),
+                version: String::new(),
+                extra: HashMap::new(),
+            });
+        }
+
+        packages
+    }
+}
+
+impl Backend for Nix {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::INSTALL
+            | Capabilities::UNINSTALL
+            | Capabilities::UPDATE
+            | Capabilities::CANCEL
+    }
+
+    fn load_caches(&mut self, _refresh: bool) -> Result<(), Box<dyn Error>> {
+        // Packages in the profile are the only catalog
+        let mut cache = AppstreamCache {
+            source_id: SOURCE_ID.to_string(),
+            source_name: SOURCE_NAME.to_string(),
+            locale: self.locale.clone(),
+            ..Default::default()
+        };
+        for element in self.elements()? {
+            for (id, info) in self.element_infos(&element) {
+                cache
+                    .pkgnames
+                    .entry(element.name.clone())
+                    .or_default()
+                    .insert(id.clone());
+                cache.infos.insert(id, Arc::new(info));
+            }
+        }
+        self.appstream_caches = vec![cache];
+        Ok(())
+    }
+
+    fn info_caches(&self) -> &[AppstreamCache] {
+        &self.appstream_caches
+    }
+
+    fn installed(&self) -> Result<Vec<Package>, Box<dyn Error>> {
+        let elements = self
+            .elements()?
+            .into_iter()
+            .map(|element| {
+                let version = element.version();
+                (element, version)
+            })
+            .collect();
+        Ok(self.packages(elements))
+    }
+
+    fn updates(&self) -> Result<Vec<Package>, Box<dyn Error>> {
+        let mut updates = Vec::new();
+        for element in self.elements()? {
+            // Entries installed from a store path have nothing to upgrade from
+            let Some(installable) = &element.installable_opt else {
+                continue;
+            };
+            let version = match self.run(&["eval", "--raw", &format!("{installable}.version")]) {
+                Ok(ok) => ok.trim().to_string(),
+                Err(err) => {
+                    log::warn!("failed to check updates for {}: {}", element.name, err);
+                    continue;
+                }
+            };
+            if version.is_empty() || version == element.version() {
+                continue;
+            }
+            updates.push((element, version));
+        }
+        Ok(self.packages(updates))
+    }
+
+    fn file_packages(&self, _path: &str) -> Result<Vec<Package>, Box<dyn Error>> {
+        Err("nix backend does not support file-based package parsing".into())
+    }
+
+    fn operation(
+        &self,
+        op: &Operation,
+        cancel_token: &CancelToken,
+        mut f: Box<dyn FnMut(OperationProgress) + 'static>,
+    ) -> Result<(), BackendError> {
+        let (command, phase) = match &op.kind {
+            OperationKind::Install => ("install", OperationPhase::Installing),
+            OperationKind::Uninstall { .. } => ("remove", OperationPhase::Removing),
+            OperationKind::Update => ("upgrade", OperationPhase::Installing),
+            OperationKind::Downgrade(_)
+            | OperationKind::RepositoryAdd(_)
+            | OperationKind::RepositoryRemove(_, _)
+            | OperationKind::Deployment(_) => {
+                return Err(BackendError::NotSupported(
+                    "nix backend only supports installing, uninstalling and updating".to_string(),
+                ));
+            }
+        };
+
+        let mut names: Vec<String> = op
+            .infos
+            .iter()
+            .flat_map(|info| info.pkgnames.iter().cloned())
+            .collect();
+        names.dedup();
+        for (i, name) in names.iter().enumerate() {
+            f(OperationProgress {
+                percent: (i as f32) * 100.0 / (names.len() as f32),
+                phase_opt: Some(phase),
+                package_opt: Some(name.clone()),
+                ..Default::default()
+            });
+            // Entries not in the profile are installed from nixpkgs by name
+            let target = if command == "install" && !name.contains('#') {
+                format!("nixpkgs#{name}")
+            } else {
+                name.clone()
+            };
+            self.run_cancellable(&["profile", command, &target], cancel_token)?;
+        }
+        f(OperationProgress::new(100.0));
+        Ok(())
+    }
+}
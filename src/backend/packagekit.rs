@@ -11,8 +11,11 @@ use packagekit_zbus::{
 };
 use std::{collections::HashMap, error::Error, fmt::Write, sync::Arc};
 
-use super::{Backend, Package};
-use crate::{AppId, AppInfo, AppUrl, AppstreamCache, GStreamerCodec, Operation, OperationKind};
+use super::{Backend, BackendError, CancelToken, Capabilities, Package};
+use crate::{
+    AppId, AppInfo, AppUrl, AppstreamCache, GStreamerCodec, Operation, OperationKind,
+    OperationPhase, OperationProgress, fl,
+};
 
 #[derive(Debug)]
 struct TransactionDetails {
@@ -37,6 +40,55 @@ struct TransactionProgress {
     percentage: u32,
 }
 
+// https://lazka.github.io/pgi-docs/PackageKitGlib-1.0/enums.html#PackageKitGlib.StatusEnum
+fn status_phase(status: u32) -> Option<OperationPhase> {
+    match status {
+        // Setup, query, info, refresh cache, dep resolve, sig check, test commit, loading cache
+        2 | 4 | 5 | 7 | 13 | 14 | 15 | 27 => Some(OperationPhase::Resolving),
+        // Download and download repository, package list, file list, changelog, group, update info
+        8 | 20..=25 => Some(OperationPhase::Downloading),
+        // Install, update, commit, copy files, run hook
+        9 | 10 | 16 | 35 | 36 => Some(OperationPhase::Installing),
+        // Remove, obsolete
+        6 | 12 => Some(OperationPhase::Removing),
+        // Cleanup
+        11 => Some(OperationPhase::CleaningUp),
+        _ => None,
+    }
+}
+
+fn status_text(status: u32) -> String {
+    match status {
+        1 => fl!("status-waiting-in-queue"),
+        19 => fl!("status-cancelling"),
+        30 => fl!("status-waiting-for-lock"),
+        31 => fl!("status-waiting-for-auth"),
+        _ => String::new(),
+    }
+}
+
+// https://www.freedesktop.org/software/PackageKit/gtk-doc/PackageKit-Enumerations.html#PkErrorEnum
+fn error_code(code: u32, details: String) -> BackendError {
+    let message = format!("{details} (code {code})");
+    match code {
+        // NO_NETWORK, PACKAGE_DOWNLOAD_FAILED, REPO_NOT_AVAILABLE, NO_MORE_MIRRORS_TO_TRY
+        2 | 10 | 37 | 43 => BackendError::NetworkUnavailable(message),
+        // NOT_SUPPORTED
+        3 => BackendError::NotSupported(message),
+        // PACKAGE_NOT_INSTALLED, PACKAGE_NOT_FOUND, UPDATE_NOT_FOUND
+        7 | 8 | 49 => BackendError::PackageNotFound(message),
+        // DEP_RESOLUTION_FAILED, FILE_CONFLICTS, PACKAGE_CONFLICTS
+        13 | 35 | 36 => BackendError::DependencyConflict(message),
+        // TRANSACTION_CANCELLED
+        17 => BackendError::Cancelled,
+        // NO_SPACE_ON_DEVICE
+        46 => BackendError::DiskFull(message),
+        // NOT_AUTHORIZED, such as when the authentication prompt is dismissed
+        48 => BackendError::AuthCancelled,
+        _ => BackendError::Other(message),
+    }
+}
+
 fn transaction_handle(
     tx: TransactionProxyBlocking,
     mut on_progress: impl FnMut(u32, TransactionProgress),
@@ -89,9 +141,7 @@ fn transaction_handle(
                 "ErrorCode" => {
                     // https://www.freedesktop.org/software/PackageKit/gtk-doc/Transaction.html#Transaction::ErrorCode
                     let (code, details) = signal.body().deserialize::<(u32, String)>()?;
-                    if code != 48 {
-                        return Err(format!("{details} (code {code})").into());
-                    }
+                    return Err(error_code(code, details).into());
                 }
                 "ItemProgress" => {
                     // https://www.freedesktop.org/software/PackageKit/gtk-doc/Transaction.html#Transaction::ItemProgress
@@ -238,12 +288,24 @@ impl Packagekit {
                 continue;
             };
             let version_opt = parts.next();
-            let _architecture_opt = parts.next();
+            let architecture_opt = parts.next();
 
             let data = parts.next().unwrap_or("");
             let mut data_parts = data.split(':');
-            let _status_opt = data_parts.next();
-            let _origin_opt = data_parts.next();
+            let status_opt = data_parts.next();
+            let origin_opt = data_parts.next();
+
+            // Installed packages have installed:origin as data, the origin is needed to install again
+            let revision = match (status_opt, origin_opt) {
+                (Some("installed"), Some(origin)) => format!(
+                    "{};{};{};{}",
+                    package_name,
+                    version_opt.unwrap_or(""),
+                    architecture_opt.unwrap_or(""),
+                    origin
+                ),
+                _ => tx_package.package_id.clone(),
+            };
 
             match appstream_cache.pkgnames.get(package_name) {
                 Some(ids) => {
@@ -255,7 +317,10 @@ impl Packagekit {
                                     icon: appstream_cache.icon(info),
                                     info: info.clone(),
                                     version: version_opt.unwrap_or("").to_string(),
-                                    extra: HashMap::new(),
+                                    extra: HashMap::from([(
+                                        "revision".to_string(),
+                                        revision.clone(),
+                                    )]),
                                 });
                             }
                             None => {
@@ -311,6 +376,16 @@ impl Packagekit {
 }
 
 impl Backend for Packagekit {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::INSTALL
+            | Capabilities::UNINSTALL
+            | Capabilities::UPDATE
+            | Capabilities::FILE_INSTALL
+            | Capabilities::GSTREAMER
+            | Capabilities::CANCEL
+            | Capabilities::DOWNGRADE
+    }
+
     fn load_caches(&mut self, refresh: bool) -> Result<(), Box<dyn Error>> {
         if refresh {
             let tx = self.transaction()?;
@@ -413,8 +488,9 @@ impl Backend for Packagekit {
     fn operation(
         &self,
         op: &Operation,
-        mut f: Box<dyn FnMut(f32) + 'static>,
-    ) -> Result<(), Box<dyn Error>> {
+        cancel_token: &CancelToken,
+        mut f: Box<dyn FnMut(OperationProgress) + 'static>,
+    ) -> Result<(), BackendError> {
         let mut package_names = Vec::new();
         let mut package_paths = Vec::new();
         for info in op.infos.iter() {
@@ -437,7 +513,9 @@ impl Backend for Packagekit {
                         | FilterKind::Newest as u64
                         | FilterKind::Arch as u64
                 }
-                OperationKind::Uninstall { .. } => FilterKind::Installed as u64,
+                OperationKind::Uninstall { .. } | OperationKind::Downgrade(_) => {
+                    FilterKind::Installed as u64
+                }
                 // Other operations not supported
                 _ => 0,
             };
@@ -484,13 +562,48 @@ impl Backend for Packagekit {
                 //TODO: transaction flags?
                 tx.update_packages(TransactionFlag::OnlyTrusted as u64, &package_ids)?;
             }
+            OperationKind::Downgrade(revisions) => {
+                // Revisions are full package IDs of the previously installed versions
+                let revisions = revisions.iter().map(String::as_str).collect::<Vec<_>>();
+                log::info!("downgrading packages {:?} to {:?}", package_ids, revisions);
+                tx.install_packages(
+                    TransactionFlag::OnlyTrusted as u64 | TransactionFlag::AllowDowngrade as u64,
+                    &revisions,
+                )?;
+            }
             OperationKind::RepositoryAdd { .. } => {
-                return Err("packagekit backend does not support adding repositories".into());
+                return Err(BackendError::NotSupported(
+                    "packagekit backend does not support adding repositories".to_string(),
+                ));
             }
             OperationKind::RepositoryRemove { .. } => {
-                return Err("packagekit backend does not support removing repositories".into());
+                return Err(BackendError::NotSupported(
+                    "packagekit backend does not support removing repositories".to_string(),
+                ));
             }
+            OperationKind::Deployment(_) => {
+                return Err(BackendError::NotSupported(
+                    "packagekit backend does not support deployments".to_string(),
+                ));
+            }
+        }
+        {
+            // Cancel using a new proxy, as this thread is blocked handling transaction signals
+            let connection = self.connection.clone();
+            let tx_path = zvariant::OwnedObjectPath::from(tx.inner().path().clone());
+            cancel_token.on_cancel(move || {
+                log::info!("cancelling packagekit transaction {}", tx_path.as_str());
+                let res = TransactionProxyBlocking::builder(&connection)
+                    .destination("org.freedesktop.PackageKit")
+                    .and_then(|builder| builder.path(tx_path))
+                    .and_then(|builder| builder.build())
+                    .and_then(|tx| tx.cancel());
+                if let Err(err) = res {
+                    log::warn!("failed to cancel packagekit transaction: {}", err);
+                }
+            });
         }
+        let mut item_percents = vec![0.0; op.infos.len()];
         let _tx_packages = transaction_handle(tx, |total_percentage, progress| {
             log::info!(
                 "{}%: {} {} {}%",
@@ -499,7 +612,31 @@ impl Backend for Packagekit {
                 progress.status,
                 progress.percentage
             );
-            f(total_percentage as f32);
+            // Package IDs are formatted as name;version;arch;data
+            let package_opt = progress
+                .package_id
+                .split(';')
+                .next()
+                .filter(|name| !name.is_empty())
+                .map(str::to_string);
+            // Percentage is 101 when unknown
+            if let Some(name) = &package_opt
+                && progress.percentage <= 100
+                && let Some(item) = op
+                    .infos
+                    .iter()
+                    .position(|info| info.pkgnames.contains(name))
+            {
+                item_percents[item] = progress.percentage as f32;
+            }
+            f(OperationProgress {
+                percent: total_percentage as f32,
+                phase_opt: status_phase(progress.status),
+                package_opt,
+                status: status_text(progress.status),
+                item_percents: item_percents.clone(),
+                ..Default::default()
+            });
         })?;
         Ok(())
     }
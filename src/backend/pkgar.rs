@@ -1,8 +1,137 @@
 use cosmic::widget;
-use std::{collections::HashMap, error::Error, fmt::Write, fs, sync::Arc};
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    error::Error,
+    fmt::Write as _,
+    fs,
+    io::Read,
+    path::Path,
+    process::{Command, Stdio},
+    sync::Arc,
+    thread,
+    time::Duration,
+};
 
-use super::{Backend, Package};
-use crate::{AppId, AppInfo, AppstreamCache, Operation};
+use super::{Backend, BackendError, CancelToken, Capabilities, Package};
+use crate::{
+    AppId, AppInfo, AppstreamCache, Operation, OperationKind, OperationPhase, OperationProgress,
+};
+
+// Installed packages have a head file here, and metadata when pkg saved it
+const PKG_DIR: &str = "/pkg";
+// Files listing the URLs of configured repositories, one per line
+const REPOS_DIR: &str = "/etc/pkg.d";
+// Size of the signature, public key, hash and entry count at the start of a head file
+const HEAD_HEADER_SIZE: usize = 64 + 32 + 32 + 8;
+// Size of the hash, offset, size, mode and path of each entry
+const HEAD_ENTRY_SIZE: u64 = 32 + 8 + 8 + 4 + 256;
+
+/// Read the top level string values of package metadata such as `version = "0.1.0"`
+fn parse_metadata(text: &str) -> HashMap<String, String> {
+    let mut values = HashMap::new();
+    for line in text.lines() {
+        let line = line.trim();
+        // Tables such as dependencies come after the top level values
+        if line.starts_with('[') {
+            break;
+        }
+        if let Some((key, value)) = line.split_once('=')
+            && let Some(value) = value.trim().strip_prefix('"')
+            && let Some(value) = value.strip_suffix('"')
+        {
+            values.insert(key.trim().to_string(), value.to_string());
+        }
+    }
+    values
+}
+
+/// Read the hash of a head file, checking that the entries it lists are all present
+fn read_head(path: &Path) -> Result<String, Box<dyn Error>> {
+    let mut file = fs::File::open(path)?;
+    let mut header = [0; HEAD_HEADER_SIZE];
+    file.read_exact(&mut header)?;
+    let count = u64::from_le_bytes(header[128..136].try_into()?);
+    let len = file.metadata()?.len();
+    if len < HEAD_HEADER_SIZE as u64 + count * HEAD_ENTRY_SIZE {
+        return Err(format!("{path:?} is truncated, expected {count} entries").into());
+    }
+    let mut blake3 = String::with_capacity(64);
+    for byte in &header[96..128] {
+        let _ = write!(blake3, "{byte:02x}");
+    }
+    Ok(blake3)
+}
+
+/// Compare versions by their numeric and text parts, so `0.10.0` is newer than `0.9.1`
+fn compare_versions(a: &str, b: &str) -> Ordering {
+    let parts = |version: &str| -> Vec<String> {
+        version
+            .split(|c: char| !c.is_ascii_alphanumeric())
+            .filter(|part| !part.is_empty())
+            .map(str::to_string)
+            .collect()
+    };
+    let (a_parts, b_parts) = (parts(a), parts(b));
+    for (a_part, b_part) in a_parts.iter().zip(b_parts.iter()) {
+        let ordering = match (a_part.parse::<u64>(), b_part.parse::<u64>()) {
+            (Ok(a_number), Ok(b_number)) => a_number.cmp(&b_number),
+            _ => a_part.cmp(b_part),
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    a_parts.len().cmp(&b_parts.len())
+}
+
+fn target() -> String {
+    format!("{}-unknown-redox", std::env::consts::ARCH)
+}
+
+/// URLs of the configured repositories, in the order pkg uses them
+fn repositories() -> Vec<String> {
+    let mut paths: Vec<_> = match fs::read_dir(REPOS_DIR) {
+        Ok(read_dir) => read_dir
+            .filter_map(|entry_res| entry_res.ok())
+            .map(|entry| entry.path())
+            .collect(),
+        Err(err) => {
+            log::warn!(
+                "failed to read pkg repositories from {}: {}",
+                REPOS_DIR,
+                err
+            );
+            return Vec::new();
+        }
+    };
+    paths.sort();
+    let mut urls = Vec::new();
+    for path in paths {
+        match fs::read_to_string(&path) {
+            Ok(text) => {
+                for line in text.lines() {
+                    let line = line.trim();
+                    if !line.is_empty() && !line.starts_with('#') {
+                        urls.push(line.trim_end_matches('/').to_string());
+                    }
+                }
+            }
+            Err(err) => {
+                log::warn!("failed to read pkg repository {:?}: {}", path, err);
+            }
+        }
+    }
+    urls
+}
+
+#[derive(Debug)]
+struct InstalledPackage {
+    name: String,
+    version: String,
+    // Head hash, identifies the installed build when metadata is missing
+    blake3: String,
+}
 
 #[derive(Debug)]
 pub struct Pkgar {
@@ -22,60 +151,98 @@ impl Pkgar {
             )],
         })
     }
-}
 
-impl Backend for Pkgar {
-    fn load_caches(&mut self, _refresh: bool) -> Result<(), Box<dyn Error>> {
-        for appstream_cache in self.appstream_caches.iter_mut() {
-            appstream_cache.reload();
+    fn installed_packages(&self) -> Result<Vec<InstalledPackage>, Box<dyn Error>> {
+        let mut installed = Vec::new();
+        for entry_res in fs::read_dir(PKG_DIR)? {
+            let entry = entry_res?;
+            let file_name_os = entry.file_name();
+            let file_name = file_name_os.to_string_lossy();
+            let Some(package_name) = file_name.strip_suffix(".pkgar_head") else {
+                continue;
+            };
+            let blake3 = match read_head(&entry.path()) {
+                Ok(ok) => ok,
+                Err(err) => {
+                    log::warn!("failed to read pkgar head {:?}: {}", entry.path(), err);
+                    continue;
+                }
+            };
+            let metadata_path = Path::new(PKG_DIR).join(format!("{package_name}.toml"));
+            let version = match fs::read_to_string(&metadata_path) {
+                Ok(text) => parse_metadata(&text).remove("version").unwrap_or_default(),
+                Err(err) => {
+                    log::debug!("no pkgar metadata {:?}: {}", metadata_path, err);
+                    String::new()
+                }
+            };
+            installed.push(InstalledPackage {
+                name: package_name.to_string(),
+                version,
+                blake3,
+            });
         }
-        Ok(())
+        installed.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(installed)
     }
 
-    fn info_caches(&self) -> &[AppstreamCache] {
-        &self.appstream_caches
+    /// Fetch package metadata from the first repository that has the package
+    fn remote_metadata(
+        &self,
+        repos: &[String],
+        package_name: &str,
+    ) -> Result<Option<HashMap<String, String>>, Box<dyn Error>> {
+        let target = target();
+        // Backend calls run on blocking threads of the tokio runtime
+        tokio::runtime::Handle::current().block_on(async {
+            for repo in repos {
+                let url = format!("{repo}/{target}/{package_name}.toml");
+                let response = reqwest::get(&url).await?;
+                if response.status() == reqwest::StatusCode::NOT_FOUND {
+                    continue;
+                }
+                let text = response.error_for_status()?.text().await?;
+                return Ok(Some(parse_metadata(&text)));
+            }
+            Ok(None)
+        })
     }
 
-    fn installed(&self) -> Result<Vec<Package>, Box<dyn Error>> {
+    /// Convert installed packages into packages for their components, with the rest grouped
+    /// into one system package
+    fn packages(
+        &self,
+        installed: Vec<(InstalledPackage, HashMap<String, String>)>,
+    ) -> Vec<Package> {
         let appstream_cache = &self.appstream_caches[0];
 
         let mut system_packages = Vec::new();
         let mut packages = Vec::new();
-        for entry_res in fs::read_dir("/pkg")? {
-            let entry = entry_res?;
-            let file_name_os = entry.file_name();
-            let file_name = file_name_os.to_string_lossy();
-            if file_name.ends_with(".pkgar_head") {
-                let package_name = file_name.trim_end_matches(".pkgar_head");
-                let version_opt = None; //TODO: get pkgar version
-                println!("installed: {}", package_name);
-                match appstream_cache.pkgnames.get(package_name) {
-                    Some(ids) => {
-                        for id in ids.iter() {
-                            match appstream_cache.infos.get(id) {
-                                Some(info) => {
-                                    packages.push(Package {
-                                        id: id.clone(),
-                                        icon: appstream_cache.icon(info),
-                                        info: info.clone(),
-                                        version: version_opt.unwrap_or("").to_string(),
-                                        extra: HashMap::new(),
-                                    });
-                                }
-                                None => {
-                                    log::warn!("failed to find info {:?}", id);
-                                }
+        for (installed_package, extra) in installed {
+            let package_name = installed_package.name.as_str();
+            match appstream_cache.pkgnames.get(package_name) {
+                Some(ids) => {
+                    for id in ids.iter() {
+                        match appstream_cache.infos.get(id) {
+                            Some(info) => {
+                                packages.push(Package {
+                                    id: id.clone(),
+                                    icon: appstream_cache.icon(info),
+                                    info: info.clone(),
+                                    version: installed_package.version.clone(),
+                                    extra: extra.clone(),
+                                });
+                            }
+                            None => {
+                                log::warn!("failed to find info {:?}", id);
                             }
                         }
                     }
-                    None => {
-                        // Ignore packages with no components
-                        log::debug!("no components for package {}", package_name);
-                        system_packages.push((
-                            package_name.to_string(),
-                            version_opt.unwrap_or("").to_string(),
-                        ));
-                    }
+                }
+                None => {
+                    // Ignore packages with no components
+                    log::debug!("no components for package {}", package_name);
+                    system_packages.push((installed_package, extra));
                 }
             }
         }
@@ -85,9 +252,15 @@ impl Backend for Pkgar {
             let summary = crate::fl!("system-packages-summary", count = system_packages.len());
             let mut description = String::new();
             let mut pkgnames = Vec::with_capacity(system_packages.len());
-            for (package_name, version) in system_packages {
-                let _ = writeln!(description, " * {}: {}", package_name, version);
-                pkgnames.push(package_name);
+            let mut system_extra = HashMap::new();
+            for (installed_package, extra) in system_packages {
+                let _ = writeln!(
+                    description,
+                    " * {}: {}",
+                    installed_package.name, installed_package.version
+                );
+                system_extra.extend(extra);
+                pkgnames.push(installed_package.name);
             }
             //TODO: translate
             packages.push(Package {
@@ -106,15 +279,141 @@ impl Backend for Pkgar {
                     ..Default::default()
                 }),
                 version: String::new(),
-                extra: HashMap::new(),
+                extra: system_extra,
             });
         }
 
-        Ok(packages)
+        packages
+    }
+
+    /// Run pkg for one package, killing it if the operation is cancelled
+    fn run_pkg(
+        &self,
+        command: &str,
+        package_name: &str,
+        cancel_token: &CancelToken,
+    ) -> Result<(), BackendError> {
+        log::info!("running pkg {} {}", command, package_name);
+        let mut child = Command::new("pkg")
+            .arg(command)
+            .arg(package_name)
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|err| format!("failed to run pkg: {err}"))?;
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if cancel_token.is_cancelled() {
+                log::info!("cancelling pkg {} {}", command, package_name);
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(BackendError::Cancelled);
+            }
+            thread::sleep(Duration::from_millis(100));
+        };
+        if !status.success() {
+            let mut stderr = String::new();
+            if let Some(mut pipe) = child.stderr.take() {
+                let _ = pipe.read_to_string(&mut stderr);
+            }
+            return Err(BackendError::Other(format!(
+                "pkg {} {} failed with status {}: {}",
+                command,
+                package_name,
+                status,
+                stderr.trim()
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl Backend for Pkgar {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::INSTALL
+            | Capabilities::UNINSTALL
+            | Capabilities::UPDATE
+            | Capabilities::CANCEL
+    }
+
+    fn load_caches(&mut self, _refresh: bool) -> Result<(), Box<dyn Error>> {
+        for appstream_cache in self.appstream_caches.iter_mut() {
+            appstream_cache.reload();
+        }
+        Ok(())
+    }
+
+    fn info_caches(&self) -> &[AppstreamCache] {
+        &self.appstream_caches
+    }
+
+    fn installed(&self) -> Result<Vec<Package>, Box<dyn Error>> {
+        let installed = self
+            .installed_packages()?
+            .into_iter()
+            .map(|mut installed_package| {
+                // Show the start of the head hash when the version is unknown
+                if installed_package.version.is_empty() {
+                    installed_package.version = installed_package.blake3[..12].to_string();
+                }
+                (installed_package, HashMap::new())
+            })
+            .collect();
+        Ok(self.packages(installed))
     }
 
     fn updates(&self) -> Result<Vec<Package>, Box<dyn Error>> {
-        Ok(Vec::new())
+        let repos = repositories();
+        if repos.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut updates = Vec::new();
+        for mut installed_package in self.installed_packages()? {
+            let remote = match self.remote_metadata(&repos, &installed_package.name) {
+                Ok(Some(remote)) => remote,
+                Ok(None) => continue,
+                Err(err) => {
+                    log::warn!(
+                        "failed to check updates for {}: {}",
+                        installed_package.name,
+                        err
+                    );
+                    continue;
+                }
+            };
+            let version = remote.get("version").cloned().unwrap_or_default();
+            // An older remote version is never offered, even if it is a different build
+            let newer_opt = (!version.is_empty() && !installed_package.version.is_empty())
+                .then(|| compare_versions(&version, &installed_package.version));
+            let update = match remote.get("blake3") {
+                // The head hash identifies the build, so new builds of a version are found
+                Some(blake3) => {
+                    !blake3.eq_ignore_ascii_case(&installed_package.blake3)
+                        && newer_opt != Some(Ordering::Less)
+                }
+                None => newer_opt == Some(Ordering::Greater),
+            };
+            if !update {
+                continue;
+            }
+            if installed_package.version.is_empty() {
+                installed_package.version = installed_package.blake3[..12].to_string();
+            }
+            let mut extra = HashMap::new();
+            extra.insert(
+                format!("{}_installed", installed_package.name),
+                installed_package.version.clone(),
+            );
+            installed_package.version = match remote.get("blake3") {
+                Some(blake3) if version.is_empty() => blake3.chars().take(12).collect(),
+                _ => version,
+            };
+            updates.push((installed_package, extra));
+        }
+        Ok(self.packages(updates))
     }
 
     fn file_packages(&self, _path: &str) -> Result<Vec<Package>, Box<dyn Error>> {
@@ -123,9 +422,56 @@ impl Backend for Pkgar {
 
     fn operation(
         &self,
-        _op: &Operation,
-        _f: Box<dyn FnMut(f32) + 'static>,
-    ) -> Result<(), Box<dyn Error>> {
-        Err("Pkgar::operation not implemented".into())
+        op: &Operation,
+        cancel_token: &CancelToken,
+        mut f: Box<dyn FnMut(OperationProgress) + 'static>,
+    ) -> Result<(), BackendError> {
+        let (command, phase) = match &op.kind {
+            OperationKind::Install => ("install", OperationPhase::Installing),
+            OperationKind::Uninstall { .. } => {
+                if op.package_ids.iter().any(|id| id.is_system()) {
+                    return Err(BackendError::NotSupported(
+                        "pkgar backend cannot uninstall system packages".to_string(),
+                    ));
+                }
+                ("uninstall", OperationPhase::Removing)
+            }
+            OperationKind::Update => ("upgrade", OperationPhase::Installing),
+            OperationKind::Downgrade(_)
+            | OperationKind::RepositoryAdd(_)
+            | OperationKind::RepositoryRemove(_, _)
+            | OperationKind::Deployment(_) => {
+                return Err(BackendError::NotSupported(
+                    "pkgar backend only supports installing, uninstalling and updating".to_string(),
+                ));
+            }
+        };
+
+        // pkg reports no progress, so progress advances as each package finishes
+        let items: Vec<(usize, &str)> = op
+            .infos
+            .iter()
+            .enumerate()
+            .flat_map(|(i, info)| info.pkgnames.iter().map(move |x| (i, x.as_str())))
+            .collect();
+        let mut item_percents = vec![0.0; op.infos.len()];
+        for (done, (i, package_name)) in items.iter().enumerate() {
+            f(OperationProgress {
+                percent: (done as f32) * 100.0 / (items.len() as f32),
+                phase_opt: Some(phase),
+                package_opt: Some(package_name.to_string()),
+                item_percents: item_percents.clone(),
+                ..Default::default()
+            });
+            self.run_pkg(command, package_name, cancel_token)?;
+            let info_items = items.iter().filter(|(x, _)| x == i).count();
+            let info_done = items[..=done].iter().filter(|(x, _)| x == i).count();
+            item_percents[*i] = (info_done as f32) * 100.0 / (info_items as f32);
+        }
+        f(OperationProgress {
+            item_percents,
+            ..OperationProgress::new(100.0)
+        });
+        Ok(())
     }
 }
@@ -1,17 +1,81 @@
 use cosmic::widget;
+use serde::Deserialize;
 use std::{
     collections::HashMap,
     error::Error,
+    fmt::Write as _,
     process::{Command, Stdio},
-    sync::Arc,
+    sync::{Arc, Mutex},
 };
 
-use super::{Backend, Package};
-use crate::{AppId, AppInfo, AppstreamCache, GStreamerCodec, Operation, OperationKind};
+use super::{
+    Backend, BackendError, CancelToken, Capabilities, Deployment, DeploymentState, Package,
+};
+use crate::{
+    AppId, AppInfo, AppstreamCache, DeploymentAction, GStreamerCodec, Operation, OperationKind,
+    OperationPhase, OperationProgress,
+};
+
+/// Output of `rpm-ostree status --json`
+#[derive(Debug, Deserialize)]
+struct Status {
+    deployments: Vec<StatusDeployment>,
+}
+
+impl Status {
+    /// Commit of the staged deployment, without any layered packages
+    fn staged_commit(&self) -> Option<&str> {
+        self.deployments
+            .iter()
+            .find(|deployment| deployment.staged)
+            .map(|deployment| {
+                deployment
+                    .base_checksum
+                    .as_deref()
+                    .unwrap_or(&deployment.checksum)
+            })
+    }
+
+    fn booted_index(&self) -> Option<usize> {
+        self.deployments
+            .iter()
+            .position(|deployment| deployment.booted)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct StatusDeployment {
+    id: String,
+    checksum: String,
+    /// Commit the deployment is based on, if packages are layered
+    #[serde(default)]
+    base_checksum: Option<String>,
+    #[serde(default)]
+    version: Option<String>,
+    #[serde(default)]
+    origin: Option<String>,
+    #[serde(default)]
+    timestamp: i64,
+    #[serde(default)]
+    booted: bool,
+    #[serde(default)]
+    staged: bool,
+    #[serde(default)]
+    pinned: bool,
+    /// Layered packages in the deployment
+    #[serde(default)]
+    packages: Vec<String>,
+    /// Layered packages requested with `rpm-ostree install`
+    #[serde(default)]
+    requested_packages: Vec<String>,
+}
 
 #[derive(Debug)]
 pub struct RpmOstree {
     appstream_caches: Vec<AppstreamCache>,
+    // Package changes between two commits, which never change once committed
+    diffs: Mutex<HashMap<(String, String), Vec<String>>>,
 }
 
 impl RpmOstree {
@@ -25,28 +89,10 @@ impl RpmOstree {
                 source_name.to_string(),
                 locale,
             )],
+            diffs: Mutex::new(HashMap::new()),
         })
     }
 
-    fn parse_staged_commit(&self, output: &str) -> Result<Option<String>, Box<dyn Error>> {
-        let parts: Vec<&str> = output.split("\u{25cf} ").collect();
-
-        // If there's more than one part, something is staged before the ●
-        if parts.len() >= 2 {
-            let staged_section = parts[0];
-            for line in staged_section.lines() {
-                let line = line.trim();
-                if line.starts_with("Commit:") || line.starts_with("BaseCommit:") {
-                    if let Some(commit) = line.split(':').nth(1).map(|s| s.trim().to_string()) {
-                        return Ok(Some(commit));
-                    }
-                }
-            }
-        }
-
-        Ok(None)
-    }
-
     fn parse_upgrade_check(
         &self,
         output: &str,
@@ -130,9 +176,136 @@ impl RpmOstree {
 
         Ok(String::from_utf8_lossy(&output.stdout).to_string())
     }
+
+    fn status(&self) -> Result<Status, Box<dyn Error>> {
+        let output = self.run_command(&["status", "--json"])?;
+        Ok(serde_json::from_str(&output)?)
+    }
+
+    /// Package changes between two commits, such as `Upgraded:` followed by the packages
+    fn diff(&self, from: &str, to: &str) -> Vec<String> {
+        let key = (from.to_string(), to.to_string());
+        if let Some(diff) = self.diffs.lock().unwrap().get(&key) {
+            return diff.clone();
+        }
+        match self.run_command(&["db", "diff", from, to]) {
+            Ok(output) => {
+                let diff: Vec<String> = output
+                    .lines()
+                    .filter(|line| !line.trim().is_empty() && !line.starts_with("ostree diff"))
+                    .map(|line| line.trim_end().to_string())
+                    .collect();
+                self.diffs.lock().unwrap().insert(key, diff.clone());
+                diff
+            }
+            // Failures are not cached, so the diff is tried again on the next refresh
+            Err(err) => {
+                log::warn!("failed to diff {} and {}: {}", from, to, err);
+                Vec::new()
+            }
+        }
+    }
+
+    /// Pin or unpin a deployment, which ostree refers to by its current index
+    fn pin(&self, id: &str, pinned: bool, cancel_token: &CancelToken) -> Result<(), BackendError> {
+        let index = self
+            .status()?
+            .deployments
+            .iter()
+            .position(|deployment| deployment.id == id)
+            .ok_or_else(|| format!("deployment {id} not found"))?;
+        let index = index.to_string();
+        let mut args = vec!["ostree", "admin", "pin"];
+        if !pinned {
+            args.push("--unpin");
+        }
+        args.push(&index);
+        // Pins are changed with ostree directly, which requires root
+        log::info!("running pkexec {}", args.join(" "));
+        let output = Command::new("pkexec")
+            .args(&args)
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .output()?;
+        match output.status.code() {
+            Some(0) => Ok(()),
+            // pkexec exits with 126 if authorization was dismissed
+            Some(126) => Err(BackendError::AuthCancelled),
+            _ if cancel_token.is_cancelled() => Err(BackendError::Cancelled),
+            _ => Err(BackendError::Other(format!(
+                "ostree admin pin failed with status {}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ))),
+        }
+    }
+
+    /// Versions of packages in the booted deployment, packages only in a pending
+    /// deployment are missing
+    fn package_versions(&self, pkgnames: &[String]) -> HashMap<String, String> {
+        let mut versions = HashMap::new();
+        if pkgnames.is_empty() {
+            return versions;
+        }
+        let output = match Command::new("rpm")
+            .arg("-q")
+            .arg("--queryformat")
+            .arg("%{NAME} %{VERSION}-%{RELEASE}\n")
+            .args(pkgnames)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .output()
+        {
+            Ok(ok) => ok,
+            Err(err) => {
+                log::warn!("failed to query rpm versions: {}", err);
+                return versions;
+            }
+        };
+        // Status is not checked, as rpm fails if any package is not installed
+        for line in String::from_utf8_lossy(&output.stdout).lines() {
+            if let Some((name, version)) = line.split_once(' ') {
+                versions.insert(name.to_string(), version.to_string());
+            }
+        }
+        versions
+    }
+
+    /// Run an rpm-ostree transaction that creates a new deployment
+    fn run_transaction(
+        &self,
+        args: &[&str],
+        cancel_token: &CancelToken,
+    ) -> Result<(), BackendError> {
+        cancel_token.on_cancel(|| {
+            log::info!("cancelling rpm-ostree transaction");
+            if let Err(err) = Command::new("rpm-ostree").arg("cancel").status() {
+                log::warn!("failed to cancel rpm-ostree transaction: {}", err);
+            }
+        });
+        log::info!("running rpm-ostree {}", args.join(" "));
+        if let Err(err) = self.run_command(args) {
+            if cancel_token.is_cancelled() {
+                return Err(BackendError::Cancelled);
+            }
+            return Err(err.into());
+        }
+        Ok(())
+    }
 }
 
 impl Backend for RpmOstree {
+    fn capabilities(&self) -> Capabilities {
+        // Packages are layered onto the system, changes apply after a reboot
+        Capabilities::INSTALL
+            | Capabilities::UNINSTALL
+            | Capabilities::UPDATE
+            | Capabilities::CANCEL
+            | Capabilities::DEPLOYMENTS
+            | Capabilities::DEPLOYMENT_CLEANUP
+            | Capabilities::DEPLOYMENT_PIN
+    }
+
     fn load_caches(&mut self, refresh: bool) -> Result<(), Box<dyn Error>> {
         if refresh {
             // Refresh appstream cache
@@ -150,17 +323,130 @@ impl Backend for RpmOstree {
     }
 
     fn installed(&self) -> Result<Vec<Package>, Box<dyn Error>> {
-        // while rpm-ostree supports package layering,
-        // we are focusing on system updates only
-        Ok(Vec::new())
+        let status = self.status()?;
+        // The first deployment is the one that will be booted next
+        let Some(deployment) = status.deployments.first() else {
+            return Ok(Vec::new());
+        };
+        let mut pkgnames: Vec<String> = deployment
+            .packages
+            .iter()
+            .chain(deployment.requested_packages.iter())
+            .cloned()
+            .collect();
+        pkgnames.sort();
+        pkgnames.dedup();
+        let versions = self.package_versions(&pkgnames);
+
+        let appstream_cache = &self.appstream_caches[0];
+        let mut system_packages = Vec::new();
+        let mut packages = Vec::new();
+        for package_name in pkgnames {
+            let version = versions.get(&package_name).cloned().unwrap_or_default();
+            match appstream_cache.pkgnames.get(&package_name) {
+                Some(ids) => {
+                    for id in ids.iter() {
+                        match appstream_cache.infos.get(id) {
+                            Some(info) => {
+                                packages.push(Package {
+                                    id: id.clone(),
+                                    icon: appstream_cache.icon(info),
+                                    info: info.clone(),
+                                    version: version.clone(),
+                                    extra: HashMap::new(),
+                                });
+                            }
+                            None => {
+                                log::warn!("failed to find info {:?}", id);
+                            }
+                        }
+                    }
+                }
+                None => {
+                    log::debug!("no components for layered package {}", package_name);
+                    system_packages.push((package_name, version));
+                }
+            }
+        }
+
+        if !system_packages.is_empty() {
+            let name = crate::fl!("system-packages");
+            let summary = crate::fl!("system-packages-summary", count = system_packages.len());
+            let mut description = String::new();
+            let mut pkgnames = Vec::with_capacity(system_packages.len());
+            for (package_name, version) in system_packages {
+                let _ = writeln!(description, " * {}: {}", package_name, version);
+                pkgnames.push(package_name);
+            }
+            packages.push(Package {
+                id: AppId::system(),
+                icon: widget::icon::from_name("package-x-generic")
+                    .size(128)
+                    .handle(),
+                info: Arc::new(AppInfo {
+                    source_id: appstream_cache.source_id.clone(),
+                    source_name: appstream_cache.source_name.clone(),
+                    name,
+                    summary,
+                    description,
+                    pkgnames,
+                    ..Default::default()
+                }),
+                version: String::new(),
+                extra: HashMap::new(),
+            });
+        }
+
+        Ok(packages)
     }
 
-    fn updates(&self) -> Result<Vec<Package>, Box<dyn Error>> {
-        let status_output = self.run_command(&["status"])?;
-        let staged_version = self.parse_staged_commit(&status_output)?;
+    fn reboot_required(&self) -> Result<bool, Box<dyn Error>> {
+        // A pending deployment is listed before the booted one
+        Ok(self
+            .status()?
+            .deployments
+            .first()
+            .is_some_and(|deployment| !deployment.booted))
+    }
+
+    fn deployments(&self) -> Result<Vec<Deployment>, Box<dyn Error>> {
+        let status = self.status()?;
+        let booted_index_opt = status.booted_index();
+        let booted_checksum_opt = booted_index_opt.map(|i| status.deployments[i].checksum.as_str());
+        let mut deployments = Vec::with_capacity(status.deployments.len());
+        for (i, deployment) in status.deployments.iter().enumerate() {
+            let state = match booted_index_opt {
+                Some(booted_i) if i == booted_i => DeploymentState::Booted,
+                Some(booted_i) if i < booted_i => DeploymentState::Pending,
+                Some(booted_i) if i == booted_i + 1 => DeploymentState::Rollback,
+                _ => DeploymentState::Other,
+            };
+            let diff = match booted_checksum_opt {
+                Some(booted_checksum) if state != DeploymentState::Booted => {
+                    self.diff(booted_checksum, &deployment.checksum)
+                }
+                _ => Vec::new(),
+            };
+            deployments.push(Deployment {
+                id: deployment.id.clone(),
+                version: deployment
+                    .version
+                    .clone()
+                    .unwrap_or_else(|| deployment.checksum.chars().take(12).collect()),
+                origin: deployment.origin.clone().unwrap_or_default(),
+                state,
+                pinned: deployment.pinned,
+                timestamp: deployment.timestamp,
+                diff,
+            });
+        }
+        Ok(deployments)
+    }
 
+    fn updates(&self) -> Result<Vec<Package>, Box<dyn Error>> {
+        let status = self.status()?;
         let output = self.run_command(&["upgrade", "--check"])?;
-        self.parse_upgrade_check(&output, staged_version.as_deref())
+        self.parse_upgrade_check(&output, status.staged_commit())
     }
 
     fn file_packages(&self, _path: &str) -> Result<Vec<Package>, Box<dyn Error>> {
@@ -177,24 +463,216 @@ impl Backend for RpmOstree {
     fn operation(
         &self,
         op: &Operation,
-        mut f: Box<dyn FnMut(f32) + 'static>,
-    ) -> Result<(), Box<dyn Error>> {
+        cancel_token: &CancelToken,
+        mut f: Box<dyn FnMut(OperationProgress) + 'static>,
+    ) -> Result<(), BackendError> {
+        let pkgnames: Vec<&str> = op
+            .infos
+            .iter()
+            .flat_map(|info| info.pkgnames.iter().map(String::as_str))
+            .collect();
         match &op.kind {
+            OperationKind::Install => {
+                f(OperationProgress {
+                    phase_opt: Some(OperationPhase::Installing),
+                    ..Default::default()
+                });
+                let mut args = vec!["install", "--idempotent"];
+                args.extend(pkgnames);
+                self.run_transaction(&args, cancel_token)?;
+                f(OperationProgress::new(100.0));
+                Ok(())
+            }
+            OperationKind::Uninstall { .. } => {
+                if op.package_ids.iter().any(|id| id.is_system()) {
+                    return Err(BackendError::NotSupported(
+                        "rpm-ostree backend cannot uninstall system packages".to_string(),
+                    ));
+                }
+                f(OperationProgress {
+                    phase_opt: Some(OperationPhase::Removing),
+                    ..Default::default()
+                });
+                let mut args = vec!["uninstall"];
+                args.extend(pkgnames);
+                self.run_transaction(&args, cancel_token)?;
+                f(OperationProgress::new(100.0));
+                Ok(())
+            }
             OperationKind::Update => {
-                f(0.0);
+                f(OperationProgress {
+                    phase_opt: Some(OperationPhase::Installing),
+                    ..Default::default()
+                });
                 log::info!("Applying rpm-ostree system update");
-                let _ = self.run_command(&["upgrade"])?;
-                f(100.0);
+                self.run_transaction(&["upgrade"], cancel_token)?;
+                f(OperationProgress::new(100.0));
                 log::info!("rpm-ostree upgrade completed");
                 Ok(())
             }
-            // rpm-ostree supports package layering, but we only care about updates
-            OperationKind::Install
-            | OperationKind::Uninstall { .. }
-            | OperationKind::RepositoryAdd { .. }
-            | OperationKind::RepositoryRemove { .. } => {
-                Err("rpm-ostree backend does not support per-package operations or repository management".into())
+            OperationKind::Deployment(action) => {
+                f(OperationProgress {
+                    phase_opt: Some(OperationPhase::Installing),
+                    ..Default::default()
+                });
+                match action {
+                    DeploymentAction::Rollback => {
+                        self.run_transaction(&["rollback"], cancel_token)?
+                    }
+                    DeploymentAction::CleanupPending => {
+                        self.run_transaction(&["cleanup", "-p"], cancel_token)?
+                    }
+                    DeploymentAction::Pin { id, pinned } => self.pin(id, *pinned, cancel_token)?,
+                    DeploymentAction::Switch(refspec) => {
+                        self.run_transaction(&["rebase", refspec], cancel_token)?
+                    }
+                }
+                f(OperationProgress::new(100.0));
+                Ok(())
             }
+            OperationKind::Downgrade(_)
+            | OperationKind::RepositoryAdd { .. }
+            | OperationKind::RepositoryRemove { .. } => Err(BackendError::NotSupported(
+                "rpm-ostree backend does not support downgrades or repository management"
+                    .to_string(),
+            )),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `rpm-ostree status --json` with a staged upgrade that layers a package
+    const STATUS_STAGED: &str = r#"{
+  "deployments" : [
+    {
+      "unlocked" : "none",
+      "requested-local-packages" : [],
+      "base-commit-meta" : {},
+      "base-removals" : [],
+      "pinned" : false,
+      "osname" : "fedora",
+      "base-checksum" : "8d6f3c4b2a",
+      "regenerate-initramfs" : false,
+      "checksum" : "5e1a9b7c30",
+      "requested-base-local-replacements" : [],
+      "requested-packages" : [
+        "htop"
+      ],
+      "id" : "fedora-5e1a9b7c30.0",
+      "version" : "41.20241102.0",
+      "requested-local-fileoverride-packages" : [],
+      "initramfs-etc" : [],
+      "timestamp" : 1730534400,
+      "staged" : true,
+      "booted" : false,
+      "packages" : [
+        "htop"
+      ],
+      "serial" : 0,
+      "origin" : "fedora:fedora/41/x86_64/silverblue",
+      "modules" : []
+    },
+    {
+      "pinned" : false,
+      "osname" : "fedora",
+      "checksum" : "2c4e6a8b10",
+      "requested-packages" : [],
+      "id" : "fedora-2c4e6a8b10.0",
+      "version" : "41.20241101.0",
+      "timestamp" : 1730448000,
+      "staged" : false,
+      "booted" : true,
+      "packages" : [],
+      "serial" : 0,
+      "origin" : "fedora:fedora/41/x86_64/silverblue"
+    },
+    {
+      "pinned" : true,
+      "osname" : "fedora",
+      "checksum" : "0a1b2c3d4e",
+      "id" : "fedora-0a1b2c3d4e.1",
+      "version" : "41.20241020.0",
+      "timestamp" : 1729411200,
+      "staged" : false,
+      "booted" : false,
+      "serial" : 1,
+      "origin" : "fedora:fedora/41/x86_64/silverblue"
+    }
+  ],
+  "transaction" : null,
+  "cached-update" : null,
+  "update-driver" : null
+}"#;
+
+    // `rpm-ostree status --json` booted from a container image, which has no origin or version
+    const STATUS_IMAGE: &str = r#"{
+  "deployments" : [
+    {
+      "container-image-reference" : "ostree-unverified-registry:quay.io/fedora/fedora-silverblue:41",
+      "container-image-reference-digest" : "sha256:9f8e7d6c5b",
+      "pinned" : false,
+      "osname" : "fedora",
+      "checksum" : "7b5d3f1e90",
+      "id" : "fedora-7b5d3f1e90.0",
+      "timestamp" : 1730448000,
+      "staged" : false,
+      "booted" : true,
+      "serial" : 0
+    }
+  ],
+  "transaction" : null
+}"#;
+
+    #[test]
+    fn status_staged() {
+        let status: Status = serde_json::from_str(STATUS_STAGED).unwrap();
+        assert_eq!(status.deployments.len(), 3);
+        assert_eq!(status.booted_index(), Some(1));
+        // Layered packages are not part of the base commit
+        assert_eq!(status.staged_commit(), Some("8d6f3c4b2a"));
+
+        let staged = &status.deployments[0];
+        assert_eq!(staged.id, "fedora-5e1a9b7c30.0");
+        assert_eq!(staged.checksum, "5e1a9b7c30");
+        assert_eq!(staged.version.as_deref(), Some("41.20241102.0"));
+        assert_eq!(
+            staged.origin.as_deref(),
+            Some("fedora:fedora/41/x86_64/silverblue")
+        );
+        assert_eq!(staged.timestamp, 1730534400);
+        assert!(staged.staged && !staged.booted && !staged.pinned);
+        assert_eq!(staged.packages, ["htop"]);
+        assert_eq!(staged.requested_packages, ["htop"]);
+
+        let booted = &status.deployments[1];
+        assert!(booted.booted && !booted.staged);
+        assert_eq!(booted.base_checksum, None);
+
+        let rollback = &status.deployments[2];
+        assert!(rollback.pinned);
+        assert!(rollback.packages.is_empty());
+        assert!(rollback.requested_packages.is_empty());
+    }
+
+    #[test]
+    fn status_image() {
+        let status: Status = serde_json::from_str(STATUS_IMAGE).unwrap();
+        assert_eq!(status.booted_index(), Some(0));
+        assert_eq!(status.staged_commit(), None);
+
+        let booted = &status.deployments[0];
+        assert_eq!(booted.version, None);
+        assert_eq!(booted.origin, None);
+        assert!(booted.packages.is_empty());
+    }
+
+    #[test]
+    fn status_invalid() {
+        // Deployments must have an ID and checksum
+        assert!(serde_json::from_str::<Status>(r#"{ "deployments": [{ "id": "x" }] }"#).is_err());
+        assert!(serde_json::from_str::<Status>("{}").is_err());
+    }
+}
@@ -0,0 +1,1122 @@
+use cosmic::widget;
+use serde::{Deserialize, de::DeserializeOwned};
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+    error::Error,
+    fmt::Write as _,
+    fs,
+    io::{Read, Write},
+    os::unix::net::UnixStream,
+    path::PathBuf,
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+
+use super::{Backend, BackendError, CancelToken, Capabilities, Package};
+use crate::{
+    AppId, AppInfo, AppstreamCache, Operation, OperationKind, OperationPhase, OperationProgress,
+    app_info::{AppIcon, AppKind, AppScreenshot, AppUrl},
+};
+
+const SOCKET_PATH: &str = "/run/snapd.socket";
+// Use another snapd socket, such as one from a mock server
+const SOCKET_ENV: &str = "COSMIC_STORE_SNAPD_SOCKET";
+
+const SOURCE_ID: &str = "snap";
+//TODO: translate?
+const SOURCE_NAME: &str = "Snap Store";
+
+// Delay between checks of a running change
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+// Store sections and the closest desktop category
+const SECTION_CATEGORIES: &[(&str, &str)] = &[
+    ("art-and-design", "Graphics"),
+    ("books-and-reference", "Education"),
+    ("development", "Development"),
+    ("devices-and-iot", "System"),
+    ("education", "Education"),
+    ("entertainment", "AudioVideo"),
+    ("finance", "Office"),
+    ("games", "Game"),
+    ("music-and-audio", "AudioVideo"),
+    ("news-and-weather", "Network"),
+    ("personalisation", "Settings"),
+    ("photo-and-video", "AudioVideo"),
+    ("productivity", "Office"),
+    ("science", "Science"),
+    ("security", "System"),
+    ("server-and-cloud", "System"),
+    ("social", "Network"),
+    ("utilities", "Utility"),
+];
+
+struct HttpResponse {
+    status: u16,
+    content_type: String,
+    body: Vec<u8>,
+}
+
+// Envelope of every snapd response
+#[derive(Deserialize)]
+struct SnapdResponse {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(rename = "status-code")]
+    status_code: u16,
+    #[serde(default)]
+    result: serde_json::Value,
+    change: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct SnapdError {
+    message: String,
+    #[serde(default)]
+    kind: String,
+}
+
+impl SnapdError {
+    fn into_backend_error(self, status_code: u16) -> BackendError {
+        let message = format!("{} (code {})", self.message, status_code);
+        match self.kind.as_str() {
+            "auth-cancelled" | "login-required" | "interaction-required" => {
+                BackendError::AuthCancelled
+            }
+            "snap-not-found" | "snap-not-installed" => BackendError::PackageNotFound(message),
+            "snap-needs-classic" | "snap-needs-devmode" | "snap-needs-classic-system" => {
+                BackendError::NotSupported(message)
+            }
+            "insufficient-disk-space" => BackendError::DiskFull(message),
+            "dns-failure" | "network-timeout" => BackendError::NetworkUnavailable(message),
+            _ if status_code == 401 || status_code == 403 => BackendError::AuthCancelled,
+            _ => BackendError::Other(message),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct SnapMedia {
+    #[serde(rename = "type")]
+    kind: String,
+    url: String,
+}
+
+#[derive(Default, Deserialize)]
+struct SnapPublisher {
+    #[serde(rename = "display-name", default)]
+    display_name: String,
+}
+
+#[derive(Deserialize)]
+struct SnapdSnap {
+    name: String,
+    #[serde(default)]
+    title: String,
+    #[serde(default)]
+    summary: String,
+    #[serde(default)]
+    description: String,
+    /// Path of the icon served by snapd, only set for installed snaps
+    #[serde(default)]
+    icon: String,
+    #[serde(default)]
+    version: String,
+    #[serde(default)]
+    revision: String,
+    #[serde(default)]
+    license: String,
+    #[serde(default)]
+    confinement: String,
+    #[serde(default)]
+    publisher: Option<SnapPublisher>,
+    #[serde(default)]
+    media: Vec<SnapMedia>,
+    #[serde(default)]
+    website: String,
+    #[serde(default)]
+    contact: String,
+    /// Snap type, such as `app`, `base` or `snapd`
+    #[serde(rename = "type", default)]
+    kind: String,
+}
+
+#[derive(Deserialize)]
+struct ChangeTaskProgress {
+    done: u64,
+    total: u64,
+}
+
+#[derive(Deserialize)]
+struct ChangeTask {
+    kind: String,
+    summary: String,
+    status: String,
+    progress: ChangeTaskProgress,
+}
+
+#[derive(Deserialize)]
+struct Change {
+    status: String,
+    ready: bool,
+    #[serde(default)]
+    err: String,
+    #[serde(default)]
+    tasks: Vec<ChangeTask>,
+}
+
+impl Change {
+    fn progress(&self, op: &Operation) -> OperationProgress {
+        let mut done = 0;
+        let mut total = 0;
+        let mut item_done = vec![0; op.infos.len()];
+        let mut item_total = vec![0; op.infos.len()];
+        let mut current_opt = None;
+        for task in self.tasks.iter() {
+            done += task.progress.done;
+            total += task.progress.total;
+            if let Some(name) = task_snap(&task.summary)
+                && let Some(item) = op
+                    .infos
+                    .iter()
+                    .position(|info| info.pkgnames.iter().any(|x| x == name))
+            {
+                item_done[item] += task.progress.done;
+                item_total[item] += task.progress.total;
+            }
+            if current_opt.is_none() && task.status == "Doing" {
+                current_opt = Some(task);
+            }
+        }
+        let percent = |done: u64, total: u64| {
+            if total > 0 {
+                (done as f32) * 100.0 / (total as f32)
+            } else {
+                0.0
+            }
+        };
+        let mut progress = OperationProgress {
+            percent: percent(done, total),
+            item_percents: item_done
+                .into_iter()
+                .zip(item_total)
+                .map(|(done, total)| percent(done, total))
+                .collect(),
+            ..Default::default()
+        };
+        if let Some(task) = current_opt {
+            progress.phase_opt = task_phase(&task.kind);
+            progress.package_opt = task_snap(&task.summary).map(str::to_string);
+            // Download tasks report progress in bytes
+            if task.kind == "download-snap" {
+                progress.bytes_downloaded = task.progress.done;
+                progress.bytes_total = task.progress.total;
+            }
+            progress.status = task.summary.clone();
+        }
+        progress
+    }
+}
+
+// Task summaries quote the snap name, such as `Download snap "hello" (42) from channel "stable"`
+fn task_snap(summary: &str) -> Option<&str> {
+    let (_, rest) = summary.split_once('"')?;
+    let (name, _) = rest.split_once('"')?;
+    Some(name)
+}
+
+fn task_phase(kind: &str) -> Option<OperationPhase> {
+    match kind {
+        "prerequisites" | "validate-snap" => Some(OperationPhase::Resolving),
+        "download-snap" => Some(OperationPhase::Downloading),
+        "mount-snap"
+        | "copy-snap-data"
+        | "setup-profiles"
+        | "link-snap"
+        | "auto-connect"
+        | "setup-aliases"
+        | "run-hook"
+        | "start-snap-services"
+        | "prepare-snap" => Some(OperationPhase::Installing),
+        "stop-snap-services"
+        | "remove-aliases"
+        | "unlink-snap"
+        | "unlink-current-snap"
+        | "remove-profiles"
+        | "clear-snap"
+        | "discard-snap" => Some(OperationPhase::Removing),
+        "cleanup" => Some(OperationPhase::CleaningUp),
+        _ => None,
+    }
+}
+
+fn decode_chunked(mut data: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut body = Vec::new();
+    loop {
+        let line_end = data
+            .windows(2)
+            .position(|x| x == b"\r\n")
+            .ok_or("invalid chunked response from snapd")?;
+        // Chunk extensions follow a semicolon
+        let size_str = std::str::from_utf8(&data[..line_end])?;
+        let size = usize::from_str_radix(size_str.split(';').next().unwrap_or("").trim(), 16)?;
+        data = &data[line_end + 2..];
+        if size == 0 {
+            break;
+        }
+        let chunk = data
+            .get(..size)
+            .ok_or("truncated chunked response from snapd")?;
+        body.extend_from_slice(chunk);
+        data = data.get(size + 2..).unwrap_or_default();
+    }
+    Ok(body)
+}
+
+/// Client for the snapd REST API, every request uses a new connection
+#[derive(Clone, Debug)]
+struct Snapd {
+    socket: PathBuf,
+}
+
+impl Snapd {
+    fn http(
+        &self,
+        method: &str,
+        path: &str,
+        body_opt: Option<&serde_json::Value>,
+    ) -> Result<HttpResponse, Box<dyn Error>> {
+        let body = match body_opt {
+            Some(body) => serde_json::to_vec(body)?,
+            None => Vec::new(),
+        };
+        // Interaction allows polkit to ask for authorization
+        let mut request = format!(
+            "{method} {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nX-Allow-Interaction: true\r\n"
+        );
+        if body_opt.is_some() {
+            let _ = write!(
+                request,
+                "Content-Type: application/json\r\nContent-Length: {}\r\n",
+                body.len()
+            );
+        }
+        request.push_str("\r\n");
+
+        let mut stream = UnixStream::connect(&self.socket)?;
+        stream.write_all(request.as_bytes())?;
+        stream.write_all(&body)?;
+        let mut data = Vec::new();
+        stream.read_to_end(&mut data)?;
+
+        let header_end = data
+            .windows(4)
+            .position(|x| x == b"\r\n\r\n")
+            .ok_or("invalid HTTP response from snapd")?;
+        let head = String::from_utf8_lossy(&data[..header_end]).into_owned();
+        let mut lines = head.lines();
+        let status = lines
+            .next()
+            .and_then(|line| line.split(' ').nth(1))
+            .and_then(|status| status.parse().ok())
+            .ok_or("invalid HTTP status from snapd")?;
+        let mut chunked = false;
+        let mut content_type = String::new();
+        for line in lines {
+            let Some((name, value)) = line.split_once(':') else {
+                continue;
+            };
+            let value = value.trim();
+            if name.eq_ignore_ascii_case("transfer-encoding") {
+                chunked = value.eq_ignore_ascii_case("chunked");
+            } else if name.eq_ignore_ascii_case("content-type") {
+                content_type = value.to_string();
+            }
+        }
+        let mut body = data.split_off(header_end + 4);
+        if chunked {
+            body = decode_chunked(&body)?;
+        }
+        Ok(HttpResponse {
+            status,
+            content_type,
+            body,
+        })
+    }
+
+    /// Send a request, returning the error reported by snapd if it failed
+    fn call(
+        &self,
+        method: &str,
+        path: &str,
+        body_opt: Option<&serde_json::Value>,
+    ) -> Result<SnapdResponse, Box<dyn Error>> {
+        let response = self.http(method, path, body_opt)?;
+        let snapd_response: SnapdResponse = serde_json::from_slice(&response.body)?;
+        if snapd_response.kind == "error" {
+            let err: SnapdError = serde_json::from_value(snapd_response.result)?;
+            return Err(err.into_backend_error(snapd_response.status_code).into());
+        }
+        Ok(snapd_response)
+    }
+
+    fn get<R: DeserializeOwned>(&self, path: &str) -> Result<R, Box<dyn Error>> {
+        Ok(serde_json::from_value(
+            self.call("GET", path, None)?.result,
+        )?)
+    }
+
+    /// Start a change, returning its ID
+    fn post(&self, path: &str, body: serde_json::Value) -> Result<String, Box<dyn Error>> {
+        let response = self.call("POST", path, Some(&body))?;
+        Ok(response
+            .change
+            .ok_or_else(|| format!("snapd did not start a change for {path}"))?)
+    }
+}
+
+#[derive(Debug)]
+pub struct Snap {
+    snapd: Snapd,
+    locale: String,
+    appstream_caches: Vec<AppstreamCache>,
+    // Snaps that must be installed with classic confinement
+    classic: HashSet<String>,
+}
+
+impl Snap {
+    pub fn new(locale: &str) -> Result<Self, Box<dyn Error>> {
+        let socket =
+            env::var_os(SOCKET_ENV).map_or_else(|| PathBuf::from(SOCKET_PATH), PathBuf::from);
+        if !socket.exists() {
+            return Err(format!("snapd socket {socket:?} not found").into());
+        }
+        // Caches are filled from the store in load_caches
+        Ok(Self {
+            snapd: Snapd { socket },
+            locale: locale.to_string(),
+            appstream_caches: Vec::new(),
+            classic: HashSet::new(),
+        })
+    }
+
+    /// Save the icon of an installed snap from snapd, returning its path
+    fn icon_path(&self, snap: &SnapdSnap) -> Option<PathBuf> {
+        if snap.icon.is_empty() {
+            return None;
+        }
+        let dir = dirs::cache_dir()?.join("cosmic-store").join("snap-icons");
+        for ext in ["png", "svg"] {
+            let path = dir.join(format!("{}-{}.{}", snap.name, snap.revision, ext));
+            if path.is_file() {
+                return Some(path);
+            }
+        }
+        let response = match self.snapd.http("GET", &snap.icon, None) {
+            Ok(ok) if ok.status == 200 => ok,
+            Ok(ok) => {
+                log::debug!("no icon for snap {}: status {}", snap.name, ok.status);
+                return None;
+            }
+            Err(err) => {
+                log::warn!("failed to get icon for snap {}: {}", snap.name, err);
+                return None;
+            }
+        };
+        let ext = if response.content_type.starts_with("image/svg") {
+            "svg"
+        } else {
+            "png"
+        };
+        let path = dir.join(format!("{}-{}.{}", snap.name, snap.revision, ext));
+        if let Err(err) = fs::create_dir_all(&dir).and_then(|()| fs::write(&path, &response.body)) {
+            log::warn!("failed to save icon {:?}: {}", path, err);
+            return None;
+        }
+        Some(path)
+    }
+
+    fn info(snap: &SnapdSnap, icon_path_opt: Option<PathBuf>, categories: Vec<String>) -> AppInfo {
+        let mut icons = Vec::new();
+        if let Some(icon_path) = icon_path_opt {
+            icons.push(AppIcon::Local(
+                icon_path.to_string_lossy().into_owned(),
+                None,
+                None,
+                None,
+            ));
+        }
+        let mut screenshots = Vec::new();
+        for media in snap.media.iter() {
+            match media.kind.as_str() {
+                "icon" => icons.push(AppIcon::Remote(media.url.clone(), None, None, None)),
+                "screenshot" => screenshots.push(AppScreenshot {
+                    caption: String::new(),
+                    url: media.url.clone(),
+                }),
+                _ => {}
+            }
+        }
+        let mut urls = Vec::new();
+        if !snap.website.is_empty() {
+            urls.push(AppUrl::Homepage(snap.website.clone()));
+        }
+        if !snap.contact.is_empty() {
+            urls.push(AppUrl::Contact(snap.contact.clone()));
+        }
+        AppInfo {
+            source_id: SOURCE_ID.to_string(),
+            source_name: SOURCE_NAME.to_string(),
+            name: if snap.title.is_empty() {
+                snap.name.clone()
+            } else {
+                snap.title.clone()
+            },
+            summary: snap.summary.clone(),
+            kind: if snap.kind.is_empty() || snap.kind == "app" {
+                AppKind::DesktopApplication
+            } else {
+                AppKind::Addon
+            },
+            developer_name: snap
+                .publisher
+                .as_ref()
+                .map_or_else(String::new, |x| x.display_name.clone()),
+            description: snap.description.clone(),
+            license_opt: (!snap.license.is_empty()).then(|| snap.license.clone()),
+            pkgnames: vec![snap.name.clone()],
+            categories,
+            icons,
+            screenshots,
+            urls,
+            ..Default::default()
+        }
+    }
+
+    fn package(&self, snap: SnapdSnap) -> Package {
+        let id = AppId::new(&snap.name);
+        let cached_opt = self
+            .appstream_caches
+            .iter()
+            .find_map(|cache| Some((cache, cache.infos.get(&id)?)));
+        let (icon, info) = match cached_opt {
+            Some((cache, info)) => (cache.icon(info), info.clone()),
+            None => {
+                let icon_path_opt = self.icon_path(&snap);
+                let icon = match &icon_path_opt {
+                    Some(icon_path) => widget::icon::from_path(icon_path.clone()),
+                    None => widget::icon::from_name("package-x-generic")
+                        .size(128)
+                        .handle(),
+                };
+                (icon, Arc::new(Self::info(&snap, icon_path_opt, Vec::new())))
+            }
+        };
+        let mut extra = HashMap::new();
+        extra.insert("revision".to_string(), snap.revision);
+        Package {
+            id,
+            icon,
+            info,
+            version: snap.version,
+            extra,
+        }
+    }
+
+    /// Start a change and wait for it to finish, reporting progress
+    fn run(
+        &self,
+        path: &str,
+        body: serde_json::Value,
+        op: &Operation,
+        cancel_token: &CancelToken,
+        f: &mut dyn FnMut(OperationProgress),
+    ) -> Result<(), BackendError> {
+        if cancel_token.is_cancelled() {
+            return Err(BackendError::Cancelled);
+        }
+        log::info!("snapd {}: {}", path, body);
+        let change_id = self.snapd.post(path, body)?;
+        {
+            let snapd = self.snapd.clone();
+            let change_id = change_id.clone();
+            cancel_token.on_cancel(move || {
+                log::info!("aborting snapd change {}", change_id);
+                let body = serde_json::json!({ "action": "abort" });
+                if let Err(err) =
+                    snapd.call("POST", &format!("/v2/changes/{change_id}"), Some(&body))
+                {
+                    log::warn!("failed to abort snapd change {}: {}", change_id, err);
+                }
+            });
+        }
+        loop {
+            let change: Change = self.snapd.get(&format!("/v2/changes/{change_id}"))?;
+            f(change.progress(op));
+            if change.ready {
+                return match change.status.as_str() {
+                    "Done" => Ok(()),
+                    _ if cancel_token.is_cancelled() => Err(BackendError::Cancelled),
+                    status => Err(BackendError::Other(format!(
+                        "snapd change {change_id} {status}: {}",
+                        change.err
+                    ))),
+                };
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+impl Backend for Snap {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::INSTALL
+            | Capabilities::UNINSTALL
+            | Capabilities::UPDATE
+            | Capabilities::PURGE_DATA
+            | Capabilities::CANCEL
+            | Capabilities::DOWNGRADE
+    }
+
+    fn load_caches(&mut self, _refresh: bool) -> Result<(), Box<dyn Error>> {
+        let installed: Vec<SnapdSnap> = self.snapd.get("/v2/snaps")?;
+        let mut icon_paths = HashMap::new();
+        for snap in installed.iter() {
+            if let Some(icon_path) = self.icon_path(snap) {
+                icon_paths.insert(snap.name.clone(), icon_path);
+            }
+        }
+
+        // snapd cannot list the whole store, so it is searched by section
+        let sections: Vec<String> = match self.snapd.get("/v2/sections") {
+            Ok(ok) => ok,
+            Err(err) => {
+                log::warn!("failed to list snap sections: {}", err);
+                vec!["featured".to_string()]
+            }
+        };
+        let mut found: HashMap<String, (SnapdSnap, Vec<String>)> = HashMap::new();
+        for section in sections.iter() {
+            let snaps: Vec<SnapdSnap> = match self.snapd.get(&format!("/v2/find?section={section}"))
+            {
+                Ok(ok) => ok,
+                Err(err) => {
+                    log::warn!("failed to find snaps in section {}: {}", section, err);
+                    continue;
+                }
+            };
+            let category_opt = SECTION_CATEGORIES
+                .iter()
+                .find(|(name, _)| name == section)
+                .map(|(_, category)| category.to_string());
+            for snap in snaps {
+                let (_, categories) = found
+                    .entry(snap.name.clone())
+                    .or_insert_with(|| (snap, Vec::new()));
+                if let Some(category) = &category_opt
+                    && !categories.contains(category)
+                {
+                    categories.push(category.clone());
+                }
+            }
+        }
+        // Installed apps may not be in any section
+        for snap in installed {
+            if snap.kind == "app" && !found.contains_key(&snap.name) {
+                found.insert(snap.name.clone(), (snap, Vec::new()));
+            }
+        }
+
+        let mut cache = AppstreamCache {
+            source_id: SOURCE_ID.to_string(),
+            source_name: SOURCE_NAME.to_string(),
+            locale: self.locale.clone(),
+            ..Default::default()
+        };
+        self.classic.clear();
+        for (name, (snap, categories)) in found {
+            if snap.confinement == "classic" {
+                self.classic.insert(name.clone());
+            }
+            let id = AppId::new(&name);
+            let info = Self::info(&snap, icon_paths.remove(&name), categories);
+            cache.infos.insert(id.clone(), Arc::new(info));
+            cache.pkgnames.entry(name).or_default().insert(id);
+        }
+        log::info!("loaded {} snaps", cache.infos.len());
+        self.appstream_caches = vec![cache];
+        Ok(())
+    }
+
+    fn info_caches(&self) -> &[AppstreamCache] {
+        &self.appstream_caches
+    }
+
+    fn installed(&self) -> Result<Vec<Package>, Box<dyn Error>> {
+        let snaps: Vec<SnapdSnap> = self.snapd.get("/v2/snaps")?;
+        Ok(snaps
+            .into_iter()
+            // Bases, kernels and snapd itself are not shown as apps
+            .filter(|snap| snap.kind == "app")
+            .map(|snap| self.package(snap))
+            .collect())
+    }
+
+    fn updates(&self) -> Result<Vec<Package>, Box<dyn Error>> {
+        let snaps: Vec<SnapdSnap> = self.snapd.get("/v2/find?select=refresh")?;
+        Ok(snaps.into_iter().map(|snap| self.package(snap)).collect())
+    }
+
+    fn file_packages(&self, _path: &str) -> Result<Vec<Package>, Box<dyn Error>> {
+        Err("snap backend does not support installing from files".into())
+    }
+
+    fn operation(
+        &self,
+        op: &Operation,
+        cancel_token: &CancelToken,
+        mut f: Box<dyn FnMut(OperationProgress) + 'static>,
+    ) -> Result<(), BackendError> {
+        let names: Vec<&str> = op
+            .infos
+            .iter()
+            .flat_map(|info| info.pkgnames.iter().map(String::as_str))
+            .collect();
+        match &op.kind {
+            OperationKind::Install => {
+                // Classic snaps cannot be installed together with others
+                let (classic, strict): (Vec<&str>, Vec<&str>) =
+                    names.iter().partition(|name| self.classic.contains(**name));
+                if !strict.is_empty() {
+                    self.run(
+                        "/v2/snaps",
+                        serde_json::json!({ "action": "install", "snaps": strict }),
+                        op,
+                        cancel_token,
+                        &mut f,
+                    )?;
+                }
+                for name in classic {
+                    self.run(
+                        &format!("/v2/snaps/{name}"),
+                        serde_json::json!({ "action": "install", "classic": true }),
+                        op,
+                        cancel_token,
+                        &mut f,
+                    )?;
+                }
+                Ok(())
+            }
+            OperationKind::Uninstall { purge_data } => self.run(
+                "/v2/snaps",
+                serde_json::json!({ "action": "remove", "snaps": names, "purge": purge_data }),
+                op,
+                cancel_token,
+                &mut f,
+            ),
+            OperationKind::Update => self.run(
+                "/v2/snaps",
+                serde_json::json!({ "action": "refresh", "snaps": names }),
+                op,
+                cancel_token,
+                &mut f,
+            ),
+            OperationKind::Downgrade(revisions) => {
+                for (name, revision) in names.iter().zip(revisions.iter()) {
+                    self.run(
+                        &format!("/v2/snaps/{name}"),
+                        serde_json::json!({ "action": "revert", "revision": revision }),
+                        op,
+                        cancel_token,
+                        &mut f,
+                    )?;
+                }
+                Ok(())
+            }
+            OperationKind::RepositoryAdd(_) | OperationKind::RepositoryRemove(_, _) => {
+                Err(BackendError::NotSupported(
+                    "snap backend does not support repositories".to_string(),
+                ))
+            }
+            OperationKind::Deployment(_) => Err(BackendError::NotSupported(
+                "snap backend does not support deployments".to_string(),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io::{BufRead, BufReader},
+        os::unix::net::UnixListener,
+        sync::Mutex,
+    };
+    use tempfile::TempDir;
+
+    type Requests = Arc<Mutex<Vec<(String, serde_json::Value)>>>;
+
+    // Serve snapd requests from a thread, recording the method, path and body of each
+    fn serve(
+        dir: &TempDir,
+        respond: impl Fn(&str, &serde_json::Value) -> serde_json::Value + Send + 'static,
+    ) -> (Snap, Requests) {
+        let socket = dir.path().join("snapd.socket");
+        let listener = UnixListener::bind(&socket).unwrap();
+        let requests = Requests::default();
+        {
+            let requests = requests.clone();
+            thread::spawn(move || {
+                for stream_res in listener.incoming() {
+                    let mut stream = stream_res.unwrap();
+                    let mut reader = BufReader::new(stream.try_clone().unwrap());
+                    let mut request_line = String::new();
+                    reader.read_line(&mut request_line).unwrap();
+                    let mut content_length = 0;
+                    loop {
+                        let mut line = String::new();
+                        reader.read_line(&mut line).unwrap();
+                        if line.trim().is_empty() {
+                            break;
+                        }
+                        if let Some((name, value)) = line.split_once(':')
+                            && name.eq_ignore_ascii_case("content-length")
+                        {
+                            content_length = value.trim().parse().unwrap();
+                        }
+                    }
+                    let mut body = vec![0; content_length];
+                    reader.read_exact(&mut body).unwrap();
+                    let body = if body.is_empty() {
+                        serde_json::Value::Null
+                    } else {
+                        serde_json::from_slice(&body).unwrap()
+                    };
+
+                    let request = request_line
+                        .rsplit_once(' ')
+                        .map_or("", |(request, _)| request)
+                        .to_string();
+                    let response = respond(&request, &body).to_string();
+                    requests.lock().unwrap().push((request, body));
+
+                    // snapd responds with chunked bodies, split this one in two chunks
+                    let (a, b) = response.split_at(response.len() / 2);
+                    write!(
+                        stream,
+                        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nTransfer-Encoding: chunked\r\n\r\n{:x}\r\n{a}\r\n{:x}\r\n{b}\r\n0\r\n\r\n",
+                        a.len(),
+                        b.len()
+                    )
+                    .unwrap();
+                }
+            });
+        }
+        let snap = Snap {
+            snapd: Snapd { socket },
+            locale: "en".to_string(),
+            appstream_caches: Vec::new(),
+            classic: HashSet::new(),
+        };
+        (snap, requests)
+    }
+
+    fn sync(result: serde_json::Value) -> serde_json::Value {
+        serde_json::json!({ "type": "sync", "status-code": 200, "result": result })
+    }
+
+    fn async_change(change_id: &str) -> serde_json::Value {
+        serde_json::json!({ "type": "async", "status-code": 202, "change": change_id })
+    }
+
+    fn change(status: &str, ready: bool, done: u64) -> serde_json::Value {
+        sync(serde_json::json!({
+            "id": "1",
+            "kind": "install-snap",
+            "status": status,
+            "ready": ready,
+            "tasks": [{
+                "kind": "download-snap",
+                "summary": "Download snap \"hello\" (42) from channel \"stable\"",
+                "status": if ready { "Done" } else { "Doing" },
+                "progress": { "label": "hello", "done": done, "total": 100 },
+            }],
+        }))
+    }
+
+    fn operation(kind: OperationKind, names: &[&str]) -> Operation {
+        Operation {
+            kind,
+            backend_name: crate::BackendName::Snap,
+            package_ids: names.iter().map(|name| AppId::new(name)).collect(),
+            infos: names
+                .iter()
+                .map(|name| {
+                    Arc::new(AppInfo {
+                        pkgnames: vec![name.to_string()],
+                        ..Default::default()
+                    })
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn chunked() {
+        for (data, expected) in [
+            (
+                &b"4\r\nWiki\r\n5\r\npedia\r\n0\r\n\r\n"[..],
+                &b"Wikipedia"[..],
+            ),
+            (b"4;name=value\r\nWiki\r\n0\r\n\r\n", b"Wiki"),
+            (b"A\r\n0123456789\r\n0\r\n\r\n", b"0123456789"),
+            (b"0\r\n\r\n", b""),
+        ] {
+            assert_eq!(
+                decode_chunked(data).unwrap(),
+                expected,
+                "data {:?}",
+                String::from_utf8_lossy(data)
+            );
+        }
+    }
+
+    #[test]
+    fn chunked_invalid() {
+        for data in [
+            &b"a\r\nWiki"[..],
+            b"4\r\nWiki\r\n",
+            b"4\r\nWiki",
+            b"zz\r\n",
+            b"",
+        ] {
+            assert!(
+                decode_chunked(data).is_err(),
+                "data {:?}",
+                String::from_utf8_lossy(data)
+            );
+        }
+    }
+
+    #[test]
+    fn snap_names() {
+        for (summary, expected) in [
+            (
+                "Download snap \"hello\" (42) from channel \"stable\"",
+                Some("hello"),
+            ),
+            ("Mount snap \"core22\" (1380)", Some("core22")),
+            ("Run configure hook", None),
+            ("Broken \"quote", None),
+        ] {
+            assert_eq!(task_snap(summary), expected, "summary {summary:?}");
+        }
+    }
+
+    #[test]
+    fn change_progress() {
+        let change: Change = serde_json::from_value(serde_json::json!({
+            "status": "Doing",
+            "ready": false,
+            "tasks": [
+                {
+                    "kind": "link-snap",
+                    "summary": "Make snap \"world\" (7) available to the system",
+                    "status": "Done",
+                    "progress": { "label": "", "done": 40, "total": 40 },
+                },
+                {
+                    "kind": "download-snap",
+                    "summary": "Download snap \"hello\" (42) from channel \"stable\"",
+                    "status": "Doing",
+                    "progress": { "label": "hello", "done": 30, "total": 60 },
+                },
+                {
+                    "kind": "mount-snap",
+                    "summary": "Mount snap \"hello\" (42)",
+                    "status": "Do",
+                    "progress": { "label": "", "done": 0, "total": 0 },
+                },
+            ],
+        }))
+        .unwrap();
+        let progress = change.progress(&operation(OperationKind::Install, &["hello", "world"]));
+        assert_eq!(progress.percent, 70.0);
+        assert_eq!(progress.item_percents, [50.0, 100.0]);
+        assert_eq!(progress.phase_opt, Some(OperationPhase::Downloading));
+        assert_eq!(progress.package_opt.as_deref(), Some("hello"));
+        assert_eq!(progress.bytes_downloaded, 30);
+        assert_eq!(progress.bytes_total, 60);
+        assert_eq!(
+            progress.status,
+            "Download snap \"hello\" (42) from channel \"stable\""
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let message = |code| format!("test (code {code})");
+        for (kind, status_code, expected) in [
+            ("auth-cancelled", 403, BackendError::AuthCancelled),
+            ("login-required", 401, BackendError::AuthCancelled),
+            ("interaction-required", 403, BackendError::AuthCancelled),
+            (
+                "snap-not-found",
+                404,
+                BackendError::PackageNotFound(message(404)),
+            ),
+            (
+                "snap-not-installed",
+                400,
+                BackendError::PackageNotFound(message(400)),
+            ),
+            (
+                "snap-needs-classic",
+                400,
+                BackendError::NotSupported(message(400)),
+            ),
+            (
+                "insufficient-disk-space",
+                507,
+                BackendError::DiskFull(message(507)),
+            ),
+            (
+                "dns-failure",
+                400,
+                BackendError::NetworkUnavailable(message(400)),
+            ),
+            (
+                "network-timeout",
+                400,
+                BackendError::NetworkUnavailable(message(400)),
+            ),
+            ("", 401, BackendError::AuthCancelled),
+            ("", 500, BackendError::Other(message(500))),
+        ] {
+            let err = SnapdError {
+                message: "test".to_string(),
+                kind: kind.to_string(),
+            };
+            assert_eq!(
+                err.into_backend_error(status_code),
+                expected,
+                "kind {kind:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn error_response() {
+        let dir = tempfile::tempdir().unwrap();
+        let (snap, _requests) = serve(&dir, |_, _| {
+            serde_json::json!({
+                "type": "error",
+                "status-code": 404,
+                "result": { "message": "not found", "kind": "snap-not-found" },
+            })
+        });
+        let err = BackendError::from(snap.installed().unwrap_err());
+        assert_eq!(
+            err,
+            BackendError::PackageNotFound("not found (code 404)".to_string())
+        );
+    }
+
+    #[test]
+    fn install() {
+        let dir = tempfile::tempdir().unwrap();
+        let polls = Arc::new(Mutex::new(0));
+        let (snap, requests) = {
+            let polls = polls.clone();
+            serve(&dir, move |request, _| match request {
+                "POST /v2/snaps" => async_change("1"),
+                "GET /v2/changes/1" => {
+                    let mut polls = polls.lock().unwrap();
+                    *polls += 1;
+                    if *polls < 2 {
+                        change("Doing", false, 50)
+                    } else {
+                        change("Done", true, 100)
+                    }
+                }
+                _ => panic!("unexpected request {request}"),
+            })
+        };
+
+        let percents = Arc::new(Mutex::new(Vec::new()));
+        let f = {
+            let percents = percents.clone();
+            Box::new(move |progress: OperationProgress| {
+                percents.lock().unwrap().push(progress.percent);
+            })
+        };
+        snap.operation(
+            &operation(OperationKind::Install, &["hello"]),
+            &CancelToken::new(),
+            f,
+        )
+        .unwrap();
+
+        assert_eq!(*percents.lock().unwrap(), [50.0, 100.0]);
+        let requests = requests.lock().unwrap();
+        assert_eq!(
+            requests[0],
+            (
+                "POST /v2/snaps".to_string(),
+                serde_json::json!({ "action": "install", "snaps": ["hello"] })
+            )
+        );
+        assert_eq!(requests.len(), 3);
+    }
+
+    #[test]
+    fn abort() {
+        let dir = tempfile::tempdir().unwrap();
+        let aborted = Arc::new(Mutex::new(false));
+        let (snap, requests) = {
+            let aborted = aborted.clone();
+            serve(&dir, move |request, body| match request {
+                "POST /v2/snaps" => async_change("2"),
+                "POST /v2/changes/2" => {
+                    assert_eq!(body, &serde_json::json!({ "action": "abort" }));
+                    *aborted.lock().unwrap() = true;
+                    sync(serde_json::Value::Null)
+                }
+                "GET /v2/changes/2" => {
+                    if *aborted.lock().unwrap() {
+                        change("Undone", true, 50)
+                    } else {
+                        change("Doing", false, 50)
+                    }
+                }
+                _ => panic!("unexpected request {request}"),
+            })
+        };
+
+        // Cancel once the change reports progress
+        let cancel_token = CancelToken::new();
+        let f = {
+            let cancel_token = cancel_token.clone();
+            Box::new(move |_: OperationProgress| cancel_token.cancel())
+        };
+        let err = snap
+            .operation(
+                &operation(OperationKind::Uninstall { purge_data: false }, &["hello"]),
+                &cancel_token,
+                f,
+            )
+            .unwrap_err();
+
+        assert_eq!(err, BackendError::Cancelled);
+        assert!(
+            requests
+                .lock()
+                .unwrap()
+                .iter()
+                .any(|(request, _)| request == "POST /v2/changes/2")
+        );
+    }
+}
@@ -0,0 +1,552 @@
+// Copyright 2023 System76 <info@system76.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs,
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{AppId, BackendError, BackendName, Operation, OperationKind, Package, fl};
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HistoryOutcome {
+    Completed,
+    Cancelled,
+    Failed(String),
+}
+
+impl HistoryOutcome {
+    pub fn from_error(err: &BackendError) -> Self {
+        match err {
+            BackendError::Cancelled => Self::Cancelled,
+            _ => Self::Failed(err.to_string()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HistoryKind {
+    Install,
+    Uninstall,
+    Update,
+    Downgrade,
+    RepositoryAdd,
+    RepositoryRemove,
+    Deployment,
+}
+
+impl From<&OperationKind> for HistoryKind {
+    fn from(kind: &OperationKind) -> Self {
+        match kind {
+            OperationKind::Install => Self::Install,
+            OperationKind::Uninstall { .. } => Self::Uninstall,
+            OperationKind::Update => Self::Update,
+            OperationKind::Downgrade(_) => Self::Downgrade,
+            OperationKind::RepositoryAdd(_) => Self::RepositoryAdd,
+            OperationKind::RepositoryRemove(_, _) => Self::RepositoryRemove,
+            OperationKind::Deployment(_) => Self::Deployment,
+        }
+    }
+}
+
+/// Record of a single operation, stored as one line of JSON in the journal
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct HistoryEntry {
+    pub backend: String,
+    pub kind: HistoryKind,
+    /// Package IDs, or repository IDs for repository operations
+    pub ids: Vec<String>,
+    pub names: Vec<String>,
+    #[serde(default)]
+    pub source_ids: Vec<String>,
+    /// Versions in the same order as `ids`, empty if not installed or unknown
+    pub versions_before: Vec<String>,
+    pub versions_after: Vec<String>,
+    /// Backend specific revisions installed before the operation, used to undo updates
+    #[serde(default)]
+    pub revisions_before: Vec<String>,
+    /// Seconds since the Unix epoch
+    pub started: i64,
+    pub finished: i64,
+    pub outcome: HistoryOutcome,
+}
+
+impl HistoryEntry {
+    pub fn new(op: &Operation, installed_opt: Option<&[(BackendName, Package)]>) -> Self {
+        let (ids, names) = match &op.kind {
+            OperationKind::RepositoryAdd(adds) => (
+                adds.iter().map(|add| add.id.clone()).collect(),
+                adds.iter().map(|add| add.id.clone()).collect(),
+            ),
+            OperationKind::RepositoryRemove(rms, _force) => (
+                rms.iter().map(|rm| rm.id.clone()).collect(),
+                rms.iter().map(|rm| rm.name.clone()).collect(),
+            ),
+            OperationKind::Deployment(action) => (Vec::new(), vec![action.title()]),
+            _ => (
+                op.package_ids
+                    .iter()
+                    .map(|id| id.raw().to_string())
+                    .collect(),
+                op.infos.iter().map(|info| info.name.clone()).collect(),
+            ),
+        };
+        let installed: Vec<Option<&Package>> = op
+            .package_ids
+            .iter()
+            .map(|id| find_package(op.backend_name, id, installed_opt))
+            .collect();
+        Self {
+            backend: op.backend_name.to_string(),
+            kind: HistoryKind::from(&op.kind),
+            ids,
+            names,
+            source_ids: op.infos.iter().map(|info| info.source_id.clone()).collect(),
+            versions_before: installed
+                .iter()
+                .map(|package_opt| package_opt.map_or_else(String::new, |x| x.version.clone()))
+                .collect(),
+            versions_after: Vec::new(),
+            revisions_before: installed
+                .iter()
+                .map(|package_opt| {
+                    package_opt
+                        .and_then(|x| x.extra.get("revision").cloned())
+                        .unwrap_or_default()
+                })
+                .collect(),
+            started: chrono::Utc::now().timestamp(),
+            finished: 0,
+            outcome: HistoryOutcome::Completed,
+        }
+    }
+
+    pub fn title(&self) -> String {
+        let names = self.names.join(", ");
+        match self.kind {
+            HistoryKind::Install => fl!("history-install", names = names),
+            HistoryKind::Uninstall => fl!("history-uninstall", names = names),
+            HistoryKind::Update => fl!("history-update", names = names),
+            HistoryKind::Downgrade => fl!("history-downgrade", names = names),
+            HistoryKind::RepositoryAdd => fl!("history-repository-add", names = names),
+            HistoryKind::RepositoryRemove => fl!("history-repository-remove", names = names),
+            // Deployment changes are recorded with a full description
+            HistoryKind::Deployment => names,
+        }
+    }
+
+    /// Describe version changes, such as `1.0 → 1.1`
+    pub fn versions_text(&self) -> String {
+        let mut parts = Vec::new();
+        for (i, name) in self.names.iter().enumerate() {
+            let before = self.versions_before.get(i).map_or("", String::as_str);
+            let after = self.versions_after.get(i).map_or("", String::as_str);
+            if before.is_empty() && after.is_empty() {
+                continue;
+            }
+            let text = match (before.is_empty(), after.is_empty()) {
+                (false, false) => format!("{before} → {after}"),
+                (false, true) => before.to_string(),
+                _ => after.to_string(),
+            };
+            if self.names.len() > 1 {
+                parts.push(format!("{name} {text}"));
+            } else {
+                parts.push(text);
+            }
+        }
+        parts.join(", ")
+    }
+
+    pub fn outcome_text(&self) -> String {
+        match &self.outcome {
+            HistoryOutcome::Completed => fl!("history-completed"),
+            HistoryOutcome::Cancelled => fl!("cancelled"),
+            HistoryOutcome::Failed(err) => fl!("history-failed", error = err.as_str()),
+        }
+    }
+
+    /// Kind of operation that reverses this one, if possible
+    pub fn undo_kind(&self) -> Option<OperationKind> {
+        if self.outcome != HistoryOutcome::Completed {
+            return None;
+        }
+        match self.kind {
+            HistoryKind::Install => Some(OperationKind::Uninstall { purge_data: false }),
+            HistoryKind::Uninstall => Some(OperationKind::Install),
+            HistoryKind::Update => {
+                if self.revisions_before.len() != self.ids.len()
+                    || self.revisions_before.iter().any(|x| x.is_empty())
+                {
+                    return None;
+                }
+                Some(OperationKind::Downgrade(self.revisions_before.clone()))
+            }
+            HistoryKind::Downgrade
+            | HistoryKind::RepositoryAdd
+            | HistoryKind::RepositoryRemove
+            | HistoryKind::Deployment => None,
+        }
+    }
+
+    /// Returns true if the filter is empty or matches an ID or name, ignoring case
+    pub fn matches(&self, filter: &str) -> bool {
+        let filter = filter.trim().to_lowercase();
+        filter.is_empty()
+            || self
+                .ids
+                .iter()
+                .chain(self.names.iter())
+                .any(|x| x.to_lowercase().contains(&filter))
+    }
+}
+
+/// Journal of finished operations, persisted across restarts
+///
+/// The default history is only kept in memory.
+#[derive(Debug, Default)]
+pub struct History {
+    pub entries: Vec<HistoryEntry>,
+    // Entries for pending operations, by operation ID
+    pending: HashMap<u64, HistoryEntry>,
+    // Journal that finished entries are appended to
+    path_opt: Option<PathBuf>,
+}
+
+impl History {
+    /// Load the journal in the user data directory
+    pub fn load() -> Self {
+        match dirs::data_dir() {
+            Some(data_dir) => Self::load_from(&data_dir.join("cosmic-store").join("history.jsonl")),
+            None => {
+                log::warn!("failed to find user data directory");
+                Self::default()
+            }
+        }
+    }
+
+    /// Load a journal, skipping lines that cannot be parsed
+    pub fn load_from(path: &Path) -> Self {
+        let mut history = Self {
+            path_opt: Some(path.to_path_buf()),
+            ..Default::default()
+        };
+        let file = match fs::File::open(path) {
+            Ok(ok) => ok,
+            Err(err) => {
+                if err.kind() != std::io::ErrorKind::NotFound {
+                    log::warn!("failed to open history {:?}: {}", path, err);
+                }
+                return history;
+            }
+        };
+        for (i, line_res) in BufReader::new(file).lines().enumerate() {
+            let line = match line_res {
+                Ok(ok) => ok,
+                Err(err) => {
+                    log::warn!("failed to read history {:?}: {}", path, err);
+                    break;
+                }
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str(&line) {
+                Ok(entry) => history.entries.push(entry),
+                Err(err) => {
+                    log::warn!("invalid history entry {:?}:{}: {}", path, i + 1, err);
+                }
+            }
+        }
+        history
+    }
+
+    /// Index of the most recent package operation, if it can be undone
+    pub fn undo_index(&self) -> Option<usize> {
+        let (index, entry) = self.entries.iter().enumerate().rev().find(|(_, entry)| {
+            entry.outcome == HistoryOutcome::Completed
+                && matches!(
+                    entry.kind,
+                    HistoryKind::Install
+                        | HistoryKind::Uninstall
+                        | HistoryKind::Update
+                        | HistoryKind::Downgrade
+                )
+        })?;
+        entry.undo_kind().map(|_| index)
+    }
+
+    pub fn start(&mut self, id: u64, entry: HistoryEntry) {
+        self.pending.insert(id, entry);
+    }
+
+    pub fn finish(&mut self, id: u64, versions_after: Vec<String>, outcome: HistoryOutcome) {
+        let Some(mut entry) = self.pending.remove(&id) else {
+            return;
+        };
+        entry.versions_after = versions_after;
+        entry.finished = chrono::Utc::now().timestamp();
+        entry.outcome = outcome;
+        if let Some(path) = &self.path_opt
+            && let Err(err) = Self::append(path, &entry)
+        {
+            log::warn!("failed to save history entry to {:?}: {}", path, err);
+        }
+        self.entries.push(entry);
+    }
+
+    fn append(path: &Path, entry: &HistoryEntry) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut line = serde_json::to_string(entry)?;
+        line.push('\n');
+        // A single write keeps concurrent instances from interleaving lines
+        fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?
+            .write_all(line.as_bytes())?;
+        Ok(())
+    }
+}
+
+/// Versions of the packages in an operation, looked up from installed or updates lists
+fn package_versions(
+    op: &Operation,
+    packages_opt: Option<&[(BackendName, Package)]>,
+) -> Vec<String> {
+    op.package_ids
+        .iter()
+        .map(|id| {
+            find_package(op.backend_name, id, packages_opt)
+                .map_or_else(String::new, |package| package.version.clone())
+        })
+        .collect()
+}
+
+/// Expected versions of the packages after an operation completes
+pub fn versions_after(
+    op: &Operation,
+    updates_opt: Option<&[(BackendName, Package)]>,
+) -> Vec<String> {
+    match &op.kind {
+        OperationKind::Install => op
+            .infos
+            .iter()
+            .map(|info| {
+                info.releases
+                    .first()
+                    .map_or_else(String::new, |release| release.version.clone())
+            })
+            .collect(),
+        OperationKind::Update => package_versions(op, updates_opt),
+        OperationKind::Uninstall { .. }
+        | OperationKind::Downgrade(_)
+        | OperationKind::RepositoryAdd(_)
+        | OperationKind::RepositoryRemove(_, _)
+        | OperationKind::Deployment(_) => Vec::new(),
+    }
+}
+
+fn find_package<'a>(
+    backend_name: BackendName,
+    id: &AppId,
+    packages_opt: Option<&'a [(BackendName, Package)]>,
+) -> Option<&'a Package> {
+    packages_opt?
+        .iter()
+        .find(|(package_backend_name, package)| {
+            *package_backend_name == backend_name && package.id == *id
+        })
+        .map(|(_, package)| package)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AppInfo, DeploymentAction, RepositoryAdd, app_info::AppRelease};
+    use cosmic::widget;
+    use std::sync::Arc;
+
+    fn operation(kind: OperationKind) -> Operation {
+        Operation {
+            kind,
+            backend_name: BackendName::Fake,
+            package_ids: vec![AppId::new("com.example.Hello")],
+            infos: vec![Arc::new(AppInfo {
+                source_id: "fake".to_string(),
+                name: "Hello".to_string(),
+                releases: vec![AppRelease {
+                    timestamp: None,
+                    version: "2.0".to_string(),
+                    description: None,
+                    url: None,
+                }],
+                ..Default::default()
+            })],
+        }
+    }
+
+    fn packages(version: &str) -> Vec<(BackendName, Package)> {
+        let op = operation(OperationKind::Install);
+        vec![(
+            BackendName::Fake,
+            Package {
+                id: op.package_ids[0].clone(),
+                icon: widget::icon::from_name("package-x-generic").handle(),
+                info: op.infos[0].clone(),
+                version: version.to_string(),
+                extra: HashMap::from([("revision".to_string(), format!("rev-{version}"))]),
+            },
+        )]
+    }
+
+    fn completed(kind: OperationKind, installed: &[(BackendName, Package)]) -> HistoryEntry {
+        let mut history = History::default();
+        history.start(1, HistoryEntry::new(&operation(kind), Some(installed)));
+        history.finish(1, Vec::new(), HistoryOutcome::Completed);
+        history.entries.remove(0)
+    }
+
+    #[test]
+    fn undo() {
+        let installed = packages("1.0");
+        for (kind, undo_kind) in [
+            (
+                OperationKind::Install,
+                Some(OperationKind::Uninstall { purge_data: false }),
+            ),
+            (
+                OperationKind::Uninstall { purge_data: true },
+                Some(OperationKind::Install),
+            ),
+            (
+                OperationKind::Update,
+                Some(OperationKind::Downgrade(vec!["rev-1.0".to_string()])),
+            ),
+            (OperationKind::Downgrade(vec!["rev-0.9".to_string()]), None),
+            (
+                OperationKind::RepositoryAdd(vec![RepositoryAdd {
+                    id: "fake".to_string(),
+                    data: Vec::new(),
+                }]),
+                None,
+            ),
+            (OperationKind::Deployment(DeploymentAction::Rollback), None),
+        ] {
+            let entry = completed(kind.clone(), &installed);
+            assert_eq!(entry.undo_kind(), undo_kind, "kind {kind:?}");
+        }
+    }
+
+    #[test]
+    fn undo_update_needs_revisions() {
+        // Not installed before the update, so there is no revision to return to
+        let entry = completed(OperationKind::Update, &[]);
+        assert_eq!(entry.undo_kind(), None);
+    }
+
+    #[test]
+    fn undo_needs_completed() {
+        let mut history = History::default();
+        history.start(
+            1,
+            HistoryEntry::new(&operation(OperationKind::Install), None),
+        );
+        history.finish(1, Vec::new(), HistoryOutcome::Cancelled);
+        assert_eq!(history.entries[0].undo_kind(), None);
+        assert_eq!(history.undo_index(), None);
+    }
+
+    #[test]
+    fn undo_index_skips_repositories_and_deployments() {
+        let mut history = History::default();
+        history.entries.push(completed(OperationKind::Install, &[]));
+        history.entries.push(completed(
+            OperationKind::RepositoryAdd(vec![RepositoryAdd {
+                id: "fake".to_string(),
+                data: Vec::new(),
+            }]),
+            &[],
+        ));
+        history.entries.push(completed(
+            OperationKind::Deployment(DeploymentAction::CleanupPending),
+            &[],
+        ));
+        assert_eq!(history.undo_index(), Some(0));
+
+        history.entries.remove(0);
+        assert_eq!(history.undo_index(), None);
+    }
+
+    #[test]
+    fn versions() {
+        let updates = packages("1.1");
+        for (kind, expected) in [
+            (OperationKind::Install, vec!["2.0".to_string()]),
+            (OperationKind::Update, vec!["1.1".to_string()]),
+            (OperationKind::Uninstall { purge_data: false }, Vec::new()),
+            (
+                OperationKind::Downgrade(vec!["rev-1.0".to_string()]),
+                Vec::new(),
+            ),
+        ] {
+            assert_eq!(
+                versions_after(&operation(kind.clone()), Some(&updates)),
+                expected,
+                "kind {kind:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn load_skips_malformed_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history.jsonl");
+
+        let mut history = History::load_from(&path);
+        assert!(history.entries.is_empty());
+        history.start(
+            1,
+            HistoryEntry::new(&operation(OperationKind::Install), None),
+        );
+        history.finish(1, vec!["2.0".to_string()], HistoryOutcome::Completed);
+
+        let mut file = fs::OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"not json\n\n{\"kind\":\"install\"}\n")
+            .unwrap();
+        drop(file);
+
+        history.start(
+            2,
+            HistoryEntry::new(
+                &operation(OperationKind::Uninstall { purge_data: false }),
+                None,
+            ),
+        );
+        history.finish(2, Vec::new(), HistoryOutcome::Failed("test".to_string()));
+
+        let loaded = History::load_from(&path);
+        assert_eq!(loaded.entries.len(), 2);
+        assert_eq!(loaded.entries[0].kind, HistoryKind::Install);
+        assert_eq!(loaded.entries[0].versions_after, ["2.0"]);
+        assert_eq!(loaded.entries[1].kind, HistoryKind::Uninstall);
+        assert_eq!(
+            loaded.entries[1].outcome,
+            HistoryOutcome::Failed("test".to_string())
+        );
+    }
+
+    #[test]
+    fn kind_is_snake_case() {
+        let entry = completed(OperationKind::RepositoryRemove(Vec::new(), false), &[]);
+        let json = serde_json::to_string(&entry).unwrap();
+        assert!(json.contains(r#""kind":"repository_remove""#), "{json}");
+    }
+}
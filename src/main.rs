@@ -33,7 +33,7 @@ use std::{
     fmt::Debug,
     future::pending,
     hash::Hash,
-    path::Path,
+    path::{Path, PathBuf},
     sync::{Arc, Mutex, atomic},
     time::Instant,
 };
@@ -47,7 +47,10 @@ mod app_info;
 use appstream_cache::AppstreamCache;
 mod appstream_cache;
 
-use backend::{BackendName, Backends, Package};
+use backend::{
+    BackendError, BackendName, Backends, CancelToken, Capabilities, Deployment, DeploymentState,
+    Package, Runtime,
+};
 mod backend;
 
 use config::{AppTheme, CONFIG_VERSION, Config};
@@ -59,6 +62,9 @@ mod editors_choice;
 use gstreamer::GStreamerCodec;
 mod gstreamer;
 
+use history::{History, HistoryEntry};
+mod history;
+
 mod icon_cache;
 
 use key_bind::{KeyBind, key_binds};
@@ -69,14 +75,25 @@ mod localize;
 #[cfg(feature = "logind")]
 mod logind;
 
-use operation::{Operation, OperationKind, RepositoryAdd, RepositoryRemove, RepositoryRemoveError};
+use operation::{
+    DeploymentAction, Operation, OperationKind, OperationPhase, OperationProgress, RepositoryAdd,
+    RepositoryRemove, RepositoryRemoveError,
+};
 mod operation;
 
+use permission::{Permission, Permissions};
+mod permission;
+
 use priority::priority;
 
 use crate::backend::Backend;
 mod priority;
 
+mod queue;
+
+use scheduler::Scheduler;
+mod scheduler;
+
 mod stats;
 
 use explore::ExplorePage;
@@ -291,6 +308,9 @@ pub enum Message {
     DialogCancel,
     DialogConfirm,
     DialogPage(DialogPage),
+    DeploymentAction(BackendName, DeploymentAction),
+    DeploymentSwitchInput(BackendName, String),
+    Deployments(BackendName, Vec<Deployment>),
     ExplorePage(Option<ExplorePage>),
     AllExploreResults(
         HashMap<ExplorePage, Vec<SearchResult>>,
@@ -300,6 +320,8 @@ pub enum Message {
     GStreamerExit(GStreamerExitCode),
     GStreamerInstall,
     GStreamerToggle(usize),
+    HistoryFilter(String),
+    HistoryUndo(usize),
     AppsUpdated(Arc<Apps>, Arc<CategoryIndex>),
     AppsUpdatedFinished,
     /// Use to unset `App::updated_apps_scheduled`
@@ -307,21 +329,31 @@ pub enum Message {
     Installed((BackendName, Vec<(BackendName, Package)>)),
     InstalledResults(Vec<SearchResult>),
     InstalledIconsLoaded(Vec<(usize, widget::icon::Handle)>),
+    RebootRequired(BackendName, bool),
     Key(Modifiers, Key, Option<SmolStr>),
     LaunchUrl(String),
     MaybeExit,
+    MoveScope(BackendName, AppId, Arc<AppInfo>),
     #[cfg(feature = "notify")]
     Notification(Arc<Mutex<notify_rust::NotificationHandle>>),
     OpenDesktopId(String),
     Operation(OperationKind, BackendName, AppId, Arc<AppInfo>),
     PeriodicUpdateCheck,
+    PendingCancel(u64),
     PendingComplete(u64),
     PendingDismiss,
-    PendingError(u64, String),
-    PendingProgress(u64, f32),
+    PendingError(u64, BackendError),
+    PendingMoveDown(u64),
+    PendingMoveUp(u64),
+    PendingRetry(u64),
+    PendingProgress(u64, OperationProgress),
+    PermissionOverride(String, Permission, Option<bool>),
+    PermissionsReset(String),
     RepositoryAdd(BackendName, Vec<RepositoryAdd>),
     RepositoryAddDialog(BackendName),
     RepositoryRemove(BackendName, Vec<RepositoryRemove>),
+    RemoveUnusedRuntimes(BackendName),
+    Runtimes(BackendName, Vec<Runtime>),
     ScrollView(scrollable::Viewport),
     SearchActivate,
     SearchClear,
@@ -342,10 +374,15 @@ pub enum Message {
     SelectExploreResult(ExplorePage, usize),
     SelectSearchResult(usize),
     SelectedAddonsViewMore(bool),
+    SelectedPermissions(BackendName, AppId, String, Permissions),
+    SelectedRef(usize),
+    SelectedRefs(BackendName, AppId, String, Vec<String>),
+    SelectedScope(BackendName),
     SelectedScreenshot(usize, String, Vec<u8>),
     SelectedScreenshotShown(usize),
     ToggleUninstallPurgeData(bool),
     SelectedSource(usize),
+    SwitchRef(BackendName, AppId, Arc<AppInfo>, Arc<AppInfo>),
     SystemThemeModeChange(cosmic_theme::ThemeMode),
     ToggleContextPage(ContextPage),
     UpdateAll,
@@ -358,9 +395,12 @@ pub enum Message {
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum ContextPage {
+    Deployments,
+    History,
     Operations,
     ReleaseNotes(usize, String),
     Repositories,
+    Runtimes,
     Settings,
 }
 
@@ -369,6 +409,7 @@ pub enum DialogPage {
     FailedOperation(u64),
     RepositoryAddError(String),
     RepositoryRemove(BackendName, RepositoryRemoveError),
+    ResumeOperations,
     Uninstall(BackendName, AppId, Arc<AppInfo>),
     Place(AppId),
 }
@@ -401,6 +442,30 @@ impl AsRef<str> for SelectedSource {
     }
 }
 
+#[derive(Clone, Debug)]
+pub struct SelectedRef {
+    pub flatpak_ref: String,
+    pub name: String,
+}
+
+impl SelectedRef {
+    pub fn new(flatpak_ref: String) -> Self {
+        // Refs are formatted as kind/name/arch/branch
+        let mut parts = flatpak_ref.split('/').skip(2);
+        let arch = parts.next().unwrap_or_default();
+        let branch = parts.next().unwrap_or_default();
+        let name = fl!("flatpak-ref", branch = branch, arch = arch);
+        SelectedRef { flatpak_ref, name }
+    }
+}
+
+// For use in dropdown widget
+impl AsRef<str> for SelectedRef {
+    fn as_ref(&self) -> &str {
+        &self.name
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Selected {
     pub backend_name: BackendName,
@@ -412,6 +477,10 @@ pub struct Selected {
     pub sources: Vec<SelectedSource>,
     pub addons: Vec<(AppId, Arc<AppInfo>)>,
     pub addons_view_more: bool,
+    /// Sandbox permissions, loaded after selecting
+    pub permissions_opt: Option<Permissions>,
+    /// Branches and arches to choose from, loaded after selecting
+    pub refs: Vec<SelectedRef>,
 }
 
 /// The [`App`] stores application-specific state.
@@ -422,6 +491,7 @@ pub struct App {
     pub mode: Mode,
     pub locale: String,
     pub app_themes: Vec<String>,
+    pub install_scopes: Vec<String>,
     pub apps: Arc<Apps>,
     pub category_index: Arc<CategoryIndex>,
     pub backends: Backends,
@@ -433,10 +503,26 @@ pub struct App {
     #[cfg(feature = "notify")]
     pub notification_opt: Option<Arc<Mutex<notify_rust::NotificationHandle>>>,
     pub pending_operation_id: u64,
-    pub pending_operations: BTreeMap<u64, (Operation, f32)>,
+    pub pending_operations: BTreeMap<u64, (Operation, OperationProgress)>,
+    pub pending_cancel_tokens: HashMap<u64, CancelToken>,
+    pub scheduler: Scheduler,
     pub progress_operations: BTreeSet<u64>,
     pub complete_operations: BTreeMap<u64, Operation>,
-    pub failed_operations: BTreeMap<u64, (Operation, f32, String)>,
+    pub failed_operations: BTreeMap<u64, (Operation, OperationProgress, BackendError)>,
+    /// Operations to start once another completes, such as removing an app after moving it
+    pub after_operations: HashMap<u64, Operation>,
+    pub history: History,
+    pub history_filter: String,
+    /// Deployments of backends that support them, such as rpm-ostree
+    pub deployments: BTreeMap<BackendName, Vec<Deployment>>,
+    /// Image or ref entered to switch deployments to, for each backend
+    pub deployment_switch_inputs: BTreeMap<BackendName, String>,
+    /// Runtimes and extensions of backends that support them, such as flatpak
+    pub runtimes: BTreeMap<BackendName, Vec<Runtime>>,
+    /// Operations left over from the last run and their follow-ups, waiting for the user to resume or discard them
+    pub queued_operations: Vec<(Operation, Option<Operation>)>,
+    /// File the operation queue is persisted to, if any
+    pub queue_path_opt: Option<PathBuf>,
     pub repos_changing: Vec<(BackendName, String, bool)>,
     pub scrollable_id: widget::Id,
     pub scroll_views: HashMap<ScrollContext, scrollable::Viewport>,
@@ -452,6 +538,8 @@ pub struct App {
     pub waiting_installed: Vec<(BackendName, String, AppId)>,
     //TODO: use hashset?
     pub waiting_updates: Vec<(BackendName, String, AppId)>,
+    /// Backends with changes that take effect after a reboot
+    pub reboot_required: BTreeSet<BackendName>,
     pub category_results: Option<(&'static [Category], Vec<SearchResult>)>,
     pub category_load_start: Option<Instant>,
     pub explore_results: HashMap<ExplorePage, Vec<SearchResult>>,
@@ -535,7 +623,7 @@ impl App {
         });
     }
 
-    fn operation(&mut self, operation: Operation) {
+    fn operation(&mut self, operation: Operation) -> u64 {
         match &operation.kind {
             OperationKind::RepositoryAdd(adds) => {
                 for add in adds.iter() {
@@ -554,8 +642,59 @@ impl App {
 
         let id = self.pending_operation_id;
         self.pending_operation_id += 1;
+        self.history
+            .start(id, HistoryEntry::new(&operation, self.installed.as_deref()));
         self.progress_operations.insert(id);
-        self.pending_operations.insert(id, (operation, 0.0));
+        self.scheduler.push(id, operation.backend_name);
+        self.pending_operations.insert(id, (operation, OperationProgress::default()));
+        self.pending_cancel_tokens.insert(id, CancelToken::new());
+        self.schedule();
+        self.save_queue();
+        id
+    }
+
+    /// Install a package, then uninstall another once that succeeds, keeping its app data
+    fn replace_operation(&mut self, install: Operation, uninstall: Operation) {
+        let install_id = self.operation(install);
+        self.after_operations.insert(install_id, uninstall);
+        self.save_queue();
+    }
+
+    /// Start operations for idle backends, a batch shares one cancel token
+    fn schedule(&mut self) {
+        for ids in self.scheduler.schedule(&self.pending_operations) {
+            let Some(cancel_token) = ids
+                .first()
+                .and_then(|id| self.pending_cancel_tokens.get(id))
+                .cloned()
+            else {
+                continue;
+            };
+            for id in ids.iter().skip(1) {
+                self.pending_cancel_tokens.insert(*id, cancel_token.clone());
+            }
+        }
+    }
+
+    /// Persist pending operations, and any not yet resumed, so they survive restarts
+    fn save_queue(&self) {
+        // Codec installs are tied to the requesting app and are not resumed
+        if let Mode::GStreamer { .. } = self.mode {
+            return;
+        }
+        let Some(queue_path) = &self.queue_path_opt else {
+            return;
+        };
+        queue::save(
+            queue_path,
+            self.queued_operations
+                .iter()
+                .map(|(op, after_opt)| (op, after_opt.as_ref()))
+                .chain(self.scheduler.order().iter().filter_map(|id| {
+                    let (op, _) = self.pending_operations.get(id)?;
+                    Some((op, self.after_operations.get(id)))
+                })),
+        );
     }
 
     fn generic_search<F: Fn(&AppId, &AppInfo, bool) -> Option<i64> + Send + Sync>(
@@ -1114,6 +1253,18 @@ impl App {
         );
         let sources = self.selected_sources(backend_name, &id, &info);
         let addons = self.selected_addons(backend_name, &id, &info);
+        // Show the installed flatpak branch, which may not be the one listed in appstream
+        let info = match self.installed_package(backend_name, &id, &info) {
+            Some(package)
+                if backend_name.is_flatpak() && package.info.flatpak_refs != info.flatpak_refs =>
+            {
+                Arc::new(AppInfo {
+                    flatpak_refs: package.info.flatpak_refs.clone(),
+                    ..(*info).clone()
+                })
+            }
+            _ => info,
+        };
         self.selected_opt = Some(Selected {
             backend_name,
             id,
@@ -1124,8 +1275,77 @@ impl App {
             sources,
             addons,
             addons_view_more: false,
+            permissions_opt: None,
+            refs: Vec::new(),
         });
-        self.update_scroll()
+        Task::batch([
+            self.update_scroll(),
+            self.load_permissions(),
+            self.load_refs(),
+        ])
+    }
+
+    fn load_refs(&self) -> Task<Message> {
+        let Some(selected) = &self.selected_opt else {
+            return Task::none();
+        };
+        let Some(backend) = self.backends.get(&selected.backend_name).cloned() else {
+            return Task::none();
+        };
+        let backend_name = selected.backend_name;
+        let id = selected.id.clone();
+        let info = selected.info.clone();
+        Task::perform(
+            async move {
+                tokio::task::spawn_blocking(move || match backend.app_refs(&info) {
+                    Ok(refs) => action::app(Message::SelectedRefs(
+                        backend_name,
+                        id,
+                        info.source_id.clone(),
+                        refs,
+                    )),
+                    Err(err) => {
+                        log::warn!("failed to load refs of {:?}: {}", id, err);
+                        action::none()
+                    }
+                })
+                .await
+                .unwrap_or(action::none())
+            },
+            |x| x,
+        )
+    }
+
+    fn load_permissions(&self) -> Task<Message> {
+        let Some(selected) = &self.selected_opt else {
+            return Task::none();
+        };
+        let Some(backend) = self.backends.get(&selected.backend_name).cloned() else {
+            return Task::none();
+        };
+        let backend_name = selected.backend_name;
+        let id = selected.id.clone();
+        let info = selected.info.clone();
+        Task::perform(
+            async move {
+                tokio::task::spawn_blocking(move || match backend.permissions(&info) {
+                    Ok(Some(permissions)) => action::app(Message::SelectedPermissions(
+                        backend_name,
+                        id,
+                        info.source_id.clone(),
+                        permissions,
+                    )),
+                    Ok(None) => action::none(),
+                    Err(err) => {
+                        log::warn!("failed to load permissions of {:?}: {}", id, err);
+                        action::none()
+                    }
+                })
+                .await
+                .unwrap_or(action::none())
+            },
+            |x| x,
+        )
     }
 
     fn scroll_context(&self) -> ScrollContext {
@@ -1220,130 +1440,198 @@ impl App {
         Self::is_installed_inner(&self.installed, backend_name, id, info)
     }
 
-    fn update_apps(&mut self) -> Task<Message> {
-        self.update_apps_scheduled = false;
-        self.update_apps_in_progress = true;
-        let backends = self.backends.clone();
-        let installed = self.installed.clone();
+    /// Returns the installed package of an app, which has details such as the flatpak branch
+    pub fn installed_package(
+        &self,
+        backend_name: BackendName,
+        id: &AppId,
+        info: &AppInfo,
+    ) -> Option<&Package> {
+        self.installed
+            .as_ref()?
+            .iter()
+            .find(|(installed_backend_name, package)| {
+                *installed_backend_name == backend_name
+                    && package.info.source_id == info.source_id
+                    && &package.id == id
+            })
+            .map(|(_, package)| package)
+    }
 
-        Task::perform(
-            async move {
-                tokio::task::spawn_blocking(move || {
-                    log::debug!("update_apps start");
-                    let start = Instant::now();
-                    let mut apps = Apps::new();
-
-                    let entry_sort = |a: &AppEntry, b: &AppEntry, id: &AppId| {
-                        // Sort with installed first
-                        match b.installed.cmp(&a.installed) {
-                            cmp::Ordering::Equal => {
-                                // Sort by highest priority first to lowest priority
-                                let a_priority = priority(a.backend_name, &a.info.source_id, id);
-                                let b_priority = priority(b.backend_name, &b.info.source_id, id);
-                                match b_priority.cmp(&a_priority) {
-                                    cmp::Ordering::Equal => {
-                                        match LANGUAGE_SORTER
-                                            .compare(&a.info.source_id, &b.info.source_id)
-                                        {
-                                            cmp::Ordering::Equal => {
-                                                a.backend_name.cmp(&b.backend_name)
-                                            }
-                                            ordering => ordering,
-                                        }
-                                    }
-                                    ordering => ordering,
-                                }
-                            }
-                            ordering => ordering,
-                        }
-                    };
+    /// Returns the capabilities of a backend, which are empty if it is not loaded
+    pub fn backend_capabilities(&self, backend_name: BackendName) -> Capabilities {
+        self.backends
+            .get(&backend_name)
+            .map_or(Capabilities::empty(), |backend| backend.capabilities())
+    }
 
-                    // Collect all entries from backends in parallel
-                    let collect_start = Instant::now();
-                    let installed_ref = &installed;
-                    let all_entries: Vec<(AppId, AppEntry)> = backends
-                        .par_iter()
-                        .flat_map(|(backend_name, backend)| {
-                            backend
-                                .info_caches()
-                                .iter()
-                                .flat_map(|appstream_cache| {
-                                    appstream_cache.infos.iter().map(|(id, info)| {
-                                        (
-                                            id.clone(),
-                                            AppEntry {
-                                                backend_name: *backend_name,
-                                                info: info.clone(),
-                                                installed: Self::is_installed_inner(
-                                                    installed_ref,
-                                                    *backend_name,
-                                                    id,
-                                                    info,
-                                                ),
-                                            },
-                                        )
-                                    })
-                                })
-                                .collect::<Vec<_>>()
-                        })
-                        .collect();
+    /// Returns the operation reversing a history entry, if its backend supports it
+    pub fn undo_operation(&self, entry: &HistoryEntry) -> Option<Operation> {
+        let backend_name: BackendName = entry.backend.parse().ok()?;
+        let kind = entry.undo_kind()?;
+        let capability = match kind {
+            OperationKind::Install => Capabilities::INSTALL,
+            OperationKind::Uninstall { .. } => Capabilities::UNINSTALL,
+            _ => Capabilities::DOWNGRADE,
+        };
+        if !self.backend_capabilities(backend_name).contains(capability) {
+            return None;
+        }
 
-                    let entry_count = all_entries.len();
+        let mut package_ids = Vec::with_capacity(entry.ids.len());
+        let mut infos = Vec::with_capacity(entry.ids.len());
+        for (id_raw, source_id) in entry.ids.iter().zip(entry.source_ids.iter()) {
+            let id = AppId::new(id_raw);
+            // Use the same source, undo is not possible if it no longer provides the app
+            let info = self.apps.get(&id).and_then(|app_entries| {
+                app_entries
+                    .iter()
+                    .find(|x| x.backend_name == backend_name && x.info.source_id == *source_id)
+                    .map(|x| x.info.clone())
+            })?;
+            package_ids.push(id);
+            infos.push(info);
+        }
+        if package_ids.is_empty() {
+            return None;
+        }
 
-                    // Merge entries into HashMap
-                    for (id, entry) in all_entries {
-                        apps.entry(id).or_default().push(entry);
-                    }
-                    log::debug!(
-                        "update_apps: collected {} entries in {:?}",
-                        entry_count,
-                        collect_start.elapsed()
-                    );
+        Some(Operation {
+            kind,
+            backend_name,
+            package_ids,
+            infos,
+        })
+    }
 
-                    // Manually insert system apps
-                    if let Some(installed) = &installed {
-                        for (backend_name, package) in installed {
-                            if package.id.is_system() {
-                                apps.entry(package.id.clone()).or_default().push(AppEntry {
-                                    backend_name: *backend_name,
-                                    info: package.info.clone(),
-                                    installed: true,
-                                });
+    /// Collect the apps of all backends, sorted by preference, and index them by category
+    fn build_apps(
+        backends: &Backends,
+        installed: &Option<Vec<(BackendName, Package)>>,
+    ) -> (Apps, CategoryIndex) {
+        log::debug!("update_apps start");
+        let start = Instant::now();
+        let mut apps = Apps::new();
+
+        let entry_sort = |a: &AppEntry, b: &AppEntry, id: &AppId| {
+            // Sort with installed first
+            match b.installed.cmp(&a.installed) {
+                cmp::Ordering::Equal => {
+                    // Sort by highest priority first to lowest priority
+                    let a_priority = priority(a.backend_name, &a.info.source_id, id);
+                    let b_priority = priority(b.backend_name, &b.info.source_id, id);
+                    match b_priority.cmp(&a_priority) {
+                        cmp::Ordering::Equal => {
+                            match LANGUAGE_SORTER.compare(&a.info.source_id, &b.info.source_id) {
+                                cmp::Ordering::Equal => a.backend_name.cmp(&b.backend_name),
+                                ordering => ordering,
                             }
                         }
+                        ordering => ordering,
                     }
+                }
+                ordering => ordering,
+            }
+        };
+
+        // Collect all entries from backends in parallel
+        let collect_start = Instant::now();
+        let all_entries: Vec<(AppId, AppEntry)> = backends
+            .par_iter()
+            .flat_map(|(backend_name, backend)| {
+                backend
+                    .info_caches()
+                    .iter()
+                    .flat_map(|appstream_cache| {
+                        appstream_cache.infos.iter().map(|(id, info)| {
+                            (
+                                id.clone(),
+                                AppEntry {
+                                    backend_name: *backend_name,
+                                    info: info.clone(),
+                                    installed: Self::is_installed_inner(
+                                        installed,
+                                        *backend_name,
+                                        id,
+                                        info,
+                                    ),
+                                },
+                            )
+                        })
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect();
 
-                    // Sort all entries once at the end (in parallel)
-                    let sort_start = Instant::now();
-                    apps.par_iter_mut().for_each(|(id, entries)| {
-                        entries.sort_unstable_by(|a, b| entry_sort(a, b, id));
+        let entry_count = all_entries.len();
+
+        // Merge entries into HashMap
+        for (id, entry) in all_entries {
+            apps.entry(id).or_default().push(entry);
+        }
+        log::debug!(
+            "update_apps: collected {} entries in {:?}",
+            entry_count,
+            collect_start.elapsed()
+        );
+
+        // Manually insert system apps
+        if let Some(installed) = installed {
+            for (backend_name, package) in installed {
+                if package.id.is_system() {
+                    apps.entry(package.id.clone()).or_default().push(AppEntry {
+                        backend_name: *backend_name,
+                        info: package.info.clone(),
+                        installed: true,
                     });
-                    log::debug!("update_apps: sorted entries in {:?}", sort_start.elapsed());
-
-                    // Build category index for fast category lookups (only desktop apps)
-                    let mut category_index = CategoryIndex::new();
-                    for (id, entries) in apps.iter() {
-                        // Use the first entry (highest priority) for category indexing
-                        if let Some(entry) = entries.first() {
-                            // Only index desktop applications
-                            if matches!(entry.info.kind, AppKind::DesktopApplication) {
-                                for category in &entry.info.categories {
-                                    category_index
-                                        .entry(category.clone())
-                                        .or_default()
-                                        .push(id.clone());
-                                }
-                            }
-                        }
+                }
+            }
+        }
+
+        // Sort all entries once at the end (in parallel)
+        let sort_start = Instant::now();
+        apps.par_iter_mut().for_each(|(id, entries)| {
+            entries.sort_unstable_by(|a, b| entry_sort(a, b, id));
+        });
+        log::debug!("update_apps: sorted entries in {:?}", sort_start.elapsed());
+
+        // Build category index for fast category lookups (only desktop apps)
+        let mut category_index = CategoryIndex::new();
+        for (id, entries) in apps.iter() {
+            // Use the first entry (highest priority) for category indexing
+            if let Some(entry) = entries.first() {
+                // Only index desktop applications
+                if matches!(entry.info.kind, AppKind::DesktopApplication) {
+                    for category in &entry.info.categories {
+                        category_index
+                            .entry(category.clone())
+                            .or_default()
+                            .push(id.clone());
                     }
+                }
+            }
+        }
 
-                    let duration = start.elapsed();
-                    log::info!(
-                        "update_apps: built app cache with {} ids in {:?}",
-                        apps.len(),
-                        duration
-                    );
+        let duration = start.elapsed();
+        log::info!(
+            "update_apps: built app cache with {} ids in {:?}",
+            apps.len(),
+            duration
+        );
+
+        (apps, category_index)
+    }
 
+    fn update_apps(&mut self) -> Task<Message> {
+        self.update_apps_scheduled = false;
+        self.update_apps_in_progress = true;
+        let backends = self.backends.clone();
+        let installed = self.installed.clone();
+
+        Task::perform(
+            async move {
+                tokio::task::spawn_blocking(move || {
+                    let (apps, category_index) = Self::build_apps(&backends, &installed);
                     action::app(Message::AppsUpdated(
                         Arc::new(apps),
                         Arc::new(category_index),
@@ -1361,14 +1649,17 @@ impl App {
         backend_name: BackendName,
         backend: Arc<dyn Backend>,
     ) -> Task<Message> {
-        Task::perform(
+        let installed_backend = backend.clone();
+        let deployments_backend = backend.clone();
+        let runtimes_backend = backend.clone();
+        let installed_task = Task::perform(
             async move {
                 tokio::task::spawn_blocking(move || {
                     log::debug!("update_backend_installed {backend_name}: starting");
                     let collect_start = Instant::now();
                     let installed: Vec<_> = {
                         let start = Instant::now();
-                        let result: Vec<_> = match backend.installed() {
+                        let result: Vec<_> = match installed_backend.installed() {
                             Ok(packages) => packages
                                 .into_iter()
                                 .map(|package| (backend_name, package))
@@ -1393,7 +1684,78 @@ impl App {
                 .unwrap_or(action::none())
             },
             |x| x,
-        )
+        );
+        let reboot_task = Task::perform(
+            async move {
+                tokio::task::spawn_blocking(move || {
+                    let reboot_required = match backend.reboot_required() {
+                        Ok(ok) => ok,
+                        Err(err) => {
+                            log::warn!(
+                                "failed to check if {} requires reboot: {}",
+                                backend_name,
+                                err
+                            );
+                            false
+                        }
+                    };
+                    action::app(Message::RebootRequired(backend_name, reboot_required))
+                })
+                .await
+                .unwrap_or(action::none())
+            },
+            |x| x,
+        );
+        let mut tasks = vec![installed_task, reboot_task];
+        if deployments_backend
+            .capabilities()
+            .contains(Capabilities::DEPLOYMENTS)
+        {
+            tasks.push(Task::perform(
+                async move {
+                    tokio::task::spawn_blocking(move || {
+                        let deployments = match deployments_backend.deployments() {
+                            Ok(ok) => ok,
+                            Err(err) => {
+                                log::warn!(
+                                    "failed to list deployments of {}: {}",
+                                    backend_name,
+                                    err
+                                );
+                                Vec::new()
+                            }
+                        };
+                        action::app(Message::Deployments(backend_name, deployments))
+                    })
+                    .await
+                    .unwrap_or(action::none())
+                },
+                |x| x,
+            ));
+        }
+        if runtimes_backend
+            .capabilities()
+            .contains(Capabilities::RUNTIMES)
+        {
+            tasks.push(Task::perform(
+                async move {
+                    tokio::task::spawn_blocking(move || {
+                        let runtimes = match runtimes_backend.runtimes() {
+                            Ok(ok) => ok,
+                            Err(err) => {
+                                log::warn!("failed to list runtimes of {}: {}", backend_name, err);
+                                Vec::new()
+                            }
+                        };
+                        action::app(Message::Runtimes(backend_name, runtimes))
+                    })
+                    .await
+                    .unwrap_or(action::none())
+                },
+                |x| x,
+            ));
+        }
+        Task::batch(tasks)
     }
 
     fn update_backend_updates(
@@ -1510,6 +1872,9 @@ impl App {
                     let start = Instant::now();
                     let mut packages = Vec::new();
                     for (backend_name, backend) in backends.iter() {
+                        if !backend.capabilities().contains(Capabilities::FILE_INSTALL) {
+                            continue;
+                        }
                         match backend.file_packages(&path) {
                             Ok(backend_packages) => {
                                 for package in backend_packages {
@@ -1566,6 +1931,9 @@ impl App {
                     let start = Instant::now();
                     let mut packages = Vec::new();
                     for (backend_name, backend) in backends.iter() {
+                        if !backend.capabilities().contains(Capabilities::GSTREAMER) {
+                            continue;
+                        }
                         match backend.gstreamer_packages(&gstreamer_codec) {
                             Ok(backend_packages) => {
                                 for package in backend_packages {
@@ -1652,6 +2020,270 @@ impl App {
         }
     }
 
+    fn deployments(&self) -> Element<'_, Message> {
+        let cosmic_theme::Spacing {
+            space_xxs, space_m, ..
+        } = theme::active().cosmic().spacing;
+
+        let mut children = Vec::with_capacity(self.deployments.len());
+        for (backend_name, deployments) in self.deployments.iter() {
+            // Only one change at a time, as each one rewrites the deployment list
+            let busy = self
+                .pending_operations
+                .values()
+                .any(|(op, _)| op.backend_name == *backend_name);
+            let capabilities = self.backend_capabilities(*backend_name);
+            let mut section = widget::settings::section().title(backend_name.to_string());
+            for deployment in deployments.iter() {
+                let state = match deployment.state {
+                    DeploymentState::Booted => fl!("deployment-booted"),
+                    DeploymentState::Pending => fl!("deployment-pending-restart"),
+                    DeploymentState::Rollback => fl!("deployment-rollback-state"),
+                    DeploymentState::Other => String::new(),
+                };
+                let timestamp = chrono::DateTime::from_timestamp(deployment.timestamp, 0)
+                    .map(|dt| {
+                        dt.with_timezone(&chrono::Local)
+                            .format("%Y-%m-%d %H:%M")
+                            .to_string()
+                    })
+                    .unwrap_or_default();
+                let caption = [
+                    state.as_str(),
+                    timestamp.as_str(),
+                    deployment.origin.as_str(),
+                ]
+                .into_iter()
+                .filter(|x| !x.is_empty())
+                .collect::<Vec<_>>()
+                .join(" · ");
+
+                let mut column = widget::column::with_capacity(3)
+                    .push(widget::text::body(&deployment.version))
+                    .push(widget::text::caption(caption))
+                    .spacing(space_xxs)
+                    .width(Length::Fill);
+                if !deployment.diff.is_empty() {
+                    column = column.push(widget::text::caption(deployment.diff.join("\n")));
+                }
+
+                let action_opt = match deployment.state {
+                    DeploymentState::Pending
+                        if capabilities.contains(Capabilities::DEPLOYMENT_CLEANUP) =>
+                    {
+                        Some((fl!("remove-pending"), DeploymentAction::CleanupPending))
+                    }
+                    DeploymentState::Rollback => {
+                        Some((fl!("rollback"), DeploymentAction::Rollback))
+                    }
+                    _ => None,
+                };
+                let pin_opt = capabilities
+                    .contains(Capabilities::DEPLOYMENT_PIN)
+                    .then(|| {
+                        let label = if deployment.pinned {
+                            fl!("unpin")
+                        } else {
+                            fl!("pin")
+                        };
+                        let action = DeploymentAction::Pin {
+                            id: deployment.id.clone(),
+                            pinned: !deployment.pinned,
+                        };
+                        (label, action)
+                    });
+                section = section.add(
+                    widget::row::with_capacity(3)
+                        .align_y(Alignment::Center)
+                        .spacing(space_xxs)
+                        .push(column)
+                        .push_maybe(action_opt.map(|(label, action)| {
+                            widget::button::standard(label).on_press_maybe(
+                                (!busy).then(|| Message::DeploymentAction(*backend_name, action)),
+                            )
+                        }))
+                        .push_maybe(pin_opt.map(|(label, action)| {
+                            widget::button::standard(label).on_press_maybe(
+                                (!busy).then(|| Message::DeploymentAction(*backend_name, action)),
+                            )
+                        })),
+                );
+            }
+            let switch_input = self
+                .deployment_switch_inputs
+                .get(backend_name)
+                .map_or("", String::as_str);
+            let switch_image = switch_input.trim();
+            section = section.add(
+                widget::row::with_capacity(2)
+                    .align_y(Alignment::Center)
+                    .spacing(space_xxs)
+                    .push(
+                        widget::text_input(fl!("switch-image"), switch_input)
+                            .on_input(|input| Message::DeploymentSwitchInput(*backend_name, input))
+                            .width(Length::Fill),
+                    )
+                    .push(widget::button::standard(fl!("switch")).on_press_maybe(
+                        (!busy && !switch_image.is_empty()).then(|| {
+                            Message::DeploymentAction(
+                                *backend_name,
+                                DeploymentAction::Switch(switch_image.to_string()),
+                            )
+                        }),
+                    )),
+            );
+            children.push(section.into());
+        }
+
+        widget::column::with_children(children)
+            .spacing(space_m)
+            .into()
+    }
+
+    fn runtimes(&self) -> Element<'_, Message> {
+        let cosmic_theme::Spacing {
+            space_xxs, space_m, ..
+        } = theme::active().cosmic().spacing;
+
+        let mut children = Vec::with_capacity(self.runtimes.len());
+        for (backend_name, runtimes) in self.runtimes.iter() {
+            let busy = self
+                .pending_operations
+                .values()
+                .any(|(op, _)| op.backend_name == *backend_name);
+            let unused_size: u64 = runtimes
+                .iter()
+                .filter(|runtime| runtime.unused)
+                .map(|runtime| runtime.size)
+                .sum();
+            let mut section = widget::settings::section().title(backend_name.to_string());
+            section = section.add(
+                widget::row::with_capacity(2)
+                    .align_y(Alignment::Center)
+                    .spacing(space_xxs)
+                    .push(
+                        widget::text::body(fl!(
+                            "unused-runtimes-size",
+                            size = operation::format_bytes(unused_size)
+                        ))
+                        .width(Length::Fill),
+                    )
+                    .push(
+                        widget::button::standard(fl!("remove-unused")).on_press_maybe(
+                            (!busy && unused_size > 0)
+                                .then_some(Message::RemoveUnusedRuntimes(*backend_name)),
+                        ),
+                    ),
+            );
+            for runtime in runtimes.iter() {
+                let used_by = if runtime.unused {
+                    fl!("unused")
+                } else if let Some(extension_of) = &runtime.extension_of {
+                    fl!("extension-of", name = extension_of.as_str())
+                } else {
+                    fl!("used-by", names = runtime.used_by.join(", "))
+                };
+                let size = operation::format_bytes(runtime.size);
+                let caption = [
+                    runtime.branch.as_str(),
+                    size.as_str(),
+                    runtime.origin.as_str(),
+                ]
+                .into_iter()
+                .filter(|x| !x.is_empty())
+                .collect::<Vec<_>>()
+                .join(" · ");
+                section = section.add(
+                    widget::column::with_capacity(3)
+                        .push(widget::text::body(&runtime.name))
+                        .push(widget::text::caption(caption))
+                        .push(widget::text::caption(used_by))
+                        .spacing(space_xxs)
+                        .width(Length::Fill),
+                );
+            }
+            children.push(section.into());
+        }
+
+        widget::column::with_children(children)
+            .spacing(space_m)
+            .into()
+    }
+
+    fn history(&self) -> Element<'_, Message> {
+        let cosmic_theme::Spacing {
+            space_xxs, space_m, ..
+        } = theme::active().cosmic().spacing;
+
+        let mut children = Vec::with_capacity(2);
+        children.push(
+            widget::text_input::search_input(fl!("history-filter"), &self.history_filter)
+                .on_input(Message::HistoryFilter)
+                .on_clear(Message::HistoryFilter(String::new()))
+                .into(),
+        );
+
+        let undo_index_opt = self
+            .history
+            .undo_index()
+            .filter(|index| self.undo_operation(&self.history.entries[*index]).is_some());
+        let mut section = widget::settings::section();
+        let mut empty = true;
+        for (index, entry) in self
+            .history
+            .entries
+            .iter()
+            .enumerate()
+            .rev()
+            .filter(|(_, entry)| entry.matches(&self.history_filter))
+        {
+            let finished = chrono::DateTime::from_timestamp(entry.finished, 0)
+                .map(|dt| {
+                    dt.with_timezone(&chrono::Local)
+                        .format("%Y-%m-%d %H:%M")
+                        .to_string()
+                })
+                .unwrap_or_default();
+            let versions = entry.versions_text();
+            let undo_button = if undo_index_opt == Some(index) {
+                Some(widget::button::standard(fl!("undo")).on_press(Message::HistoryUndo(index)))
+            } else {
+                None
+            };
+            section = section.add(
+                widget::row::with_capacity(2)
+                    .align_y(Alignment::Center)
+                    .spacing(space_xxs)
+                    .push(
+                        widget::column::with_capacity(3)
+                            .push(widget::text::body(entry.title()))
+                            .push_maybe(
+                                (!versions.is_empty()).then(|| widget::text::caption(versions)),
+                            )
+                            .push(widget::text::caption(format!(
+                                "{} · {} · {}",
+                                finished,
+                                entry.backend,
+                                entry.outcome_text()
+                            )))
+                            .spacing(space_xxs)
+                            .width(Length::Fill),
+                    )
+                    .push_maybe(undo_button),
+            );
+            empty = false;
+        }
+        if empty {
+            children.push(widget::text::body(fl!("no-history")).into());
+        } else {
+            children.push(section.into());
+        }
+
+        widget::column::with_children(children)
+            .spacing(space_m)
+            .into()
+    }
+
     fn operations(&self) -> Element<'_, Message> {
         let cosmic_theme::Spacing {
             space_xs, space_m, ..
@@ -1664,24 +2296,109 @@ impl App {
 
         if !self.pending_operations.is_empty() {
             let mut section = widget::settings::section().title(fl!("pending"));
-            for (_id, (op, progress)) in self.pending_operations.iter().rev() {
-                section = section.add(widget::column![
-                    widget::determinate_linear(*progress)
-                        .width(Length::Fill)
-                        .girth(progress_bar_height),
-                    widget::space::vertical().height(space_xs),
-                    widget::text(op.pending_text((*progress * 100.0) as i32)),
-                ]);
+            // Operations are listed in the order they run
+            for id in self.scheduler.order() {
+                let Some((op, progress)) = self.pending_operations.get(id) else {
+                    continue;
+                };
+                let running = self.scheduler.is_running(*id);
+                let cancelled = self
+                    .pending_cancel_tokens
+                    .get(id)
+                    .is_some_and(|cancel_token| cancel_token.is_cancelled());
+                let cancel_button = if cancelled {
+                    Some(widget::button::standard(fl!("cancelling")))
+                } else if !running
+                    || self
+                        .backend_capabilities(op.backend_name)
+                        .contains(Capabilities::CANCEL)
+                {
+                    Some(
+                        widget::button::standard(fl!("cancel"))
+                            .on_press(Message::PendingCancel(*id)),
+                    )
+                } else {
+                    None
+                };
+                let mut row = widget::row::with_capacity(4)
+                    .align_y(Alignment::Center)
+                    .spacing(space_xs);
+                if running {
+                    row = row.push(widget::text(op.pending_text(progress)).width(Length::Fill));
+                } else {
+                    row = row
+                        .push(
+                            widget::column::with_capacity(2)
+                                .push(widget::text(op.pending_text(progress)))
+                                .push(widget::text::caption(fl!("queued")))
+                                .width(Length::Fill),
+                        )
+                        .push(
+                            widget::button::icon(widget::icon::from_name("go-up-symbolic"))
+                                .on_press_maybe(
+                                    self.scheduler
+                                        .can_move(*id, true)
+                                        .then_some(Message::PendingMoveUp(*id)),
+                                ),
+                        )
+                        .push(
+                            widget::button::icon(widget::icon::from_name("go-down-symbolic"))
+                                .on_press_maybe(
+                                    self.scheduler
+                                        .can_move(*id, false)
+                                        .then_some(Message::PendingMoveDown(*id)),
+                                ),
+                        );
+                }
+                let mut column = widget::column::with_capacity(3 + op.infos.len())
+                    .push(
+                        widget::determinate_linear(progress.fraction())
+                            .width(Length::Fill)
+                            .girth(progress_bar_height),
+                    )
+                    .push(widget::space::vertical().height(space_xs))
+                    .push(row.push_maybe(cancel_button));
+                // Show the progress of each package when there are several
+                if op.infos.len() > 1 {
+                    for (i, info) in op.infos.iter().enumerate() {
+                        let percent = progress.item_percents.get(i).copied().unwrap_or(0.0);
+                        column = column.push(
+                            widget::row::with_capacity(2)
+                                .align_y(Alignment::Center)
+                                .spacing(space_xs)
+                                .push(widget::text::caption(&info.name).width(Length::Fill))
+                                .push(
+                                    widget::determinate_linear(percent / 100.0)
+                                        .width(Length::FillPortion(1))
+                                        .girth(progress_bar_height),
+                                ),
+                        );
+                    }
+                }
+                section = section.add(column);
             }
             children.push(section.into());
         }
 
         if !self.failed_operations.is_empty() {
             let mut section = widget::settings::section().title(fl!("failed"));
-            for (_id, (op, progress, error)) in self.failed_operations.iter().rev() {
+            for (id, (op, progress, error)) in self.failed_operations.iter().rev() {
+                let retry_button = if error.is_retryable() {
+                    Some(
+                        widget::button::standard(fl!("retry"))
+                            .on_press(Message::PendingRetry(*id)),
+                    )
+                } else {
+                    None
+                };
                 section = section.add(widget::column::with_children(vec![
-                    widget::text(op.pending_text((*progress * 100.0) as i32)).into(),
-                    widget::text(error).into(),
+                    widget::text(op.pending_text(progress)).into(),
+                    widget::row::with_capacity(2)
+                        .align_y(Alignment::Center)
+                        .spacing(space_xs)
+                        .push(widget::text(error.text()).width(Length::Fill))
+                        .push_maybe(retry_button)
+                        .into(),
                 ]));
             }
             children.push(section.into());
@@ -1852,8 +2569,7 @@ impl App {
                         found_source = true;
                     }
                 }
-                //TODO: allow other backends to show sources?
-                if !found_source && *backend_name == BackendName::FlatpakUser {
+                if !found_source && backend.capabilities().contains(Capabilities::REPOSITORIES) {
                     sources.push(Source {
                         backend_name: *backend_name,
                         id: cache.source_id.clone(),
@@ -1868,10 +2584,28 @@ impl App {
         sources
     }
 
+    /// Flatpak backend used to import repository files, preferring the user installation
+    fn repository_backend(&self) -> Option<BackendName> {
+        if self
+            .backend_capabilities(BackendName::FlatpakUser)
+            .contains(Capabilities::REPOSITORIES)
+        {
+            return Some(BackendName::FlatpakUser);
+        }
+        // Only flatpak repository files can be imported
+        self.backends
+            .iter()
+            .find(|(backend_name, backend)| {
+                backend_name.is_flatpak()
+                    && backend.capabilities().contains(Capabilities::REPOSITORIES)
+            })
+            .map(|(backend_name, _)| *backend_name)
+    }
+
     fn repositories(&self) -> Element<'_, Message> {
-        if !cfg!(feature = "flatpak") {
+        let Some(repository_backend) = self.repository_backend() else {
             return widget::text(fl!("no-flatpak")).into();
-        }
+        };
 
         let sources = self.sources();
         let mut recommended = widget::settings::section().title(fl!("recommended-flatpak-sources"));
@@ -1964,7 +2698,7 @@ impl App {
             custom.into(),
             widget::container(widget::button::standard(fl!("import")).on_press_maybe(
                 if self.repos_changing.is_empty() {
-                    Some(Message::RepositoryAddDialog(BackendName::FlatpakUser))
+                    Some(Message::RepositoryAddDialog(repository_backend))
                 } else {
                     None
                 },
@@ -2009,6 +2743,7 @@ impl Application for App {
         });
 
         let app_themes = vec![fl!("match-desktop"), fl!("dark"), fl!("light")];
+        let install_scopes = vec![fl!("scope-user"), fl!("scope-system")];
 
         let mut nav_model = widget::nav_bar::Model::default();
         for &nav_page in NavPage::all() {
@@ -2039,6 +2774,7 @@ impl Application for App {
             mode: flags.mode,
             locale,
             app_themes,
+            install_scopes,
             apps: Arc::new(Apps::new()),
             category_index: Arc::new(CategoryIndex::new()),
             backends: Backends::new(),
@@ -2052,9 +2788,19 @@ impl Application for App {
             pending_backend_updates: HashMap::new(),
             pending_operation_id: 0,
             pending_operations: BTreeMap::new(),
+            pending_cancel_tokens: HashMap::new(),
+            scheduler: Scheduler::default(),
             progress_operations: BTreeSet::new(),
             complete_operations: BTreeMap::new(),
             failed_operations: BTreeMap::new(),
+            after_operations: HashMap::new(),
+            history: History::load(),
+            history_filter: String::new(),
+            deployments: BTreeMap::new(),
+            deployment_switch_inputs: BTreeMap::new(),
+            runtimes: BTreeMap::new(),
+            queued_operations: Vec::new(),
+            queue_path_opt: None,
             repos_changing: Vec::new(),
             // XX must be a named id or responsive widget will discard the state
             scrollable_id: widget::Id::new("store-scrollable"),
@@ -2067,6 +2813,7 @@ impl Application for App {
             updates: None,
             waiting_installed: Vec::new(),
             waiting_updates: Vec::new(),
+            reboot_required: BTreeSet::new(),
             category_results: None,
             category_load_start: Some(Instant::now()),
             explore_results: HashMap::new(),
@@ -2099,7 +2846,14 @@ impl Application for App {
         }
 
         match app.mode {
-            Mode::Normal => {}
+            Mode::Normal => {
+                app.queue_path_opt = queue::path();
+                match &app.queue_path_opt {
+                    // Offered for resuming once backends are loaded
+                    Some(queue_path) => app.queued_operations = queue::load(queue_path),
+                    None => log::warn!("failed to find user data directory"),
+                }
+            }
             Mode::GStreamer { .. } => {
                 app.core.window.use_template = false;
             }
@@ -2200,6 +2954,16 @@ impl Application for App {
         }
 
         Some(match &self.context_page {
+            ContextPage::Deployments => context_drawer::context_drawer(
+                self.deployments(),
+                Message::ToggleContextPage(ContextPage::Deployments),
+            )
+            .title(fl!("deployments")),
+            ContextPage::History => context_drawer::context_drawer(
+                self.history(),
+                Message::ToggleContextPage(ContextPage::History),
+            )
+            .title(fl!("history")),
             ContextPage::Operations => context_drawer::context_drawer(
                 self.operations(),
                 Message::ToggleContextPage(ContextPage::Operations),
@@ -2220,6 +2984,11 @@ impl Application for App {
                 Message::ToggleContextPage(ContextPage::Repositories),
             )
             .title(fl!("software-repositories")),
+            ContextPage::Runtimes => context_drawer::context_drawer(
+                self.runtimes(),
+                Message::ToggleContextPage(ContextPage::Runtimes),
+            )
+            .title(fl!("runtimes")),
         })
     }
 
@@ -2232,14 +3001,25 @@ impl Application for App {
                 let (operation, _, err) = self.failed_operations.get(id)?;
 
                 let (title, body) = operation.failed_dialog(err);
-                widget::dialog()
+                let dialog = widget::dialog()
                     .title(title)
                     .body(body)
-                    .icon(widget::icon::from_name("dialog-error").size(64))
-                    //TODO: retry action
-                    .primary_action(
+                    .icon(widget::icon::from_name("dialog-error").size(64));
+                if err.is_retryable() {
+                    dialog
+                        .primary_action(
+                            widget::button::suggested(fl!("retry"))
+                                .on_press(Message::PendingRetry(*id)),
+                        )
+                        .secondary_action(
+                            widget::button::standard(fl!("cancel"))
+                                .on_press(Message::DialogCancel),
+                        )
+                } else {
+                    dialog.primary_action(
                         widget::button::standard(fl!("cancel")).on_press(Message::DialogCancel),
                     )
+                }
             }
             DialogPage::RepositoryAddError(err) => {
                 widget::dialog()
@@ -2292,6 +3072,19 @@ impl Application for App {
                         widget::button::standard(fl!("cancel")).on_press(Message::DialogCancel),
                     )
             }
+            DialogPage::ResumeOperations => widget::dialog()
+                .title(fl!("resume-operations"))
+                .body(fl!(
+                    "resume-operations-body",
+                    count = self.queued_operations.len()
+                ))
+                .icon(widget::icon::from_name("dialog-question").size(64))
+                .primary_action(
+                    widget::button::suggested(fl!("resume")).on_press(Message::DialogConfirm),
+                )
+                .secondary_action(
+                    widget::button::standard(fl!("discard")).on_press(Message::DialogCancel),
+                ),
             DialogPage::Uninstall(backend_name, _id, info) => {
                 let is_flatpak = backend_name.is_flatpak();
                 let mut dialog = widget::dialog()
@@ -2303,8 +3096,11 @@ impl Application for App {
                     })
                     .icon(widget::icon::from_name(Self::APP_ID).size(64));
 
-                // Only show data deletion option for Flatpak apps
-                if is_flatpak {
+                // Only show data deletion option for backends that support it
+                if self
+                    .backend_capabilities(*backend_name)
+                    .contains(Capabilities::PURGE_DATA)
+                {
                     dialog = dialog.control(
                         widget::checkbox(self.uninstall_purge_data)
                             .label(fl!("delete-app-data"))
@@ -2358,9 +3154,9 @@ impl Application for App {
         let mut count = 0;
         for (_id, (op, progress)) in self.pending_operations.iter() {
             if title.is_empty() {
-                title = op.pending_text((*progress * 100.0) as i32);
+                title = op.pending_text(progress);
             }
-            total_progress += progress;
+            total_progress += progress.percent;
             count += 1;
         }
         let running = count;
@@ -2392,7 +3188,7 @@ impl Application for App {
 
         //TODO: get height from theme?
         let progress_bar_height = Length::Fixed(4.0);
-        let progress_bar = widget::determinate_linear(total_progress)
+        let progress_bar = widget::determinate_linear(total_progress / 100.0)
             .width(Length::Fill)
             .girth(progress_bar_height);
 
@@ -2465,6 +3261,43 @@ impl Application for App {
                     widgets.push(cosmic::widget::indeterminate_circular().size(20.0).into());
                 }
 
+                if !self.runtimes.is_empty() {
+                    widgets.push(
+                        widget::tooltip(
+                            widget::button::icon(widget::icon::from_name(
+                                "application-x-addon-symbolic",
+                            ))
+                            .on_press(Message::ToggleContextPage(ContextPage::Runtimes)),
+                            widget::text(fl!("runtimes")),
+                            widget::tooltip::Position::Bottom,
+                        )
+                        .into(),
+                    );
+                }
+                if !self.deployments.is_empty() {
+                    widgets.push(
+                        widget::tooltip(
+                            widget::button::icon(widget::icon::from_name(
+                                "drive-harddisk-system-symbolic",
+                            ))
+                            .on_press(Message::ToggleContextPage(ContextPage::Deployments)),
+                            widget::text(fl!("deployments")),
+                            widget::tooltip::Position::Bottom,
+                        )
+                        .into(),
+                    );
+                }
+                widgets.push(
+                    widget::tooltip(
+                        widget::button::icon(widget::icon::from_name(
+                            "document-open-recent-symbolic",
+                        ))
+                        .on_press(Message::ToggleContextPage(ContextPage::History)),
+                        widget::text(fl!("history")),
+                        widget::tooltip::Position::Bottom,
+                    )
+                    .into(),
+                );
                 widgets.push(manage_repositories.into());
                 widgets
             }
@@ -2516,19 +3349,19 @@ impl Application for App {
 
                     for (_id, (op, progress)) in self.pending_operations.iter().rev() {
                         list = list.add(widget::column::with_children(vec![
-                            widget::determinate_linear(*progress)
+                            widget::determinate_linear(progress.fraction())
                                 .width(Length::Fill)
                                 .girth(Length::Fixed(4.0))
                                 .into(),
                             widget::space::vertical().height(space_xs).into(),
-                            widget::text(op.pending_text((*progress * 100.0) as i32)).into(),
+                            widget::text(op.pending_text(progress)).into(),
                         ]));
                     }
 
                     for (_id, (op, progress, error)) in self.failed_operations.iter().rev() {
                         list = list.add(widget::column::with_children(vec![
-                            widget::text(op.pending_text((*progress * 100.0) as i32)).into(),
-                            widget::text(error).into(),
+                            widget::text(op.pending_text(progress)).into(),
+                            widget::text(error.text()).into(),
                         ]));
                     }
 
@@ -2742,15 +3575,24 @@ impl Application for App {
             }
         }
 
-        for (id, (op, _)) in self.pending_operations.iter() {
+        for ids in self.scheduler.running() {
             //TODO: use recipe?
-            let id = *id;
+            let Some(op) = scheduler::batch_operation(ids, &self.pending_operations) else {
+                continue;
+            };
+            // Messages for the batch use the ID of its first operation
+            let id = ids[0];
             let backend_opt = self.backends.get(&op.backend_name).cloned();
-            let op = op.clone();
+            let cancel_token = self
+                .pending_cancel_tokens
+                .get(&id)
+                .cloned()
+                .unwrap_or_default();
 
             struct Wrapper {
                 backend: Option<Arc<dyn Backend>>,
                 operation: Operation,
+                cancel_token: CancelToken,
                 id: u64,
             }
 
@@ -2764,15 +3606,18 @@ impl Application for App {
                 Wrapper {
                     backend: backend_opt.clone(),
                     operation: op.clone(),
+                    cancel_token,
                     id,
                 },
                 |Wrapper {
                      backend,
                      operation: op,
+                     cancel_token,
                      id,
                  }| {
                     let backend_opt = backend.clone();
                     let op = op.clone();
+                    let cancel_token = cancel_token.clone();
                     let id = *id;
                     stream::channel(
                         16,
@@ -2794,35 +3639,33 @@ impl Application for App {
                                     };
                                     let msg_tx = msg_tx.clone();
                                     tokio::task::spawn_blocking(move || {
-                                        match backend.operation(&op, on_progress) {
+                                        match backend.operation(&op, &cancel_token, on_progress) {
                                             Ok(()) => Ok(()),
-                                            Err(err) => {
-                                                match err.downcast_ref::<RepositoryRemoveError>() {
-                                                    Some(repo_rm) => {
-                                                        let _ =
-                                                            futures::executor::block_on(async {
-                                                                msg_tx
-                                                            .lock()
-                                                            .await
-                                                            .send(Message::DialogPage(
-                                                                DialogPage::RepositoryRemove(
-                                                                    op.backend_name,
-                                                                    repo_rm.clone(),
-                                                                ),
-                                                            ))
-                                                            .await
-                                                            });
-                                                        Ok(())
-                                                    }
-                                                    None => Err(err.to_string()),
-                                                }
+                                            Err(BackendError::RepositoryInUse(repo_rm)) => {
+                                                let _ = futures::executor::block_on(async {
+                                                    msg_tx
+                                                        .lock()
+                                                        .await
+                                                        .send(Message::DialogPage(
+                                                            DialogPage::RepositoryRemove(
+                                                                op.backend_name,
+                                                                repo_rm,
+                                                            ),
+                                                        ))
+                                                        .await
+                                                });
+                                                Ok(())
                                             }
+                                            Err(err) => Err(err),
                                         }
                                     })
                                     .await
                                     .unwrap()
                                 }
-                                None => Err(format!("backend {:?} not found", op.backend_name)),
+                                None => Err(BackendError::Other(format!(
+                                    "backend {:?} not found",
+                                    op.backend_name
+                                ))),
                             };
 
                             match res {
@@ -2939,3 +3782,235 @@ pub fn abortable_blocking_task(
         })
     })
 }
+
+#[cfg(all(test, feature = "fake"))]
+mod tests {
+    use super::*;
+    use backend::fake::{
+        self,
+        tests::{backend, fixture_dir},
+    };
+    use history::HistoryOutcome;
+    use std::time::Duration;
+
+    // App with only the fake backend, keeping history and the queue in memory
+    fn app(fake: fake::Fake) -> App {
+        let (mut app, _) = App::init(
+            Core::default(),
+            Flags {
+                subcommand_opt: None,
+                config_handler: None,
+                config: Config::default(),
+                mode: Mode::Normal,
+            },
+        );
+        app.history = History::default();
+        app.queue_path_opt = None;
+        app.queued_operations.clear();
+        app.backends.insert(BackendName::Fake, Arc::new(fake));
+        refresh(&mut app);
+        app
+    }
+
+    // Reload installed packages and apps, as the app does after operations complete
+    fn refresh(app: &mut App) {
+        let backend = app.backends[&BackendName::Fake].clone();
+        app.installed = Some(
+            backend
+                .installed()
+                .unwrap()
+                .into_iter()
+                .map(|package| (BackendName::Fake, package))
+                .collect(),
+        );
+        let (apps, category_index) = App::build_apps(&app.backends, &app.installed);
+        app.apps = Arc::new(apps);
+        app.category_index = Arc::new(category_index);
+    }
+
+    fn operation(app: &App, kind: OperationKind, id: &str) -> Operation {
+        let id = AppId::new(id);
+        let info = app.apps[&id][0].info.clone();
+        Operation {
+            kind,
+            backend_name: BackendName::Fake,
+            package_ids: vec![id],
+            infos: vec![info],
+        }
+    }
+
+    fn is_installed(app: &App, id: &str) -> bool {
+        let id = AppId::new(id);
+        app.is_installed(BackendName::Fake, &id, &app.apps[&id][0].info)
+    }
+
+    // Run scheduled batches like the operation subscription, feeding the results back to the app
+    fn run_operations(app: &mut App) -> Vec<OperationProgress> {
+        let mut progress = Vec::new();
+        loop {
+            let Some(ids) = app.scheduler.running().next().map(<[u64]>::to_vec) else {
+                break;
+            };
+            let op = scheduler::batch_operation(&ids, &app.pending_operations).unwrap();
+            let backend = app.backends[&op.backend_name].clone();
+            let cancel_token = app.pending_cancel_tokens[&ids[0]].clone();
+            let events = Arc::new(Mutex::new(Vec::new()));
+            let res = {
+                let events = events.clone();
+                backend.operation(
+                    &op,
+                    &cancel_token,
+                    Box::new(move |p| events.lock().unwrap().push(p)),
+                )
+            };
+            for p in events.lock().unwrap().drain(..) {
+                let _ = app.handle_update(Message::PendingProgress(ids[0], p.clone()));
+                progress.push(p);
+            }
+            let _ = app.handle_update(match res {
+                Ok(()) => Message::PendingComplete(ids[0]),
+                Err(err) => Message::PendingError(ids[0], err),
+            });
+        }
+        refresh(app);
+        progress
+    }
+
+    #[test]
+    fn build_apps_marks_installed() {
+        let dir = fixture_dir();
+        let app = app(backend(&dir, &[]));
+        assert!(!is_installed(&app, "com.example.Hello"));
+        assert!(is_installed(&app, "com.example.Installed"));
+        assert!(!app.apps[&AppId::new("com.example.Hello")][0].installed);
+        assert!(app.apps[&AppId::new("com.example.Installed")][0].installed);
+        assert_eq!(
+            app.category_index.get("Utility"),
+            Some(&vec![AppId::new("com.example.Hello")])
+        );
+    }
+
+    #[test]
+    fn is_installed_matches_system_pkgnames() {
+        let info = |pkgnames: &[&str]| AppInfo {
+            source_id: "fake".to_string(),
+            pkgnames: pkgnames.iter().map(|x| x.to_string()).collect(),
+            ..Default::default()
+        };
+        let system = Package {
+            id: AppId::system(),
+            icon: widget::icon::from_name("package-x-generic").handle(),
+            info: Arc::new(info(&["a", "b"])),
+            version: String::new(),
+            extra: HashMap::new(),
+        };
+        let installed = Some(vec![(BackendName::Fake, system)]);
+        let id = AppId::new("com.example.Packaged");
+        assert!(App::is_installed_inner(
+            &installed,
+            BackendName::Fake,
+            &id,
+            &info(&["a"])
+        ));
+        assert!(!App::is_installed_inner(
+            &installed,
+            BackendName::Fake,
+            &id,
+            &info(&["a", "c"])
+        ));
+        assert!(!App::is_installed_inner(
+            &installed,
+            BackendName::Packagekit,
+            &id,
+            &info(&["a"])
+        ));
+        assert!(!App::is_installed_inner(
+            &None,
+            BackendName::Fake,
+            &id,
+            &info(&["a"])
+        ));
+
+        // System packages are listed as an installed app
+        let (apps, _) = App::build_apps(&Backends::new(), &installed);
+        assert!(apps[&AppId::system()][0].installed);
+    }
+
+    #[test]
+    fn operation_completes() {
+        let dir = fixture_dir();
+        let mut app = app(backend(&dir, &[]));
+        let id = app.operation(operation(&app, OperationKind::Install, "com.example.Hello"));
+        assert!(app.scheduler.is_running(id));
+
+        let progress = run_operations(&mut app);
+        assert_eq!(progress.last().map(|x| x.percent), Some(100.0));
+        assert!(app.pending_operations.is_empty());
+        assert!(app.complete_operations.contains_key(&id));
+        assert!(is_installed(&app, "com.example.Hello"));
+        let entry = app.history.entries.last().unwrap();
+        assert_eq!(entry.outcome, HistoryOutcome::Completed);
+        assert_eq!(entry.versions_after, vec!["2.0".to_string()]);
+    }
+
+    #[test]
+    fn follow_up_runs_after_install() {
+        let dir = fixture_dir();
+        let mut app = app(backend(&dir, &[]));
+        let install = operation(&app, OperationKind::Install, "com.example.Hello");
+        let uninstall = operation(
+            &app,
+            OperationKind::Uninstall { purge_data: false },
+            "com.example.Installed",
+        );
+        app.replace_operation(install, uninstall);
+
+        run_operations(&mut app);
+        assert!(app.after_operations.is_empty());
+        assert_eq!(app.complete_operations.len(), 2);
+        assert!(is_installed(&app, "com.example.Hello"));
+        assert!(!is_installed(&app, "com.example.Installed"));
+    }
+
+    #[test]
+    fn failure_keeps_follow_up_until_dismissed() {
+        let dir = fixture_dir();
+        let mut app = app(backend(&dir, &[(fake::FAIL_ENV, "com.example.Hello")]));
+        let install = operation(&app, OperationKind::Install, "com.example.Hello");
+        let uninstall = operation(
+            &app,
+            OperationKind::Uninstall { purge_data: false },
+            "com.example.Installed",
+        );
+        app.replace_operation(install, uninstall);
+
+        run_operations(&mut app);
+        let (&id, (_, progress, err)) = app.failed_operations.iter().next().unwrap();
+        assert!(matches!(err, BackendError::Other(_)));
+        assert_eq!(progress.percent, 50.0);
+        assert_eq!(
+            app.dialog_pages.front(),
+            Some(&DialogPage::FailedOperation(id))
+        );
+        assert!(matches!(
+            app.history.entries.last().unwrap().outcome,
+            HistoryOutcome::Failed(_)
+        ));
+        // The follow-up is kept for a retry, until the failure is dismissed
+        assert!(app.after_operations.contains_key(&id));
+        let _ = app.handle_update(Message::DialogCancel);
+        assert!(app.after_operations.is_empty());
+        assert!(is_installed(&app, "com.example.Installed"));
+    }
+
+    #[test]
+    fn delay_slows_progress() {
+        let dir = fixture_dir();
+        let mut app = app(backend(&dir, &[(fake::DELAY_ENV, "10")]));
+        app.operation(operation(&app, OperationKind::Install, "com.example.Hello"));
+        let start = Instant::now();
+        let progress = run_operations(&mut app);
+        // Every progress step is followed by the delay
+        assert!(start.elapsed() >= Duration::from_millis(10) * progress.len() as u32);
+    }
+}
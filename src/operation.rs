@@ -1,14 +1,24 @@
-use std::{fmt, sync::Arc};
+use std::{
+    fmt::{self, Write},
+    sync::Arc,
+};
 
-use crate::{AppId, AppInfo, backend::BackendName};
+use crate::{
+    AppId, AppInfo,
+    backend::{BackendError, BackendName},
+    fl,
+};
 
-#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+#[derive(Clone, Debug, Eq, Hash, PartialEq, bitcode::Decode, bitcode::Encode)]
 pub enum OperationKind {
     Install,
     Uninstall { purge_data: bool },
     Update,
+    /// Revision to return to for each package, such as a flatpak commit or PackageKit package ID
+    Downgrade(Vec<String>),
     RepositoryAdd(Vec<RepositoryAdd>),
     RepositoryRemove(Vec<RepositoryRemove>, bool),
+    Deployment(DeploymentAction),
 }
 
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
@@ -19,7 +29,106 @@ pub struct Operation {
     pub infos: Vec<Arc<AppInfo>>,
 }
 
-#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum OperationPhase {
+    Resolving,
+    Downloading,
+    Installing,
+    Removing,
+    CleaningUp,
+}
+
+impl OperationPhase {
+    fn text(&self) -> String {
+        match self {
+            Self::Resolving => fl!("phase-resolving"),
+            Self::Downloading => fl!("phase-downloading"),
+            Self::Installing => fl!("phase-installing"),
+            Self::Removing => fl!("phase-removing"),
+            Self::CleaningUp => fl!("phase-cleaning-up"),
+        }
+    }
+}
+
+/// Progress reported by a backend while running an operation
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct OperationProgress {
+    /// Overall progress from 0 to 100
+    pub percent: f32,
+    pub phase_opt: Option<OperationPhase>,
+    /// Package or ref currently being processed
+    pub package_opt: Option<String>,
+    pub bytes_downloaded: u64,
+    /// Total bytes to download, zero if unknown
+    pub bytes_total: u64,
+    /// Status message from the backend
+    pub status: String,
+    /// Progress of each package from 0 to 100, in the same order as the operation infos,
+    /// empty if the backend does not report it
+    pub item_percents: Vec<f32>,
+}
+
+impl OperationProgress {
+    pub fn new(percent: f32) -> Self {
+        Self {
+            percent,
+            ..Default::default()
+        }
+    }
+
+    /// Progress from 0 to 1, for use with progress bars
+    pub fn fraction(&self) -> f32 {
+        self.percent / 100.0
+    }
+
+    /// Describe the phase, package, download size, and status, if known
+    pub fn details(&self) -> String {
+        let mut parts = Vec::with_capacity(4);
+        if let Some(phase) = self.phase_opt {
+            parts.push(phase.text());
+        }
+        if let Some(package) = &self.package_opt {
+            parts.push(package.clone());
+        }
+        if self.bytes_total > 0 {
+            parts.push(fl!(
+                "download-progress",
+                downloaded = format_bytes(self.bytes_downloaded),
+                total = format_bytes(self.bytes_total)
+            ));
+        }
+        let mut details = parts.join(" ");
+        if !self.status.is_empty() {
+            if !details.is_empty() {
+                details.push_str(": ");
+            }
+            details.push_str(&self.status);
+        }
+        details
+    }
+}
+
+pub fn format_bytes(bytes: u64) -> String {
+    if bytes < 1000 {
+        return fl!("size-bytes", size = bytes);
+    }
+    // Kilobytes and larger units, up to terabytes
+    let mut value = bytes as f64 / 1000.0;
+    let mut unit = 0;
+    while value >= 1000.0 && unit < 3 {
+        value /= 1000.0;
+        unit += 1;
+    }
+    let size = format!("{value:.1}");
+    match unit {
+        0 => fl!("size-kilobytes", size = size),
+        1 => fl!("size-megabytes", size = size),
+        2 => fl!("size-gigabytes", size = size),
+        _ => fl!("size-terabytes", size = size),
+    }
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq, bitcode::Decode, bitcode::Encode)]
 pub struct RepositoryAdd {
     pub id: String,
     pub data: Vec<u8>,
@@ -31,7 +140,7 @@ impl RepositoryAdd {
     }
 }
 
-#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+#[derive(Clone, Debug, Eq, Hash, PartialEq, bitcode::Decode, bitcode::Encode)]
 pub struct RepositoryRemove {
     pub id: String,
     pub name: String,
@@ -43,6 +152,53 @@ impl RepositoryRemove {
     }
 }
 
+/// Change to the deployments of an image based backend, such as rpm-ostree
+#[derive(Clone, Debug, Eq, Hash, PartialEq, bitcode::Decode, bitcode::Encode)]
+pub enum DeploymentAction {
+    /// Boot the rollback deployment next
+    Rollback,
+    /// Remove the pending deployment
+    CleanupPending,
+    /// Keep a deployment when new ones are created, or stop keeping it
+    Pin { id: String, pinned: bool },
+    /// Deploy another image or ref, such as `quay.io/fedora/fedora-bootc:41`
+    Switch(String),
+}
+
+impl DeploymentAction {
+    pub fn title(&self) -> String {
+        match self {
+            Self::Rollback => fl!("deployment-rollback"),
+            Self::CleanupPending => fl!("deployment-cleanup-pending"),
+            Self::Pin { id, pinned: true } => fl!("deployment-pin", id = id.as_str()),
+            Self::Pin { id, pinned: false } => fl!("deployment-unpin", id = id.as_str()),
+            Self::Switch(image) => fl!("deployment-switch", image = image.as_str()),
+        }
+    }
+
+    fn pending_text(&self) -> String {
+        match self {
+            Self::Rollback => fl!("deployment-rollback-pending"),
+            Self::CleanupPending => fl!("deployment-cleanup-pending-pending"),
+            Self::Pin { id, pinned: true } => fl!("deployment-pin-pending", id = id.as_str()),
+            Self::Pin { id, pinned: false } => fl!("deployment-unpin-pending", id = id.as_str()),
+            Self::Switch(image) => fl!("deployment-switch-pending", image = image.as_str()),
+        }
+    }
+
+    fn completed_text(&self) -> String {
+        match self {
+            Self::Rollback => fl!("deployment-rollback-completed"),
+            Self::CleanupPending => fl!("deployment-cleanup-pending-completed"),
+            Self::Pin { id, pinned: true } => fl!("deployment-pin-completed", id = id.as_str()),
+            Self::Pin { id, pinned: false } => {
+                fl!("deployment-unpin-completed", id = id.as_str())
+            }
+            Self::Switch(image) => fl!("deployment-switch-completed", image = image.as_str()),
+        }
+    }
+}
+
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct RepositoryRemoveError {
     pub rms: Vec<RepositoryRemove>,
@@ -68,31 +224,52 @@ impl fmt::Display for RepositoryRemoveError {
 impl std::error::Error for RepositoryRemoveError {}
 
 impl Operation {
-    pub fn pending_text(&self, progress: i32) -> String {
+    // Name and source of a single package, or the number of packages
+    fn packages_text(&self) -> String {
+        match self.infos.as_slice() {
+            [info] => fl!(
+                "package-from-source",
+                name = info.name.as_str(),
+                source = info.source_name.as_str()
+            ),
+            infos => fl!("package-count", count = infos.len()),
+        }
+    }
+
+    pub fn pending_text(&self, progress: &OperationProgress) -> String {
         //TODO: translate
+        let percent = progress.percent as i32;
         let verb = match &self.kind {
             OperationKind::Install => "Installing",
             OperationKind::Uninstall { .. } => "Uninstalling",
             OperationKind::Update => "Updating",
+            OperationKind::Downgrade(_) => "Downgrading",
             OperationKind::RepositoryAdd(adds) => {
                 return format!(
                     "Adding repositories {:?} ({}%)",
                     RepositoryAdd::ids(adds),
-                    progress
+                    percent
                 );
             }
             OperationKind::RepositoryRemove(rms, _force) => {
                 return format!(
                     "Removing repositories {:?} ({}%)",
                     RepositoryRemove::ids(rms),
-                    progress
+                    percent
                 );
             }
+            OperationKind::Deployment(action) => {
+                return format!("{} ({}%)", action.pending_text(), percent);
+            }
         };
-        format!(
-            "{} {} from {} ({}%)...",
-            verb, self.infos[0].name, self.infos[0].source_name, progress
-        )
+        let mut text = format!("{} {} ({}%)", verb, self.packages_text(), percent);
+        let details = progress.details();
+        if details.is_empty() {
+            text.push_str("...");
+        } else {
+            let _ = write!(text, ": {details}");
+        }
+        text
     }
 
     pub fn completed_text(&self) -> String {
@@ -101,25 +278,47 @@ impl Operation {
             OperationKind::Install => "Installed",
             OperationKind::Uninstall { .. } => "Uninstalled",
             OperationKind::Update => "Updated",
+            OperationKind::Downgrade(_) => "Downgraded",
             OperationKind::RepositoryAdd(adds) => {
                 return format!("Added repositories {:?}", RepositoryAdd::ids(adds));
             }
             OperationKind::RepositoryRemove(rms, _force) => {
                 return format!("Removed repositories {:?}", RepositoryRemove::ids(rms));
             }
+            OperationKind::Deployment(action) => return action.completed_text(),
         };
-        format!(
-            "{} {} from {}",
-            verb, self.infos[0].name, self.infos[0].source_name
-        )
+        format!("{} {}", verb, self.packages_text())
     }
 
-    pub fn failed_dialog(&self, err: &str) -> (String, String) {
+    pub fn failed_dialog(&self, err: &BackendError) -> (String, String) {
         //TODO: translate
+        let hint = match err {
+            BackendError::NotSupported(_) => "This action is not supported by this source.",
+            BackendError::AuthCancelled => {
+                "Authorization was not granted. Retry to be asked for your password again."
+            }
+            BackendError::NetworkUnavailable(_) => "Check your network connection, then retry.",
+            BackendError::PackageNotFound(_) => {
+                "The package may have been removed from its source. Check for updates to refresh the software catalog."
+            }
+            BackendError::DependencyConflict(_) => {
+                "The package conflicts with installed software, which must be resolved first."
+            }
+            BackendError::DiskFull(_) => "Free up disk space, then retry.",
+            BackendError::RepositoryInUse(_) => {
+                "Remove the items installed from this repository first."
+            }
+            BackendError::Cancelled | BackendError::Other(_) => "",
+        };
+        let mut err = err.to_string();
+        if !hint.is_empty() {
+            let _ = write!(err, "\n\n{hint}");
+        }
         let verb = match &self.kind {
             OperationKind::Install => "install",
             OperationKind::Uninstall { .. } => "uninstall",
             OperationKind::Update => "update",
+            OperationKind::Downgrade(_) => "downgrade",
             OperationKind::RepositoryAdd(adds) => {
                 return (
                     "Failed to add repositories".to_string(),
@@ -138,20 +337,30 @@ impl Operation {
                     ),
                 );
             }
+            OperationKind::Deployment(action) => {
+                return (
+                    "Failed to change deployments".to_string(),
+                    format!("{} failed:\n{err}", action.title()),
+                );
+            }
         };
-        //TODO: get ids and names from all packages
+        let names = self
+            .package_ids
+            .iter()
+            .zip(self.infos.iter())
+            .map(|(id, info)| {
+                format!(
+                    "{} ({}) from {} ({})",
+                    info.name,
+                    id.raw(),
+                    info.source_name,
+                    info.source_id
+                )
+            })
+            .collect::<Vec<_>>();
         (
-            format!(
-                "Failed to {verb} {} from {}",
-                self.infos[0].name, self.infos[0].source_name
-            ),
-            format!(
-                "Failed to {verb} {} ({}) from {} ({}):\n{err}",
-                self.infos[0].name,
-                self.package_ids[0].raw(),
-                self.infos[0].source_name,
-                self.infos[0].source_id
-            ),
+            format!("Failed to {verb} {}", self.packages_text()),
+            format!("Failed to {verb} {}:\n{err}", names.join(", ")),
         )
     }
 }
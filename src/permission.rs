@@ -0,0 +1,365 @@
+use std::{error::Error, fs, path::PathBuf};
+
+use crate::fl;
+
+const CONTEXT: &str = "Context";
+const SESSION_BUS: &str = "Session Bus Policy";
+const SYSTEM_BUS: &str = "System Bus Policy";
+
+/// Sandbox permission of a flatpak app, from the `[Context]` and bus policy metadata sections
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Permission {
+    /// Subsystem shared with the host, such as `network`
+    Shared(String),
+    Socket(String),
+    Device(String),
+    /// Path or special name such as `home` or `xdg-download`, with an optional `:ro` suffix
+    Filesystem(String),
+    /// Bus name and its policy, such as `talk` or `own`
+    SessionBus(String, String),
+    SystemBus(String, String),
+}
+
+impl Permission {
+    /// Parse permissions from flatpak metadata, which uses the key file format
+    pub fn parse_metadata(metadata: &str) -> Vec<Self> {
+        Self::parse_entries(metadata)
+            .into_iter()
+            .filter_map(|(permission, granted)| granted.then_some(permission))
+            .collect()
+    }
+
+    /// Parse granted and revoked permissions, as overrides can revoke permissions with `!value`
+    /// or a bus policy of `none`
+    fn parse_entries(key_file: &str) -> Vec<(Self, bool)> {
+        let mut entries = Vec::new();
+        for (section, key, value) in parse_key_file(key_file) {
+            match section.as_str() {
+                CONTEXT => {
+                    entries.extend(
+                        value
+                            .split(';')
+                            .filter_map(|x| Self::parse_context(&key, x)),
+                    );
+                }
+                SESSION_BUS => {
+                    let granted = value != "none";
+                    entries.push((Self::SessionBus(key, value), granted));
+                }
+                SYSTEM_BUS => {
+                    let granted = value != "none";
+                    entries.push((Self::SystemBus(key, value), granted));
+                }
+                _ => {}
+            }
+        }
+        entries.sort();
+        entries.dedup();
+        entries
+    }
+
+    /// Parse a value of a `[Context]` list, such as `!home` for revoked access to home
+    fn parse_context(key: &str, value: &str) -> Option<(Self, bool)> {
+        let kind: fn(String) -> Self = match key {
+            "shared" => Self::Shared,
+            "sockets" => Self::Socket,
+            "devices" => Self::Device,
+            "filesystems" => Self::Filesystem,
+            _ => return None,
+        };
+        if value.is_empty() {
+            return None;
+        }
+        Some(match value.strip_prefix('!') {
+            Some(revoked) => (kind(revoked.to_string()), false),
+            None => (kind(value.to_string()), true),
+        })
+    }
+
+    /// Section and key of the permission in the key file, and the value granting it
+    fn entry(&self) -> (&'static str, &str, &str) {
+        match self {
+            Self::Shared(name) => (CONTEXT, "shared", name.as_str()),
+            Self::Socket(name) => (CONTEXT, "sockets", name.as_str()),
+            Self::Device(name) => (CONTEXT, "devices", name.as_str()),
+            Self::Filesystem(value) => (CONTEXT, "filesystems", value.as_str()),
+            Self::SessionBus(name, policy) => (SESSION_BUS, name.as_str(), policy.as_str()),
+            Self::SystemBus(name, policy) => (SYSTEM_BUS, name.as_str(), policy.as_str()),
+        }
+    }
+
+    /// Returns true if both permissions are for the same thing, ignoring access modes and policies
+    fn same_target(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Filesystem(a), Self::Filesystem(b)) => filesystem_path(a) == filesystem_path(b),
+            (Self::SessionBus(a, _), Self::SessionBus(b, _))
+            | (Self::SystemBus(a, _), Self::SystemBus(b, _)) => a == b,
+            _ => self == other,
+        }
+    }
+
+    /// Description of what the permission allows, in plain language
+    pub fn description(&self) -> String {
+        match self {
+            Self::Shared(name) => match name.as_str() {
+                "network" => fl!("permission-network"),
+                "ipc" => fl!("permission-ipc"),
+                _ => fl!("permission-shared", name = name.as_str()),
+            },
+            Self::Socket(name) => match name.as_str() {
+                "wayland" => fl!("permission-wayland"),
+                "x11" => fl!("permission-x11"),
+                "fallback-x11" => fl!("permission-fallback-x11"),
+                "pulseaudio" => fl!("permission-pulseaudio"),
+                "session-bus" => fl!("permission-session-bus-all"),
+                "system-bus" => fl!("permission-system-bus-all"),
+                "ssh-auth" => fl!("permission-ssh-auth"),
+                "gpg-agent" => fl!("permission-gpg-agent"),
+                "pcsc" => fl!("permission-pcsc"),
+                "cups" => fl!("permission-cups"),
+                _ => fl!("permission-socket", name = name.as_str()),
+            },
+            Self::Device(name) => match name.as_str() {
+                "all" => fl!("permission-device-all"),
+                "dri" => fl!("permission-dri"),
+                "input" => fl!("permission-input"),
+                "usb" => fl!("permission-usb"),
+                "kvm" => fl!("permission-kvm"),
+                "shm" => fl!("permission-shm"),
+                _ => fl!("permission-device", name = name.as_str()),
+            },
+            Self::Filesystem(value) => {
+                let location = match filesystem_path(value) {
+                    "home" | "~" | "~/" => fl!("permission-home"),
+                    "host" => fl!("permission-host"),
+                    "host-os" => fl!("permission-host-os"),
+                    "host-etc" => fl!("permission-host-etc"),
+                    path => match path.strip_prefix("xdg-") {
+                        Some(folder) => fl!("permission-xdg", folder = folder),
+                        None => fl!("permission-path", path = path),
+                    },
+                };
+                if value.ends_with(":ro") {
+                    fl!("permission-read-only", location = location)
+                } else {
+                    fl!("permission-read-write", location = location)
+                }
+            }
+            Self::SessionBus(name, policy) => match policy.as_str() {
+                "own" => fl!("permission-session-bus-own", name = name.as_str()),
+                _ => fl!("permission-session-bus-talk", name = name.as_str()),
+            },
+            Self::SystemBus(name, policy) => match policy.as_str() {
+                "own" => fl!("permission-system-bus-own", name = name.as_str()),
+                _ => fl!("permission-system-bus-talk", name = name.as_str()),
+            },
+        }
+    }
+
+    /// Returns true if the permission gives broad access to the user's data or system
+    pub fn is_risky(&self) -> bool {
+        match self {
+            Self::Shared(_) => false,
+            Self::Socket(name) => matches!(name.as_str(), "session-bus" | "system-bus"),
+            Self::Device(name) => name == "all",
+            Self::Filesystem(value) => matches!(
+                filesystem_path(value),
+                "home" | "~" | "~/" | "host" | "host-os" | "host-etc"
+            ),
+            // Talking to flatpak itself can start commands outside of the sandbox
+            Self::SessionBus(name, _) => name.starts_with("org.freedesktop.Flatpak"),
+            Self::SystemBus(_, _) => false,
+        }
+    }
+}
+
+/// Permission of an app, with where it comes from
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AppPermission {
+    pub permission: Permission,
+    /// Granted by the app's metadata
+    pub manifest: bool,
+    /// Granted (`true`) or revoked (`false`) by a user override
+    pub override_opt: Option<bool>,
+}
+
+impl AppPermission {
+    pub fn granted(&self) -> bool {
+        self.override_opt.unwrap_or(self.manifest)
+    }
+}
+
+/// Sandbox permissions of an app, combining its metadata with user overrides
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Permissions {
+    /// ID used by the sandbox, which names the overrides file
+    pub app_id: String,
+    pub items: Vec<AppPermission>,
+}
+
+impl Permissions {
+    pub fn new(app_id: String, metadata: &str) -> Self {
+        let mut items: Vec<AppPermission> = Permission::parse_metadata(metadata)
+            .into_iter()
+            .map(|permission| AppPermission {
+                permission,
+                manifest: true,
+                override_opt: None,
+            })
+            .collect();
+        let overrides = overrides_path(&app_id)
+            .and_then(|path| fs::read_to_string(path).ok())
+            .map(|key_file| Permission::parse_entries(&key_file))
+            .unwrap_or_default();
+        for (permission, granted) in overrides {
+            match items
+                .iter_mut()
+                .find(|item| item.permission.same_target(&permission))
+            {
+                Some(item) => {
+                    // Overrides may change the access mode, such as making home read-only
+                    if granted {
+                        item.permission = permission;
+                    }
+                    item.override_opt = Some(granted);
+                }
+                None => items.push(AppPermission {
+                    permission,
+                    manifest: false,
+                    override_opt: Some(granted),
+                }),
+            }
+        }
+        Self { app_id, items }
+    }
+
+    pub fn has_overrides(&self) -> bool {
+        self.items.iter().any(|item| item.override_opt.is_some())
+    }
+}
+
+/// User overrides file, the same one used by `flatpak override --user`
+fn overrides_path(app_id: &str) -> Option<PathBuf> {
+    dirs::data_dir().map(|x| x.join("flatpak").join("overrides").join(app_id))
+}
+
+/// Grant or revoke a permission with a user override, or remove the override if `None`
+pub fn set_override(
+    app_id: &str,
+    permission: &Permission,
+    granted_opt: Option<bool>,
+) -> Result<(), Box<dyn Error>> {
+    let path = overrides_path(app_id).ok_or("failed to find user data directory")?;
+    let mut entries = match fs::read_to_string(&path) {
+        Ok(key_file) => parse_key_file(&key_file),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+        Err(err) => return Err(err.into()),
+    };
+
+    let (section, key, value) = permission.entry();
+    if section == CONTEXT {
+        // List values are rewritten without the previous override for the same target
+        let mut values: Vec<String> = entries
+            .iter()
+            .find(|(s, k, _)| s == section && k == key)
+            .map(|(_, _, v)| {
+                v.split(';')
+                    .filter(|x| {
+                        Permission::parse_context(key, x)
+                            .is_some_and(|(other, _)| !other.same_target(permission))
+                    })
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default();
+        match granted_opt {
+            Some(true) => values.push(value.to_string()),
+            // Revoking applies to every access mode
+            Some(false) => values.push(format!("!{}", filesystem_path(value))),
+            None => {}
+        }
+        entries.retain(|(s, k, _)| !(s == section && k == key));
+        if !values.is_empty() {
+            entries.push((
+                section.to_string(),
+                key.to_string(),
+                format!("{};", values.join(";")),
+            ));
+        }
+    } else {
+        entries.retain(|(s, k, _)| !(s == section && k == key));
+        match granted_opt {
+            Some(true) => entries.push((section.to_string(), key.to_string(), value.to_string())),
+            Some(false) => entries.push((section.to_string(), key.to_string(), "none".to_string())),
+            None => {}
+        }
+    }
+
+    if entries.is_empty() {
+        return reset_overrides(app_id);
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&path, write_key_file(&entries))?;
+    Ok(())
+}
+
+/// Remove all user overrides of an app
+pub fn reset_overrides(app_id: &str) -> Result<(), Box<dyn Error>> {
+    let path = overrides_path(app_id).ok_or("failed to find user data directory")?;
+    match fs::remove_file(&path) {
+        Ok(()) => Ok(()),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Path of a filesystem permission without its access mode, such as `home` from `home:ro`
+fn filesystem_path(value: &str) -> &str {
+    value.split_once(':').map_or(value, |(path, _)| path)
+}
+
+/// Section, key and value of each entry in a key file, in order
+fn parse_key_file(key_file: &str) -> Vec<(String, String, String)> {
+    let mut entries = Vec::new();
+    let mut section = "";
+    for line in key_file.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[').and_then(|x| x.strip_suffix(']')) {
+            section = name;
+            continue;
+        }
+        if let Some((key, value)) = line.split_once('=') {
+            entries.push((
+                section.to_string(),
+                key.trim().to_string(),
+                value.trim().to_string(),
+            ));
+        }
+    }
+    entries
+}
+
+fn write_key_file(entries: &[(String, String, String)]) -> String {
+    let mut sections: Vec<&str> = Vec::new();
+    for (section, _, _) in entries.iter() {
+        if !sections.contains(&section.as_str()) {
+            sections.push(section);
+        }
+    }
+    let mut key_file = String::new();
+    for section in sections {
+        if !key_file.is_empty() {
+            key_file.push('\n');
+        }
+        key_file.push_str(&format!("[{section}]\n"));
+        for (_, key, value) in entries.iter().filter(|(s, _, _)| s == section) {
+            key_file.push_str(&format!("{key}={value}\n"));
+        }
+    }
+    key_file
+}
@@ -0,0 +1,252 @@
+// Copyright 2023 System76 <info@system76.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{AppId, AppInfo, BackendName, Operation, OperationKind};
+
+// Operation with a backend name and infos that can be stored
+#[derive(bitcode::Decode, bitcode::Encode)]
+struct QueuedOperation {
+    kind: OperationKind,
+    backend_name: String,
+    package_ids: Vec<AppId>,
+    infos: Vec<AppInfo>,
+}
+
+impl QueuedOperation {
+    fn new(op: &Operation) -> Self {
+        Self {
+            kind: op.kind.clone(),
+            backend_name: op.backend_name.to_string(),
+            package_ids: op.package_ids.clone(),
+            infos: op.infos.iter().map(|info| (**info).clone()).collect(),
+        }
+    }
+
+    fn into_operation(self) -> Result<Operation, String> {
+        let backend_name = self.backend_name.parse::<BackendName>()?;
+        Ok(Operation {
+            kind: self.kind,
+            backend_name,
+            package_ids: self.package_ids,
+            infos: self.infos.into_iter().map(Arc::new).collect(),
+        })
+    }
+}
+
+// Queued operation with the one to start once it completes, such as removing a replaced app
+#[derive(bitcode::Decode, bitcode::Encode)]
+struct QueuedEntry {
+    operation: QueuedOperation,
+    after: Option<QueuedOperation>,
+}
+
+/// File in the user data directory that the queue is kept in
+pub fn path() -> Option<PathBuf> {
+    dirs::data_dir().map(|p| p.join("cosmic-store").join("queue.bin"))
+}
+
+/// Load operations that were still queued when the store last exited, with their follow-ups
+pub fn load(path: &Path) -> Vec<(Operation, Option<Operation>)> {
+    let data = match fs::read(path) {
+        Ok(ok) => ok,
+        Err(err) => {
+            if err.kind() != std::io::ErrorKind::NotFound {
+                log::warn!("failed to read operation queue {:?}: {}", path, err);
+            }
+            return Vec::new();
+        }
+    };
+    let queued: Vec<QueuedEntry> = match bitcode::decode(&data) {
+        Ok(ok) => ok,
+        Err(err) => {
+            log::warn!("failed to decode operation queue {:?}: {}", path, err);
+            return Vec::new();
+        }
+    };
+    let mut operations = Vec::with_capacity(queued.len());
+    for entry in queued {
+        let operation = match entry.operation.into_operation() {
+            Ok(ok) => ok,
+            Err(err) => {
+                log::warn!("skipping queued operation: {}", err);
+                continue;
+            }
+        };
+        let after_opt = match entry.after.map(QueuedOperation::into_operation).transpose() {
+            Ok(ok) => ok,
+            Err(err) => {
+                log::warn!("skipping follow-up of queued operation: {}", err);
+                None
+            }
+        };
+        operations.push((operation, after_opt));
+    }
+    operations
+}
+
+/// Save queued operations and their follow-ups atomically, removing the queue if there are none
+pub fn save<'a>(
+    path: &Path,
+    operations: impl Iterator<Item = (&'a Operation, Option<&'a Operation>)>,
+) {
+    let queued: Vec<QueuedEntry> = operations
+        .map(|(op, after_opt)| QueuedEntry {
+            operation: QueuedOperation::new(op),
+            after: after_opt.map(QueuedOperation::new),
+        })
+        .collect();
+
+    if queued.is_empty() {
+        if let Err(err) = fs::remove_file(path)
+            && err.kind() != std::io::ErrorKind::NotFound
+        {
+            log::warn!("failed to remove operation queue {:?}: {}", path, err);
+        }
+        return;
+    }
+
+    if let Some(parent) = path.parent()
+        && let Err(err) = fs::create_dir_all(parent)
+    {
+        log::warn!("failed to create directory {:?}: {}", parent, err);
+        return;
+    }
+    let data = bitcode::encode(&queued);
+    if let Err(err) =
+        atomicwrites::AtomicFile::new(path, atomicwrites::OverwriteBehavior::AllowOverwrite)
+            .write(|file| file.write_all(&data))
+    {
+        log::warn!("failed to write operation queue {:?}: {}", path, err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{RepositoryRemove, app_info::AppRelease};
+
+    fn operation(kind: OperationKind, backend_name: BackendName, id: &str) -> Operation {
+        Operation {
+            kind,
+            backend_name,
+            package_ids: vec![AppId::new(id)],
+            infos: vec![Arc::new(AppInfo {
+                source_id: "fake".to_string(),
+                name: id.to_string(),
+                releases: vec![AppRelease {
+                    timestamp: Some(0),
+                    version: "2.0".to_string(),
+                    description: None,
+                    url: None,
+                }],
+                ..Default::default()
+            })],
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cosmic-store").join("queue.bin");
+
+        let install = operation(
+            OperationKind::Install,
+            BackendName::FlatpakUser,
+            "com.example.Hello",
+        );
+        // Removing the app the install replaces
+        let uninstall = operation(
+            OperationKind::Uninstall { purge_data: true },
+            BackendName::Packagekit,
+            "hello",
+        );
+        let repository_remove = operation(
+            OperationKind::RepositoryRemove(
+                vec![RepositoryRemove {
+                    id: "fake".to_string(),
+                    name: "Fake".to_string(),
+                }],
+                true,
+            ),
+            BackendName::external("example"),
+            "com.example.Other",
+        );
+        save(
+            &path,
+            [(&install, Some(&uninstall)), (&repository_remove, None)].into_iter(),
+        );
+
+        assert_eq!(
+            load(&path),
+            vec![(install, Some(uninstall)), (repository_remove, None)]
+        );
+    }
+
+    #[test]
+    fn empty_removes_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("queue.bin");
+
+        let install = operation(
+            OperationKind::Install,
+            BackendName::Fake,
+            "com.example.Hello",
+        );
+        save(&path, [(&install, None)].into_iter());
+        assert!(path.is_file());
+
+        save(&path, std::iter::empty());
+        assert!(!path.exists());
+        assert!(load(&path).is_empty());
+    }
+
+    #[test]
+    fn undecodable_is_discarded() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("queue.bin");
+
+        fs::write(&path, b"not a queue").unwrap();
+        assert!(load(&path).is_empty());
+    }
+
+    #[test]
+    fn unknown_backend_is_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("queue.bin");
+
+        let mut unknown = QueuedOperation::new(&operation(
+            OperationKind::Install,
+            BackendName::Fake,
+            "com.example.Hello",
+        ));
+        unknown.backend_name = "unknown".to_string();
+        let install = operation(
+            OperationKind::Install,
+            BackendName::Fake,
+            "com.example.Other",
+        );
+        let mut unknown_after = QueuedOperation::new(&install);
+        unknown_after.backend_name = "unknown".to_string();
+        let queued = vec![
+            QueuedEntry {
+                operation: unknown,
+                after: None,
+            },
+            QueuedEntry {
+                operation: QueuedOperation::new(&install),
+                after: Some(unknown_after),
+            },
+        ];
+        fs::write(&path, bitcode::encode(&queued)).unwrap();
+
+        // Follow-ups with an unknown backend are dropped, keeping the operation
+        assert_eq!(load(&path), vec![(install, None)]);
+    }
+}
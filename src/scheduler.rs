@@ -0,0 +1,360 @@
+// Copyright 2023 System76 <info@system76.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::{BackendName, Operation, OperationKind, OperationProgress};
+
+/// Decides when pending operations run
+///
+/// Operations for the same backend run one at a time in queue order, while different
+/// backends run in parallel. Compatible operations at the front of a backend's queue are
+/// coalesced into a single backend transaction.
+#[derive(Debug, Default)]
+pub struct Scheduler {
+    // Pending operation IDs, in the order they will run
+    order: Vec<u64>,
+    // Backend of each pending operation
+    backends: BTreeMap<u64, BackendName>,
+    // Operation IDs of the running batch for each backend
+    running: BTreeMap<BackendName, Vec<u64>>,
+}
+
+impl Scheduler {
+    pub fn push(&mut self, id: u64, backend_name: BackendName) {
+        self.order.push(id);
+        self.backends.insert(id, backend_name);
+    }
+
+    pub fn order(&self) -> &[u64] {
+        &self.order
+    }
+
+    pub fn is_running(&self, id: u64) -> bool {
+        self.running.values().any(|ids| ids.contains(&id))
+    }
+
+    /// Operation IDs of running batches, the first ID identifies the batch
+    pub fn running(&self) -> impl Iterator<Item = &[u64]> {
+        self.running.values().map(Vec::as_slice)
+    }
+
+    /// Operation IDs in the same batch as an operation, or just its ID if it is not running
+    pub fn batch(&self, id: u64) -> Vec<u64> {
+        self.running
+            .values()
+            .find(|ids| ids.contains(&id))
+            .cloned()
+            .unwrap_or_else(|| vec![id])
+    }
+
+    /// Remove a finished operation along with the rest of its batch, returning their IDs
+    pub fn finish(&mut self, id: u64) -> Vec<u64> {
+        let backend_name_opt = self
+            .running
+            .iter()
+            .find(|(_, ids)| ids.contains(&id))
+            .map(|(backend_name, _)| *backend_name);
+        let ids = match backend_name_opt.and_then(|x| self.running.remove(&x)) {
+            Some(ids) => ids,
+            None => vec![id],
+        };
+        self.order.retain(|x| !ids.contains(x));
+        self.backends.retain(|x, _| !ids.contains(x));
+        ids
+    }
+
+    /// Returns true if a queued operation can move one place earlier or later
+    pub fn can_move(&self, id: u64, earlier: bool) -> bool {
+        self.neighbor(id, earlier).is_some()
+    }
+
+    /// Swap a queued operation with its neighbor on the same backend, running operations stay
+    /// in place
+    pub fn move_by_one(&mut self, id: u64, earlier: bool) {
+        if let Some((index, other)) = self.neighbor(id, earlier) {
+            self.order.swap(index, other);
+        }
+    }
+
+    // Only the order of operations on the same backend matters, others run in parallel
+    fn neighbor(&self, id: u64, earlier: bool) -> Option<(usize, usize)> {
+        let index = self.order.iter().position(|x| *x == id)?;
+        let backend_name = self.backends.get(&id)?;
+        let same_backend =
+            |other: &usize| self.backends.get(&self.order[*other]) == Some(backend_name);
+        let other = if earlier {
+            (0..index).rev().find(same_backend)?
+        } else {
+            (index + 1..self.order.len()).find(same_backend)?
+        };
+        if self.is_running(id) || self.is_running(self.order[other]) {
+            return None;
+        }
+        Some((index, other))
+    }
+
+    /// Start a batch for every backend that is idle, returning the batches started
+    pub fn schedule(
+        &mut self,
+        pending: &BTreeMap<u64, (Operation, OperationProgress)>,
+    ) -> Vec<Vec<u64>> {
+        let mut started = BTreeMap::<BackendName, Vec<u64>>::new();
+        // Backends that cannot take more operations in this pass
+        let mut closed = BTreeSet::new();
+        for id in self.order.iter() {
+            let Some((op, _)) = pending.get(id) else {
+                continue;
+            };
+            if self.running.contains_key(&op.backend_name) || closed.contains(&op.backend_name) {
+                continue;
+            }
+            match started.get_mut(&op.backend_name) {
+                Some(ids) => {
+                    // Later operations must not run before an incompatible one
+                    let batch: Vec<&Operation> = ids
+                        .iter()
+                        .filter_map(|id| pending.get(id).map(|(op, _)| op))
+                        .collect();
+                    if batch.iter().all(|batch_op| compatible(batch_op, op)) {
+                        ids.push(*id);
+                    } else {
+                        closed.insert(op.backend_name);
+                    }
+                }
+                None => {
+                    started.insert(op.backend_name, vec![*id]);
+                }
+            }
+        }
+        for (backend_name, ids) in started.iter() {
+            log::info!("starting {backend_name} operations {ids:?}");
+            self.running.insert(*backend_name, ids.clone());
+        }
+        started.into_values().collect()
+    }
+}
+
+/// Merge the operations of a batch into one operation for the backend
+pub fn batch_operation(
+    ids: &[u64],
+    pending: &BTreeMap<u64, (Operation, OperationProgress)>,
+) -> Option<Operation> {
+    let mut ops = ids
+        .iter()
+        .filter_map(|id| pending.get(id).map(|(op, _)| op));
+    let mut batch_op = ops.next()?.clone();
+    for op in ops {
+        batch_op.package_ids.extend(op.package_ids.iter().cloned());
+        batch_op.infos.extend(op.infos.iter().cloned());
+    }
+    Some(batch_op)
+}
+
+fn compatible(a: &Operation, b: &Operation) -> bool {
+    let coalescable = |op: &Operation| {
+        matches!(
+            op.kind,
+            OperationKind::Install | OperationKind::Uninstall { .. } | OperationKind::Update
+        ) && op.infos.iter().all(|info| info.package_paths.is_empty())
+    };
+    a.backend_name == b.backend_name
+        && a.kind == b.kind
+        && coalescable(a)
+        && coalescable(b)
+        && !a.package_ids.iter().any(|id| b.package_ids.contains(id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AppId, AppInfo, DeploymentAction, RepositoryAdd};
+    use std::sync::Arc;
+
+    fn operation(kind: OperationKind, backend_name: BackendName, id: &str) -> Operation {
+        Operation {
+            kind,
+            backend_name,
+            package_ids: vec![AppId::new(id)],
+            infos: vec![Arc::new(AppInfo::default())],
+        }
+    }
+
+    // Scheduler with operations pushed in order, using their index as ID
+    fn scheduler(
+        ops: Vec<Operation>,
+    ) -> (Scheduler, BTreeMap<u64, (Operation, OperationProgress)>) {
+        let mut scheduler = Scheduler::default();
+        let mut pending = BTreeMap::new();
+        for (id, op) in ops.into_iter().enumerate() {
+            let id = id as u64;
+            scheduler.push(id, op.backend_name);
+            pending.insert(id, (op, OperationProgress::default()));
+        }
+        (scheduler, pending)
+    }
+
+    #[test]
+    fn coalesces_installs() {
+        let (mut scheduler, pending) = scheduler(vec![
+            operation(OperationKind::Install, BackendName::Fake, "a"),
+            operation(OperationKind::Install, BackendName::Fake, "b"),
+        ]);
+        assert_eq!(scheduler.schedule(&pending), vec![vec![0, 1]]);
+        assert_eq!(scheduler.batch(1), vec![0, 1]);
+
+        let batch_op = batch_operation(&[0, 1], &pending).unwrap();
+        assert_eq!(batch_op.package_ids, [AppId::new("a"), AppId::new("b")]);
+        assert_eq!(batch_op.infos.len(), 2);
+    }
+
+    #[test]
+    fn does_not_coalesce_incompatible() {
+        let repository_add = || {
+            OperationKind::RepositoryAdd(vec![RepositoryAdd {
+                id: "fake".to_string(),
+                data: Vec::new(),
+            }])
+        };
+        let deployment = || OperationKind::Deployment(DeploymentAction::Rollback);
+        for (a, b) in [
+            (
+                OperationKind::Install,
+                OperationKind::Uninstall { purge_data: false },
+            ),
+            (OperationKind::Update, OperationKind::Install),
+            (repository_add(), repository_add()),
+            (OperationKind::Install, repository_add()),
+            (deployment(), deployment()),
+            (
+                OperationKind::Downgrade(vec!["1".to_string()]),
+                OperationKind::Downgrade(vec!["2".to_string()]),
+            ),
+        ] {
+            let (mut scheduler, pending) = scheduler(vec![
+                operation(a.clone(), BackendName::Fake, "a"),
+                operation(b.clone(), BackendName::Fake, "b"),
+            ]);
+            assert_eq!(scheduler.schedule(&pending), vec![vec![0]], "{a:?} {b:?}");
+        }
+    }
+
+    #[test]
+    fn does_not_coalesce_same_package() {
+        let (mut scheduler, pending) = scheduler(vec![
+            operation(OperationKind::Install, BackendName::Fake, "a"),
+            operation(OperationKind::Install, BackendName::Fake, "a"),
+        ]);
+        assert_eq!(scheduler.schedule(&pending), vec![vec![0]]);
+    }
+
+    #[test]
+    fn does_not_pass_incompatible() {
+        let (mut scheduler, pending) = scheduler(vec![
+            operation(OperationKind::Install, BackendName::Fake, "a"),
+            operation(
+                OperationKind::Uninstall { purge_data: false },
+                BackendName::Fake,
+                "b",
+            ),
+            operation(OperationKind::Install, BackendName::Fake, "c"),
+        ]);
+        assert_eq!(scheduler.schedule(&pending), vec![vec![0]]);
+    }
+
+    #[test]
+    fn backends_run_in_parallel() {
+        let (mut scheduler, pending) = scheduler(vec![
+            operation(OperationKind::Install, BackendName::Fake, "a"),
+            operation(OperationKind::Install, BackendName::Snap, "b"),
+            operation(OperationKind::Install, BackendName::Fake, "c"),
+        ]);
+        let mut started = scheduler.schedule(&pending);
+        started.sort();
+        assert_eq!(started, vec![vec![0, 2], vec![1]]);
+
+        // Nothing else starts while both backends are busy
+        assert!(scheduler.schedule(&pending).is_empty());
+    }
+
+    #[test]
+    fn finish_starts_next() {
+        let (mut scheduler, mut pending) = scheduler(vec![
+            operation(OperationKind::Install, BackendName::Fake, "a"),
+            operation(
+                OperationKind::Uninstall { purge_data: false },
+                BackendName::Fake,
+                "b",
+            ),
+        ]);
+        assert_eq!(scheduler.schedule(&pending), vec![vec![0]]);
+        assert!(scheduler.schedule(&pending).is_empty());
+
+        assert_eq!(scheduler.finish(0), vec![0]);
+        pending.remove(&0);
+        assert!(!scheduler.is_running(0));
+        assert_eq!(scheduler.order(), [1]);
+        assert_eq!(scheduler.schedule(&pending), vec![vec![1]]);
+        assert!(scheduler.is_running(1));
+    }
+
+    #[test]
+    fn finish_removes_batch() {
+        let (mut scheduler, pending) = scheduler(vec![
+            operation(OperationKind::Install, BackendName::Fake, "a"),
+            operation(OperationKind::Install, BackendName::Fake, "b"),
+            operation(OperationKind::Install, BackendName::Snap, "c"),
+        ]);
+        scheduler.schedule(&pending);
+        assert_eq!(scheduler.finish(1), vec![0, 1]);
+        assert_eq!(scheduler.order(), [2]);
+    }
+
+    #[test]
+    fn move_queued() {
+        let (mut scheduler, _pending) = scheduler(vec![
+            operation(OperationKind::Install, BackendName::Fake, "a"),
+            operation(OperationKind::Install, BackendName::Fake, "b"),
+        ]);
+        assert!(!scheduler.can_move(0, true));
+        assert!(scheduler.can_move(0, false));
+        scheduler.move_by_one(0, false);
+        assert_eq!(scheduler.order(), [1, 0]);
+        scheduler.move_by_one(0, true);
+        assert_eq!(scheduler.order(), [0, 1]);
+    }
+
+    #[test]
+    fn move_does_not_pass_running() {
+        let (mut scheduler, pending) = scheduler(vec![
+            operation(OperationKind::Install, BackendName::Fake, "a"),
+            operation(
+                OperationKind::Uninstall { purge_data: false },
+                BackendName::Fake,
+                "b",
+            ),
+        ]);
+        scheduler.schedule(&pending);
+        assert!(!scheduler.can_move(1, true));
+        assert!(!scheduler.can_move(0, false));
+        scheduler.move_by_one(1, true);
+        assert_eq!(scheduler.order(), [0, 1]);
+    }
+
+    #[test]
+    fn move_does_not_cross_backends() {
+        let (mut scheduler, _pending) = scheduler(vec![
+            operation(OperationKind::Install, BackendName::Fake, "a"),
+            operation(OperationKind::Install, BackendName::Snap, "b"),
+            operation(OperationKind::Install, BackendName::Fake, "c"),
+        ]);
+        // The only operation on its backend cannot move
+        assert!(!scheduler.can_move(1, true));
+        assert!(!scheduler.can_move(1, false));
+
+        // Operations move past other backends to the next one on their own backend
+        assert!(scheduler.can_move(2, true));
+        scheduler.move_by_one(2, true);
+        assert_eq!(scheduler.order(), [2, 1, 0]);
+    }
+}
@@ -4,6 +4,7 @@
 use std::collections::HashMap;
 use std::env;
 use std::process;
+use std::sync::Arc;
 use std::time::Instant;
 
 use cosmic::{
@@ -23,12 +24,16 @@ use cosmic::cosmic_config::CosmicConfigEntry;
 use cosmic_panel_config::CosmicPanelConfig;
 use rayon::slice::ParallelSliceMut;
 
-use crate::backend::BackendName;
+use crate::backend::{BackendError, BackendName, Capabilities};
 use crate::explore::ExplorePage;
+use crate::history::{self, HistoryOutcome};
 use crate::nav::NavPage;
-use crate::operation::{Operation, OperationKind, RepositoryAdd};
+use crate::operation::{
+    DeploymentAction, Operation, OperationKind, OperationProgress, RepositoryAdd,
+};
+use crate::permission;
 use crate::search::{apply_icons_to_results, preserve_icons_from};
-use crate::{App, DialogPage, GStreamerExitCode, Message, Mode};
+use crate::{App, AppId, AppInfo, DialogPage, GStreamerExitCode, Message, Mode, SelectedRef};
 
 impl App {
     pub fn handle_update(&mut self, message: Message) -> Task<Message> {
@@ -94,6 +99,12 @@ impl App {
             Message::BackendUpdateFinished => {
                 self.fetching_backends = false;
                 self.repos_changing.clear();
+
+                if !self.queued_operations.is_empty()
+                    && !self.dialog_pages.contains(&DialogPage::ResumeOperations)
+                {
+                    self.dialog_pages.push_back(DialogPage::ResumeOperations);
+                }
             }
 
             Message::CategoryResults(categories, mut results) => {
@@ -118,6 +129,13 @@ impl App {
                     apply_icons_to_results(results, icons);
                 }
             }
+            Message::RebootRequired(backend_name, reboot_required) => {
+                if reboot_required {
+                    self.reboot_required.insert(backend_name);
+                } else {
+                    self.reboot_required.remove(&backend_name);
+                }
+            }
             Message::CheckUpdates | Message::PeriodicUpdateCheck => {
                 if matches!(message, Message::PeriodicUpdateCheck) {
                     log::info!("periodic background update check triggered");
@@ -142,10 +160,34 @@ impl App {
                 }
             }
             Message::DialogCancel => {
-                self.dialog_pages.pop_front();
+                match self.dialog_pages.pop_front() {
+                    Some(DialogPage::ResumeOperations) => {
+                        log::info!(
+                            "discarding {} queued operations",
+                            self.queued_operations.len()
+                        );
+                        self.queued_operations.clear();
+                        self.save_queue();
+                    }
+                    Some(DialogPage::FailedOperation(id)) => {
+                        // The failure was dismissed, so what was to follow it will not happen
+                        self.after_operations.remove(&id);
+                    }
+                    _ => {}
+                }
                 self.uninstall_purge_data = false;
             }
             Message::DialogConfirm => match self.dialog_pages.pop_front() {
+                Some(DialogPage::ResumeOperations) => {
+                    log::info!("resuming {} queued operations", self.queued_operations.len());
+                    for (op, after_opt) in std::mem::take(&mut self.queued_operations) {
+                        let id = self.operation(op);
+                        if let Some(after_op) = after_opt {
+                            self.after_operations.insert(id, after_op);
+                        }
+                    }
+                    self.save_queue();
+                }
                 Some(DialogPage::RepositoryRemove(backend_name, repo_rm)) => {
                     self.operation(Operation {
                         kind: OperationKind::RepositoryRemove(repo_rm.rms, true),
@@ -166,6 +208,27 @@ impl App {
                 }
                 _ => {}
             },
+            Message::DeploymentAction(backend_name, action) => {
+                if matches!(action, DeploymentAction::Switch(_)) {
+                    self.deployment_switch_inputs.remove(&backend_name);
+                }
+                self.operation(Operation {
+                    kind: OperationKind::Deployment(action),
+                    backend_name,
+                    package_ids: Vec::new(),
+                    infos: Vec::new(),
+                });
+            }
+            Message::DeploymentSwitchInput(backend_name, input) => {
+                self.deployment_switch_inputs.insert(backend_name, input);
+            }
+            Message::Deployments(backend_name, deployments) => {
+                if deployments.is_empty() {
+                    self.deployments.remove(&backend_name);
+                } else {
+                    self.deployments.insert(backend_name, deployments);
+                }
+            }
             Message::DialogPage(dialog_page) => {
                 self.dialog_pages.push_back(dialog_page);
             }
@@ -255,6 +318,24 @@ impl App {
                     }
                 }
             },
+            Message::HistoryFilter(filter) => {
+                self.history_filter = filter;
+            }
+            Message::HistoryUndo(index) => {
+                // Only the most recent operation can be undone
+                if self.history.undo_index() == Some(index) {
+                    let entry = &self.history.entries[index];
+                    match self.undo_operation(entry) {
+                        Some(op) => {
+                            log::info!("undoing {}", entry.title());
+                            self.operation(op);
+                        }
+                        None => {
+                            log::warn!("cannot undo {}", entry.title());
+                        }
+                    }
+                }
+            }
             Message::Installed((backend_name, installed)) => {
                 let mut installed = match self.installed.take() {
                     Some(mut existing) => {
@@ -394,6 +475,26 @@ impl App {
                     process::exit(0);
                 }
             }
+            Message::MoveScope(backend_name, id, info) => {
+                if let Some((other_backend_name, other_info, false)) =
+                    self.scope_counterpart(backend_name, &id, &info)
+                {
+                    self.replace_operation(
+                        Operation {
+                            kind: OperationKind::Install,
+                            backend_name: other_backend_name,
+                            package_ids: vec![id.clone()],
+                            infos: vec![other_info],
+                        },
+                        Operation {
+                            kind: OperationKind::Uninstall { purge_data: false },
+                            backend_name,
+                            package_ids: vec![id],
+                            infos: vec![info],
+                        },
+                    );
+                }
+            }
             #[cfg(feature = "notify")]
             Message::Notification(notification) => {
                 self.notification_opt = Some(notification);
@@ -412,23 +513,48 @@ impl App {
                     infos: vec![info],
                 });
             }
+            Message::PendingCancel(id) => {
+                if !self.scheduler.is_running(id) {
+                    // Queued operations have not started, so they can be removed directly
+                    log::info!("cancelling queued operation {id}");
+                    return self.handle_update(Message::PendingError(id, BackendError::Cancelled));
+                }
+                if let Some(cancel_token) = self.pending_cancel_tokens.get(&id) {
+                    log::info!("cancelling operation {id}");
+                    // Handlers may block on backend calls
+                    let cancel_token = cancel_token.clone();
+                    tokio::task::spawn_blocking(move || cancel_token.cancel());
+                }
+            }
             Message::PendingComplete(id) => {
                 log::debug!("pending complete {id}");
-                if let Some((op, _)) = self.pending_operations.remove(&id) {
-                    for (package_id, info) in op.package_ids.iter().zip(op.infos.iter()) {
-                        self.waiting_installed.push((
-                            op.backend_name,
-                            info.source_id.clone(),
-                            package_id.clone(),
-                        ));
-                        self.waiting_updates.push((
-                            op.backend_name,
-                            info.source_id.clone(),
-                            package_id.clone(),
-                        ));
+                for id in self.scheduler.finish(id) {
+                    self.pending_cancel_tokens.remove(&id);
+                    if let Some((op, _)) = self.pending_operations.remove(&id) {
+                        let versions_after =
+                            history::versions_after(&op, self.updates.as_deref());
+                        self.history
+                            .finish(id, versions_after, HistoryOutcome::Completed);
+                        for (package_id, info) in op.package_ids.iter().zip(op.infos.iter()) {
+                            self.waiting_installed.push((
+                                op.backend_name,
+                                info.source_id.clone(),
+                                package_id.clone(),
+                            ));
+                            self.waiting_updates.push((
+                                op.backend_name,
+                                info.source_id.clone(),
+                                package_id.clone(),
+                            ));
+                        }
+                        self.complete_operations.insert(id, op);
+                    }
+                    if let Some(op) = self.after_operations.remove(&id) {
+                        self.operation(op);
                     }
-                    self.complete_operations.insert(id, op);
                 }
+                self.schedule();
+                self.save_queue();
 
                 let mut tasks = Vec::with_capacity(self.backends.len() * 2 + 1);
 
@@ -458,11 +584,36 @@ impl App {
                 self.progress_operations.clear();
             }
             Message::PendingError(id, err) => {
-                log::warn!("operation {id} failed: {err}");
-                if let Some((op, progress)) = self.pending_operations.remove(&id) {
-                    self.failed_operations.insert(id, (op, progress, err));
-                    self.dialog_pages.push_back(DialogPage::FailedOperation(id));
+                let ids = self.scheduler.finish(id);
+                for (i, id) in ids.into_iter().enumerate() {
+                    let cancelled = self
+                        .pending_cancel_tokens
+                        .remove(&id)
+                        .is_some_and(|cancel_token| cancel_token.is_cancelled());
+                    if let Some((op, progress)) = self.pending_operations.remove(&id) {
+                        if cancelled || err == BackendError::Cancelled {
+                            // Cancelled operations do not need an error dialog
+                            log::info!("operation {id} cancelled: {err}");
+                            self.after_operations.remove(&id);
+                            self.history
+                                .finish(id, Vec::new(), HistoryOutcome::Cancelled);
+                            self.failed_operations
+                                .insert(id, (op, progress, BackendError::Cancelled));
+                        } else {
+                            log::warn!("operation {id} failed: {err}");
+                            self.history
+                                .finish(id, Vec::new(), HistoryOutcome::from_error(&err));
+                            self.failed_operations
+                                .insert(id, (op, progress, err.clone()));
+                            // One dialog is enough for a batch that failed together
+                            if i == 0 {
+                                self.dialog_pages.push_back(DialogPage::FailedOperation(id));
+                            }
+                        }
+                    }
                 }
+                self.schedule();
+                self.save_queue();
 
                 let mut tasks = Vec::with_capacity(self.backends.len() * 2 + 1);
 
@@ -488,12 +639,57 @@ impl App {
 
                 return Task::batch(tasks);
             }
+            Message::PendingMoveDown(id) => {
+                self.scheduler.move_by_one(id, false);
+                self.save_queue();
+            }
+            Message::PendingMoveUp(id) => {
+                self.scheduler.move_by_one(id, true);
+                self.save_queue();
+            }
+            Message::PendingRetry(id) => {
+                self.dialog_pages.retain(|dialog_page| match dialog_page {
+                    DialogPage::FailedOperation(failed_id) => *failed_id != id,
+                    _ => true,
+                });
+                if let Some((op, _progress, _err)) = self.failed_operations.remove(&id) {
+                    log::info!("retrying operation {id}");
+                    let retry_id = self.operation(op);
+                    if let Some(after_op) = self.after_operations.remove(&id) {
+                        self.after_operations.insert(retry_id, after_op);
+                    }
+                }
+            }
             Message::PendingProgress(id, new_progress) => {
-                if let Some((_, progress)) = self.pending_operations.get_mut(&id) {
-                    *progress = new_progress / 100.0;
+                // Package progress of a batch is split between its operations
+                let mut item = 0;
+                for id in self.scheduler.batch(id) {
+                    if let Some((op, progress)) = self.pending_operations.get_mut(&id) {
+                        let items = op.infos.len();
+                        *progress = OperationProgress {
+                            item_percents: new_progress
+                                .item_percents
+                                .get(item..item + items)
+                                .map_or_else(Vec::new, <[f32]>::to_vec),
+                            ..new_progress.clone()
+                        };
+                        item += items;
+                    }
                 }
                 return self.update_notification();
             }
+            Message::PermissionOverride(app_id, permission, granted_opt) => {
+                if let Err(err) = permission::set_override(&app_id, &permission, granted_opt) {
+                    log::warn!("failed to override permission of {:?}: {}", app_id, err);
+                }
+                return self.load_permissions();
+            }
+            Message::PermissionsReset(app_id) => {
+                if let Err(err) = permission::reset_overrides(&app_id) {
+                    log::warn!("failed to reset permissions of {:?}: {}", app_id, err);
+                }
+                return self.load_permissions();
+            }
             Message::RepositoryAdd(backend_name, adds) => {
                 self.operation(Operation {
                     kind: OperationKind::RepositoryAdd(adds),
@@ -504,7 +700,7 @@ impl App {
             }
             Message::RepositoryAddDialog(backend_name) => {
                 //TODO: support other backends?
-                if backend_name == BackendName::FlatpakUser {
+                if backend_name.is_flatpak() {
                     #[cfg(feature = "xdg-portal")]
                     return Task::perform(
                         async move {
@@ -569,6 +765,44 @@ impl App {
                     infos: Vec::new(),
                 });
             }
+            Message::RemoveUnusedRuntimes(backend_name) => {
+                // Each runtime is its own package, so history lists them by name
+                let (package_ids, infos): (Vec<AppId>, Vec<Arc<AppInfo>>) = self
+                    .runtimes
+                    .get(&backend_name)
+                    .into_iter()
+                    .flatten()
+                    .filter(|runtime| runtime.unused)
+                    .map(|runtime| {
+                        (
+                            AppId::new(&runtime.id),
+                            Arc::new(AppInfo {
+                                source_id: runtime.origin.clone(),
+                                source_name: runtime.origin.clone(),
+                                name: runtime.name.clone(),
+                                flatpak_refs: vec![runtime.id.clone()],
+                                ..Default::default()
+                            }),
+                        )
+                    })
+                    .unzip();
+                if !package_ids.is_empty() {
+                    // All unused runtimes are removed in one transaction
+                    self.operation(Operation {
+                        kind: OperationKind::Uninstall { purge_data: false },
+                        backend_name,
+                        package_ids,
+                        infos,
+                    });
+                }
+            }
+            Message::Runtimes(backend_name, runtimes) => {
+                if runtimes.is_empty() {
+                    self.runtimes.remove(&backend_name);
+                } else {
+                    self.runtimes.insert(backend_name, runtimes);
+                }
+            }
             Message::ScrollView(viewport) => {
                 self.scroll_views.insert(self.scroll_context(), viewport);
             }
@@ -787,6 +1021,15 @@ impl App {
                     selected.addons_view_more = addons_view_more;
                 }
             }
+            Message::SelectedPermissions(backend_name, id, source_id, permissions) => {
+                if let Some(selected) = &mut self.selected_opt
+                    && selected.backend_name == backend_name
+                    && selected.id == id
+                    && selected.info.source_id == source_id
+                {
+                    selected.permissions_opt = Some(permissions);
+                }
+            }
             Message::SelectedScreenshot(i, url, data) => {
                 if let Some(selected) = &mut self.selected_opt
                     && let Some(screenshot) = selected.info.screenshots.get(i)
@@ -805,6 +1048,45 @@ impl App {
             Message::ToggleUninstallPurgeData(value) => {
                 self.uninstall_purge_data = value;
             }
+            Message::SelectedRef(i) => {
+                if let Some(selected) = &mut self.selected_opt
+                    && let Some(selected_ref) = selected.refs.get(i)
+                {
+                    selected.info = Arc::new(AppInfo {
+                        flatpak_refs: vec![selected_ref.flatpak_ref.clone()],
+                        ..(*selected.info).clone()
+                    });
+                    // Branches may have different permissions
+                    return self.load_permissions();
+                }
+            }
+            Message::SelectedRefs(backend_name, id, source_id, refs) => {
+                if let Some(selected) = &mut self.selected_opt
+                    && selected.backend_name == backend_name
+                    && selected.id == id
+                    && selected.info.source_id == source_id
+                {
+                    selected.refs = refs.into_iter().map(SelectedRef::new).collect();
+                }
+            }
+            Message::SelectedScope(scope_backend_name) => {
+                let mut next_opt = None;
+                if let Some(selected) = &self.selected_opt
+                    && selected.backend_name != scope_backend_name
+                    && let Some((backend_name, info, _installed)) =
+                        self.scope_counterpart(selected.backend_name, &selected.id, &selected.info)
+                {
+                    next_opt = Some((
+                        backend_name,
+                        selected.id.clone(),
+                        selected.icon_opt.clone(),
+                        info,
+                    ));
+                }
+                if let Some((backend_name, id, icon_opt, info)) = next_opt {
+                    return self.select(backend_name, id, icon_opt, info);
+                }
+            }
             Message::SelectedSource(i) => {
                 //TODO: show warnings if anything is not found?
                 let mut next_ids = None;
@@ -853,6 +1135,22 @@ impl App {
                     }
                 }
             }
+            Message::SwitchRef(backend_name, id, installed_info, info) => {
+                self.replace_operation(
+                    Operation {
+                        kind: OperationKind::Install,
+                        backend_name,
+                        package_ids: vec![id.clone()],
+                        infos: vec![info],
+                    },
+                    Operation {
+                        kind: OperationKind::Uninstall { purge_data: false },
+                        backend_name,
+                        package_ids: vec![id],
+                        infos: vec![installed_info],
+                    },
+                );
+            }
             Message::SystemThemeModeChange(_theme_mode) => {
                 return self.update_config();
             }
@@ -869,6 +1167,12 @@ impl App {
                 if let Some(updates) = &self.updates {
                     let mut ops = HashMap::with_capacity(self.backends.len());
                     for (backend_name, package) in updates.iter() {
+                        if !self
+                            .backend_capabilities(*backend_name)
+                            .contains(Capabilities::UPDATE)
+                        {
+                            continue;
+                        }
                         let op = ops.entry(*backend_name).or_insert_with(|| Operation {
                             kind: OperationKind::Update,
                             backend_name: *backend_name,
@@ -13,7 +13,7 @@ use rayon::prelude::*;
 
 use crate::app_id::AppId;
 use crate::app_info::{AppInfo, AppProvide, AppUrl};
-use crate::backend::{BackendName, Package};
+use crate::backend::{BackendName, Capabilities, Package};
 use crate::config::AppTheme;
 use crate::explore::ExplorePage;
 use crate::fl;
@@ -129,6 +129,26 @@ impl App {
         .into()
     }
 
+    /// Notice shown while changes wait for a reboot, such as a pending rpm-ostree deployment
+    fn reboot_required_view(&self) -> Option<Element<'_, Message>> {
+        if self.reboot_required.is_empty() {
+            return None;
+        }
+        let spacing = theme::active().cosmic().spacing;
+        Some(
+            widget::row::with_capacity(2)
+                .push(widget::icon::from_name("system-reboot-symbolic").size(16))
+                .push(widget::text::body(fl!("reboot-required")))
+                .align_y(Alignment::Center)
+                .spacing(spacing.space_xs)
+                .apply(widget::container)
+                .width(Length::Fill)
+                .padding([spacing.space_xs, spacing.space_s])
+                .class(theme::Container::Card)
+                .into(),
+        )
+    }
+
     fn has_category_results_for_page(&self, nav_page: NavPage) -> bool {
         self.category_results
             .as_ref()
@@ -163,6 +183,7 @@ impl App {
             }
         }
         let is_installed = self.is_installed(selected_backend_name, selected_id, selected_info);
+        let capabilities = self.backend_capabilities(selected_backend_name);
         let applet_provide = AppProvide::Id("com.system76.CosmicApplet".to_string());
         let mut update_opt = None;
         if let Some(updates) = &self.updates {
@@ -193,11 +214,30 @@ impl App {
                     .iter()
                     .any(|package_id| package_id == selected_id)
             {
-                progress_opt = Some(*progress);
+                progress_opt = Some(progress.fraction());
                 break;
             }
         }
 
+        // Flatpak apps available from the same remote in the user and system installations
+        let scope_opt = if addon {
+            None
+        } else {
+            self.scope_counterpart(selected_backend_name, selected_id, selected_info)
+                .filter(|(backend_name, _, installed)| {
+                    !installed
+                        && self
+                            .backend_capabilities(*backend_name)
+                            .contains(Capabilities::INSTALL)
+                })
+        };
+
+        // Flatpak apps may have another branch or arch installed than the one selected
+        let installed_info_opt = self
+            .installed_package(selected_backend_name, selected_id, selected_info)
+            .filter(|_| selected_backend_name.is_flatpak())
+            .map(|package| package.info.clone());
+
         let mut buttons = Vec::with_capacity(2);
         if let Some(progress) = progress_opt {
             //TODO: get height from theme?
@@ -226,25 +266,59 @@ impl App {
                     );
                 }
             }
-            if let Some(update) = update_opt {
+            if let Some(update) = update_opt
+                && capabilities.contains(Capabilities::UPDATE)
+            {
                 buttons.push(
                     widget::button::standard(fl!("update"))
                         .on_press(update)
                         .into(),
                 );
             }
-            if !selected_id.is_system() {
+            if let Some(installed_info) = &installed_info_opt
+                && installed_info.flatpak_refs != selected_info.flatpak_refs
+                && capabilities.contains(Capabilities::INSTALL | Capabilities::UNINSTALL)
+            {
+                buttons.push(
+                    widget::button::standard(fl!("switch-branch"))
+                        .on_press(Message::SwitchRef(
+                            selected_backend_name,
+                            selected_id.clone(),
+                            installed_info.clone(),
+                            selected_info.clone(),
+                        ))
+                        .into(),
+                );
+            }
+            if !selected_id.is_system() && capabilities.contains(Capabilities::UNINSTALL) {
                 buttons.push(
                     widget::button::standard(fl!("uninstall"))
                         .on_press(Message::DialogPage(DialogPage::Uninstall(
                             selected_backend_name,
                             selected_id.clone(),
-                            selected_info.clone(),
+                            installed_info_opt.unwrap_or_else(|| selected_info.clone()),
                         )))
                         .into(),
                 );
             }
-        } else {
+            if let Some((backend_name, _, _)) = scope_opt
+                && capabilities.contains(Capabilities::UNINSTALL)
+            {
+                buttons.push(
+                    widget::button::standard(if backend_name == BackendName::FlatpakSystem {
+                        fl!("move-to-system")
+                    } else {
+                        fl!("move-to-user")
+                    })
+                    .on_press(Message::MoveScope(
+                        selected_backend_name,
+                        selected_id.clone(),
+                        selected_info.clone(),
+                    ))
+                    .into(),
+                );
+            }
+        } else if capabilities.contains(Capabilities::INSTALL) {
             buttons.push(
                 if addon {
                     widget::button::standard(fl!("install"))
@@ -258,12 +332,46 @@ impl App {
                     selected_info.clone(),
                 ))
                 .into(),
-            )
+            );
+            if scope_opt.is_some() {
+                let scope = match selected_backend_name {
+                    BackendName::FlatpakSystem => 1,
+                    _ => 0,
+                };
+                buttons.push(
+                    widget::dropdown(&self.install_scopes, Some(scope), |i| {
+                        Message::SelectedScope(match i {
+                            1 => BackendName::FlatpakSystem,
+                            _ => BackendName::FlatpakUser,
+                        })
+                    })
+                    .into(),
+                );
+            }
         }
 
         buttons
     }
 
+    /// The same app from the same remote in the other flatpak installation, and whether it is
+    /// installed there
+    pub fn scope_counterpart(
+        &self,
+        backend_name: BackendName,
+        id: &AppId,
+        info: &AppInfo,
+    ) -> Option<(BackendName, Arc<AppInfo>, bool)> {
+        let (other_backend_name, other_source_id) =
+            backend_name.flatpak_other_scope(&info.source_id)?;
+        self.apps
+            .get(id)?
+            .iter()
+            .find(|entry| {
+                entry.backend_name == other_backend_name && entry.info.source_id == other_source_id
+            })
+            .map(|entry| (entry.backend_name, entry.info.clone(), entry.installed))
+    }
+
     pub fn selected_sources(
         &self,
         backend_name: BackendName,
@@ -383,12 +491,21 @@ impl App {
                         .on_press(Message::SelectNone),
                 );
 
-                let buttons = self.selected_buttons(
+                let mut buttons = self.selected_buttons(
                     selected.backend_name,
                     &selected.id,
                     &selected.info,
                     false,
                 );
+                if selected.refs.len() > 1 {
+                    let selected_ref = selected
+                        .refs
+                        .iter()
+                        .position(|x| selected.info.flatpak_refs.first() == Some(&x.flatpak_ref));
+                    buttons.push(
+                        widget::dropdown(&selected.refs, selected_ref, Message::SelectedRef).into(),
+                    );
+                }
                 column = column.push(
                     widget::row::with_children(vec![
                         match &selected.icon_opt {
@@ -640,6 +757,76 @@ impl App {
                     column = column.push(license_col);
                 }
 
+                if let Some(permissions) = &selected.permissions_opt {
+                    let mut permission_col = widget::column::with_capacity(2).spacing(space_xxxs);
+                    permission_col = permission_col.push(
+                        widget::row::with_capacity(2)
+                            .align_y(Alignment::Center)
+                            .push(widget::text::title4(fl!("permissions")).width(Length::Fill))
+                            .push(
+                                widget::button::standard(fl!("reset-permissions")).on_press_maybe(
+                                    permissions.has_overrides().then(|| {
+                                        Message::PermissionsReset(permissions.app_id.clone())
+                                    }),
+                                ),
+                            ),
+                    );
+                    if permissions.items.is_empty() {
+                        permission_col =
+                            permission_col.push(widget::text::body(fl!("no-permissions")));
+                    } else {
+                        let mut list = widget::list_column::with_capacity(permissions.items.len())
+                            .list_item_padding([space_xxs, 0])
+                            .style(theme::Container::Transparent);
+                        for item in permissions.items.iter() {
+                            let permission = &item.permission;
+                            let source = match item.override_opt {
+                                None => fl!("permission-from-app"),
+                                Some(_) if item.manifest => fl!("permission-changed"),
+                                Some(_) => fl!("permission-added"),
+                            };
+                            let mut text_col = widget::column::with_capacity(3)
+                                .width(Length::Fill)
+                                .push(widget::text::body(permission.description()))
+                                .push(widget::text::caption(source));
+                            if permission.is_risky() && item.granted() {
+                                text_col = text_col.push(
+                                    widget::row::with_capacity(2)
+                                        .align_y(Alignment::Center)
+                                        .spacing(space_xxxs)
+                                        .push(
+                                            widget::icon::from_name("dialog-warning-symbolic")
+                                                .size(16)
+                                                .icon(),
+                                        )
+                                        .push(widget::text::caption(fl!("permission-risky"))),
+                                );
+                            }
+                            let app_id = permissions.app_id.clone();
+                            let manifest = item.manifest;
+                            let permission = permission.clone();
+                            list = list.add(
+                                widget::row::with_capacity(2)
+                                    .align_y(Alignment::Center)
+                                    .spacing(space_xxs)
+                                    .push(text_col)
+                                    .push(widget::toggler(item.granted()).on_toggle(
+                                        move |granted| {
+                                            // Matching the app's metadata removes the override
+                                            Message::PermissionOverride(
+                                                app_id.clone(),
+                                                permission.clone(),
+                                                (granted != manifest).then_some(granted),
+                                            )
+                                        },
+                                    )),
+                            );
+                        }
+                        permission_col = permission_col.push(list);
+                    }
+                    column = column.push(permission_col);
+                }
+
                 if !selected.info.urls.is_empty() {
                     let mut url_items = Vec::with_capacity(selected.info.urls.len());
                     for app_url in &selected.info.urls {
@@ -825,6 +1012,9 @@ impl App {
                             .spacing(space_xxs)
                             .width(Length::Fill);
                         column = column.push(widget::text::title2(NavPage::Installed.title()));
+                        if let Some(reboot_required) = self.reboot_required_view() {
+                            column = column.push(reboot_required);
+                        }
                         match &self.installed_results {
                             Some(installed) => {
                                 if installed.is_empty() {
@@ -858,12 +1048,23 @@ impl App {
                                             widget::space::vertical().height(Length::Shrink).into(),
                                         );
                                     }
+                                    // Show the installed flatpak branch, as several may be available
+                                    let branch_opt = self
+                                        .installed_package(
+                                            result.backend_name,
+                                            &result.id,
+                                            &result.info,
+                                        )
+                                        .filter(|_| result.backend_name.is_flatpak())
+                                        .and_then(|package| package.extra.get("branch"));
                                     grid = grid.push(
                                         widget::mouse_area(package_card_view(
                                             &result.info,
                                             result.icon_opt.as_ref(),
                                             buttons,
-                                            None,
+                                            branch_opt.map(|branch| {
+                                                vec![widget::text::caption(branch).into()]
+                                            }),
                                             &spacing,
                                             item_width,
                                         ))
@@ -924,6 +1125,9 @@ impl App {
                                             .into(),
                                     ]));
                                 }
+                                if let Some(reboot_required) = self.reboot_required_view() {
+                                    column = column.push(reboot_required);
+                                }
 
                                 let GridMetrics {
                                     cols,
@@ -960,7 +1164,7 @@ impl App {
                                                 .iter()
                                                 .any(|package_id| package_id == &package.id)
                                         {
-                                            progress_opt = Some(*progress);
+                                            progress_opt = Some(progress.fraction());
                                             break;
                                         }
                                     }
@@ -971,7 +1175,11 @@ impl App {
                                                 .girth(Length::Fixed(4.0))
                                                 .into(),
                                         ]
-                                    } else if waiting_refresh {
+                                    } else if waiting_refresh
+                                        || !self
+                                            .backend_capabilities(*backend_name)
+                                            .contains(Capabilities::UPDATE)
+                                    {
                                         vec![]
                                     } else {
                                         vec![